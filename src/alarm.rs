@@ -0,0 +1,103 @@
+use std::fmt;
+
+const SECS_PER_DAY: u32 = 24 * 60 * 60;
+
+/// A wall-clock time of day, in the local timezone, at which playback should start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AlarmTime {
+    hour: u8,
+    minute: u8,
+}
+
+impl AlarmTime {
+    /// Parse a time written as `HH:MM` in 24-hour format.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (hour, minute) = s.trim().split_once(':')?;
+        let hour = hour.parse::<u8>().ok()?;
+        let minute = minute.parse::<u8>().ok()?;
+        (hour < 24 && minute < 60).then_some(Self { hour, minute })
+    }
+
+    fn seconds_of_day(self) -> u32 {
+        u32::from(self.hour) * 3600 + u32::from(self.minute) * 60
+    }
+
+    /// Whether the alarm time lies in the window `(last, now]`, both given as seconds since local
+    /// midnight. Handles the window wrapping around midnight.
+    pub fn passed_between(self, last: u32, now: u32) -> bool {
+        let alarm = self.seconds_of_day();
+        if last <= now {
+            last < alarm && alarm <= now
+        } else {
+            alarm > last || alarm <= now
+        }
+    }
+}
+
+impl fmt::Display for AlarmTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// The current local time as seconds since midnight.
+#[cfg(unix)]
+pub fn local_seconds_of_day() -> u32 {
+    // SAFETY: `time` accepts a null pointer, and `localtime_r` only writes into the `tm` we own.
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm = std::mem::zeroed::<libc::tm>();
+        libc::localtime_r(&raw const now, &raw mut tm);
+        tm
+    };
+    #[allow(clippy::cast_sign_loss)]
+    let secs = (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as u32;
+    secs % SECS_PER_DAY
+}
+
+/// The current time as seconds since midnight. Timezones are not resolved on this platform, so
+/// this is UTC.
+#[cfg(not(unix))]
+pub fn local_seconds_of_day() -> u32 {
+    #[allow(clippy::cast_possible_truncation)]
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| (d.as_secs() % u64::from(SECS_PER_DAY)) as u32);
+    secs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alarm_time_parse() {
+        assert_eq!(
+            AlarmTime::parse("07:00"),
+            Some(AlarmTime { hour: 7, minute: 0 })
+        );
+        assert_eq!(
+            AlarmTime::parse("23:59"),
+            Some(AlarmTime {
+                hour: 23,
+                minute: 59
+            })
+        );
+        assert_eq!(AlarmTime::parse("24:00"), None);
+        assert_eq!(AlarmTime::parse("7:60"), None);
+        assert_eq!(AlarmTime::parse("off"), None);
+    }
+
+    #[test]
+    fn test_alarm_time_passed_between() {
+        let alarm = AlarmTime::parse("07:00").unwrap();
+        let seven = 7 * 3600;
+        assert!(alarm.passed_between(seven - 1, seven));
+        assert!(!alarm.passed_between(seven, seven + 1));
+        assert!(!alarm.passed_between(seven - 10, seven - 1));
+        // Window wrapping past midnight
+        let midnight = AlarmTime::parse("00:00").unwrap();
+        assert!(midnight.passed_between(SECS_PER_DAY - 1, 0));
+        assert!(!alarm.passed_between(SECS_PER_DAY - 1, 0));
+    }
+}
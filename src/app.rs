@@ -1,48 +1,221 @@
+use std::cell::OnceCell;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
 use std::io::BufReader;
+use std::ops::{Add, Sub};
 use std::path::{Path, PathBuf};
-use std::{fs::File, time::Duration};
+use std::{
+    fs::File,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
 use audiotags::{AudioTag, Tag};
-use crossterm::event::{self, Event, KeyCode};
-use rand::{thread_rng, Rng};
-use rodio::{source::Source, Decoder, OutputStream, OutputStreamHandle, Sink};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    thread_rng,
+};
+use ratatui::{style::Color, text::Line};
+use rodio::{
+    source::{SeekError, Source},
+    Decoder, OutputStream, OutputStreamHandle, Sample, Sink,
+};
 
-use eyre::Result;
+use eyre::{eyre, Result};
+use symphonia::core::{
+    formats::FormatOptions,
+    io::{MediaSourceStream, MediaSourceStreamOptions},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::alarm::{self, AlarmTime};
+use crate::config::{
+    self, Action, Column, ColumnConfig, ColumnWidths, Config, ProfileConfig, SearchPreset,
+    SortDirection,
+};
+use crate::cover_art::{self, ArtworkCache, CoverArtCache, CoverArtPlaceholder};
+use crate::flac;
+use crate::id3v1;
+use crate::mp4_atoms;
+use crate::queue::Queue;
+use crate::rename;
+use crate::replaygain::{self, LoudnessMeter, ReplayGain};
+use crate::stats::PlayStatsStore;
+
+/// How long a status message stays on screen.
+const STATUS_MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How many songs the "Most Played" view shows.
+const MOST_PLAYED_LIMIT: usize = 40;
+/// Default window for the "Recently Added" view when `:recent` is given no argument.
+const DEFAULT_RECENT_DAYS: u32 = 7;
+/// Songs whose lengths differ by up to this much can still be copies of each other.
+const DUPLICATE_DURATION_TOLERANCE: Duration = Duration::from_secs(2);
+/// How many amplitude values the waveform of a track is reduced to.
+const WAVEFORM_RESOLUTION: usize = 512;
+/// Samples quieter than -60 dBFS count as silence.
+const SILENCE_THRESHOLD: f32 = 0.001;
+/// How far into each end of a track to look for silence.
+const SILENCE_SCAN_WINDOW: Duration = Duration::from_secs(10);
+/// How many times to try reopening the audio output after it goes away before giving up.
+const RECONNECT_ATTEMPTS: u32 = 3;
+/// How long the output can go without taking any samples while playing before it's taken to
+/// have gone away.
+const OUTPUT_STALL_TIMEOUT: Duration = Duration::from_secs(1);
+/// Shortest time between attempts to reopen the audio output, so a device that takes a moment
+/// to come back isn't given up on straight away.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+/// Skipping a song before this share of it has played counts against it in its play stats.
+const SKIP_THRESHOLD: f64 = 0.3;
+/// Year of the earliest surviving phonograph recording. Songs tagged with anything older are
+/// assumed to have a broken year tag.
+const EARLIEST_RECORDING_YEAR: i32 = 1877;
+/// How many earlier states of the queue are kept for undoing changes to it.
+const QUEUE_HISTORY_LIMIT: usize = 5;
+/// How many songs interrupted by picking another during shuffle can be gone back to.
+const PLAY_HISTORY_LEN: usize = 10;
+/// How long after the last change to the sort it's saved, so cycling through sorts writes once.
+const SORT_SAVE_DELAY: Duration = Duration::from_secs(2);
+/// How much listening time is counted before it's saved, so little is lost if the app crashes.
+const LISTENING_TIME_SAVE_INTERVAL: Duration = Duration::from_mins(1);
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct SongInfo {
     title: Option<String>,
     album: Option<String>,
-    artist: Option<String>,
+    /// Every artist the song is credited to, in tag order. Usually just one.
+    artists: Vec<String>,
     album_artist: Option<String>,
     year: Option<i32>,
+    /// The year tag, if it was too far in the past or future to be believed.
+    implausible_year: Option<i32>,
     genre: Option<String>,
     track: (Option<u16>, Option<u16>),
     disc: (Option<u16>, Option<u16>),
     duration: Duration,
+    /// Where the file really is, with any symlinks resolved.
     file_path: PathBuf,
+    /// Where the file was found under the library root, if that's through a symlink.
+    link_path: Option<PathBuf>,
+    file_modified: SystemTime,
 }
 
 impl SongInfo {
     fn new(path: &Path, tag: &(dyn AudioTag + Send + Sync)) -> Self {
         // If the file has the duration in the tags, great!
-        // If not, we call ffmpeg/ffprobe to get the info
+        // If not, we work it out from the audio stream
         let duration = match tag.duration() {
             Some(v) => Duration::from_secs_f64(v),
-            None => mp3_duration::from_path(path).unwrap_or(Duration::ZERO),
+            None => probe_duration(path).unwrap_or(Duration::ZERO),
         };
 
-        Self {
+        let mut song = Self {
             title: tag.title().map(std::borrow::ToOwned::to_owned),
             album: tag.album_title().map(std::borrow::ToOwned::to_owned),
-            artist: tag.artist().map(std::borrow::ToOwned::to_owned),
+            artists: tag.artist().map(ToOwned::to_owned).into_iter().collect(),
             album_artist: tag.album_artist().map(std::borrow::ToOwned::to_owned),
             year: tag.year(),
+            implausible_year: None,
             genre: tag.genre().map(std::borrow::ToOwned::to_owned),
             track: tag.track(),
             disc: tag.disc(),
             duration,
             file_path: path.to_path_buf(),
+            link_path: None,
+            file_modified: std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+        };
+
+        // Generic tag readers don't always cope with iTunes files, so read the atoms directly
+        let is_mp4 = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| ["m4a", "mp4"].contains(&e));
+        if is_mp4 && song.title.is_none() && song.artists.is_empty() && song.album.is_none() {
+            song.apply_mp4_atoms(&mp4_atoms::read_atoms(path));
+        }
+
+        let is_flac = path.extension().is_some_and(|e| e == "flac");
+        let comments = if is_flac {
+            flac::read_comments(path).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let comment = |field: &'static str| {
+            comments
+                .iter()
+                .filter(move |(name, _)| name.eq_ignore_ascii_case(field))
+                .map(|(_, value)| value.clone())
+        };
+        // The generic reader only gives the first of several ARTIST comments
+        let artists = comment("ARTIST").collect::<Vec<_>>();
+        if !artists.is_empty() {
+            song.artists = artists;
+        }
+        // Track numbers like "1 of 12" aren't understood by the generic reader, so parse the raw
+        // value instead
+        if song.track.0.is_none() {
+            let raw = if is_flac {
+                comment("TRACKNUMBER").next()
+            } else {
+                Self::raw_id3_track(path)
+            };
+            if let Some(raw) = raw {
+                song.track = normalise_track_number(&raw);
+            }
+        }
+        song.check_year();
+        song
+    }
+
+    /// Drop the year if it's before recorded music or after next year.
+    fn check_year(&mut self) {
+        let latest = current_year() + 1;
+        if let Some(year) = self
+            .year
+            .filter(|y| !(EARLIEST_RECORDING_YEAR..=latest).contains(y))
+        {
+            log::warn!(
+                "Ignoring implausible year {year} in {}",
+                self.file_path.display()
+            );
+            self.year = None;
+            self.implausible_year = Some(year);
+        }
+    }
+
+    fn raw_id3_track(path: &Path) -> Option<String> {
+        use id3::TagLike;
+
+        let tag = id3::Tag::read_from_path(path).ok()?;
+        Some(tag.get("TRCK")?.content().text()?.to_string())
+    }
+
+    fn apply_mp4_atoms(&mut self, atoms: &HashMap<String, String>) {
+        let get = |key: &str| atoms.get(key).cloned();
+        self.title = get("©nam");
+        self.artists = get("©ART").into_iter().collect();
+        self.album_artist = get("aART");
+        self.album = get("©alb");
+        self.genre = get("©gen");
+        // Release dates are often full timestamps like 2004-05-01T07:00:00Z
+        self.year = atoms
+            .get("©day")
+            .and_then(|d| d.get(..4))
+            .and_then(|y| y.parse().ok());
+        if let Some(trkn) = atoms.get("trkn") {
+            self.track = mp4_atoms::parse_pair(trkn);
+        }
+        if let Some(disk) = atoms.get("disk") {
+            self.disc = mp4_atoms::parse_pair(disk);
         }
     }
 
@@ -54,19 +227,48 @@ impl SongInfo {
         self.album.as_deref()
     }
 
+    /// The title, or the file name without its extension for songs that have none, which at
+    /// least tells the user which file it is.
+    pub fn display_title(&self) -> &str {
+        self.title.as_deref().unwrap_or_else(|| {
+            self.file_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("Unknown")
+        })
+    }
+
+    pub fn display_artist(&self) -> &str {
+        self.artist().unwrap_or("Unknown Artist")
+    }
+
+    pub fn display_album(&self) -> &str {
+        self.album().unwrap_or("Unknown Album")
+    }
+
+    pub fn artists(&self) -> &[String] {
+        &self.artists
+    }
+
+    /// The first of [`Self::artists`].
     pub fn artist(&self) -> Option<&str> {
-        self.artist.as_deref()
+        self.artists.first().map(String::as_str)
     }
 
-    pub fn _album_artist(&self) -> Option<&str> {
+    pub fn album_artist(&self) -> Option<&str> {
         self.album_artist.as_deref()
     }
 
-    pub fn _year(&self) -> &Option<i32> {
-        &self.year
+    /// The album artist, or the first artist without one, or "Unknown" without either.
+    fn credited_artist(&self) -> &str {
+        self.album_artist().or(self.artist()).unwrap_or("Unknown")
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.year
     }
 
-    pub fn _genre(&self) -> Option<&str> {
+    pub fn genre(&self) -> Option<&str> {
         self.genre.as_deref()
     }
 
@@ -74,7 +276,7 @@ impl SongInfo {
         &self.track
     }
 
-    pub fn _disc(&self) -> &(Option<u16>, Option<u16>) {
+    pub fn disc(&self) -> &(Option<u16>, Option<u16>) {
         &self.disc
     }
 
@@ -82,421 +284,5588 @@ impl SongInfo {
         &self.duration
     }
 
-    pub fn _file_path(&self) -> &Path {
+    pub fn file_path(&self) -> &Path {
         &self.file_path
     }
+
+    pub fn link_path(&self) -> Option<&Path> {
+        self.link_path.as_deref()
+    }
+
+    /// Names of the title, artist, and album tags, for whichever of them this song lacks.
+    pub fn missing_tags(&self) -> Vec<&'static str> {
+        [
+            ("title", &self.title),
+            ("artist", &self.artist().map(ToOwned::to_owned)),
+            ("album", &self.album),
+        ]
+        .into_iter()
+        .filter(|(_, tag)| tag.is_none())
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// Whether any tag had a value too broken to use, such as a year of 0.
+    pub fn has_tag_warnings(&self) -> bool {
+        self.implausible_year.is_some()
+    }
+
+    /// Key that songs which might be copies of each other share: the lowercased title and
+    /// artist. Songs missing either tag have no key, since they can't be told apart.
+    fn duplicate_key(&self) -> Option<(String, String)> {
+        Some((
+            self.title.as_ref()?.to_lowercase(),
+            self.artist()?.to_lowercase(),
+        ))
+    }
+
+    /// Whether `other` looks like a copy of this song, going by title, artist, and length.
+    pub fn is_duplicate_of(&self, other: &SongInfo) -> bool {
+        self.duplicate_key().is_some()
+            && self.duplicate_key() == other.duplicate_key()
+            && self.duration.abs_diff(other.duration) <= DUPLICATE_DURATION_TOLERANCE
+    }
+
+    /// Order of this song relative to `other` by the value in `column`. Songs missing the value
+    /// sort first.
+    fn cmp_by(&self, other: &SongInfo, column: Column) -> std::cmp::Ordering {
+        match column {
+            Column::Track => self.track.0.cmp(&other.track.0),
+            Column::Title => self.title.cmp(&other.title),
+            Column::Artist => self.artists.cmp(&other.artists),
+            Column::Album => self.album.cmp(&other.album),
+            Column::Year => self.year.cmp(&other.year),
+            Column::Genre => self.genre.cmp(&other.genre),
+            Column::Duration => self.duration.cmp(&other.duration),
+        }
+    }
+
+    /// Match of `query` against the title, artist, and album.
+    pub fn matches_query(&self, query: &str, case_sensitive: bool) -> bool {
+        let fields = [self.title(), self.album()]
+            .into_iter()
+            .flatten()
+            .chain(self.artists.iter().map(String::as_str));
+        if case_sensitive {
+            fields.into_iter().any(|f| f.contains(query))
+        } else {
+            let query = query.to_lowercase();
+            fields
+                .into_iter()
+                .any(|f| f.to_lowercase().contains(&query))
+        }
+    }
+}
+
+/// Length of the audio stream in the file at `path`, from the frame count its container
+/// declares or, for MP3s without one, an estimate from the file's size.
+fn probe_duration(path: &Path) -> Option<Duration> {
+    let file = std::fs::File::open(path).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), MediaSourceStreamOptions::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+    let params = &probed.format.default_track()?.codec_params;
+    let time = params.time_base?.calc_time(params.n_frames?);
+    Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac))
+}
+
+/// A long span of time like a whole library's length, as days, hours, and minutes, e.g.
+/// `42d 7h 23m`. Leading units that are zero are left out.
+pub fn format_long_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m")
+    }
+}
+
+/// ID of the filesystem the directory at `path` is on, following symlinks. Always `None` where
+/// that can't be told.
+fn device_id(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata(path).ok().map(|m| m.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// `path` relative to the directory `base`, going up with `..` as needed. Returns `None` if they
+/// have nothing in common, such as being on different Windows drives.
+fn relative_path(path: &Path, base: &Path) -> Option<PathBuf> {
+    let path = std::path::absolute(path).ok()?;
+    let base = std::path::absolute(base).ok()?;
+    let mut path_parts = path.components().peekable();
+    let mut base_parts = base.components().peekable();
+    let mut shared = 0;
+    while path_parts.peek().is_some() && path_parts.peek() == base_parts.peek() {
+        path_parts.next();
+        base_parts.next();
+        shared += 1;
+    }
+    if shared == 0 {
+        return None;
+    }
+    let mut relative = base_parts.map(|_| "..").collect::<PathBuf>();
+    relative.extend(path_parts);
+    Some(relative)
+}
+
+/// `text` with the first letter of each word upper case and the rest lower case. Hyphens and
+/// slashes start new words too, as in "Hip-Hop".
+fn title_case(text: &str) -> String {
+    let mut word_start = true;
+    text.chars()
+        .map(|c| {
+            let cased = if word_start {
+                c.to_uppercase().collect::<String>()
+            } else {
+                c.to_lowercase().collect()
+            };
+            word_start = c.is_whitespace() || c == '-' || c == '/';
+            cased
+        })
+        .collect()
+}
+
+/// Parse a filter like `<0.3` or `>0.5` into the side of the threshold to keep and the threshold.
+fn parse_ratio_filter(filter: &str) -> Option<(std::cmp::Ordering, f32)> {
+    let (side, threshold) = if let Some(t) = filter.strip_prefix('<') {
+        (std::cmp::Ordering::Less, t)
+    } else {
+        (std::cmp::Ordering::Greater, filter.strip_prefix('>')?)
+    };
+    Some((side, threshold.trim().parse().ok()?))
+}
+
+/// Whichever of [`BITRATE_BUCKETS`] is closest to the average bitrate of a file of `size` bytes
+/// lasting `duration`.
+fn bitrate_bucket(size: u64, duration: Duration) -> Option<u32> {
+    if duration.is_zero() {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let kbps = size as f64 * 8.0 / duration.as_secs_f64() / 1000.0;
+    BITRATE_BUCKETS.into_iter().min_by(|a, b| {
+        (f64::from(*a) - kbps)
+            .abs()
+            .total_cmp(&(f64::from(*b) - kbps).abs())
+    })
+}
+
+/// The current year in UTC, near enough for sanity checks.
+fn current_year() -> i32 {
+    const SECS_PER_YEAR: f64 = 365.2425 * 24.0 * 60.0 * 60.0;
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64());
+    #[allow(clippy::cast_possible_truncation)]
+    let years = (secs / SECS_PER_YEAR) as i32;
+    1970 + years
+}
+
+/// Parse a track number as rippers tend to write it, such as `01`, `1/12`, `1 of 12` or
+/// `Track 1`, into the track number and the number of tracks.
+pub fn normalise_track_number(raw: &str) -> (Option<u16>, Option<u16>) {
+    let raw = raw.trim().to_lowercase();
+    let raw = raw.strip_prefix("track").unwrap_or(&raw);
+    let (number, total) = raw
+        .split_once('/')
+        .or_else(|| raw.split_once(" of "))
+        .unwrap_or((raw, ""));
+    let parse = |s: &str| s.trim().trim_start_matches('#').parse().ok();
+    (parse(number), parse(total))
+}
+
+/// "Artist – Title", just the title for songs with no artist, or the file name for songs with no
+/// title either.
+impl std::fmt::Display for SongInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.artist(), self.title()) {
+            (Some(artist), Some(title)) => write!(f, "{artist} – {title}"),
+            (None, Some(title)) => write!(f, "{title}"),
+            (_, None) => match self.file_path.file_name() {
+                Some(name) => write!(f, "{}", name.to_string_lossy()),
+                None => write!(f, "{}", self.file_path.display()),
+            },
+        }
+    }
+}
+
+impl SongInfo {
+    /// A three minute song at `path` with no tags.
+    pub fn untagged(path: &Path) -> Self {
+        Self {
+            title: None,
+            album: None,
+            artists: Vec::new(),
+            album_artist: None,
+            year: None,
+            implausible_year: None,
+            genre: None,
+            track: (None, None),
+            disc: (None, None),
+            duration: Duration::from_mins(3),
+            file_path: path.to_path_buf(),
+            link_path: None,
+            file_modified: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    /// A song in a format the tag reader doesn't support, with its length taken from the audio.
+    ///
+    /// Returns `None` if the audio can't be decoded either, so there'd be no playing it.
+    fn from_audio(path: &Path) -> Option<Self> {
+        let source = Decoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+        Some(Self {
+            duration: probe_duration(path)
+                .or_else(|| source.total_duration())
+                .unwrap_or(Duration::ZERO),
+            file_modified: std::fs::metadata(path)
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH),
+            ..Self::untagged(path)
+        })
+    }
 }
 
 #[derive(PartialEq)]
 pub enum AppUiMode {
     FileList,
     SearchPopup,
+    CommandPrompt,
+    /// Entering the path of an image to embed as the selected song's cover.
+    CoverPathPrompt,
+    /// Pairs of songs that look like copies of each other, from `:duplicates`.
+    Duplicates,
+    /// Checklist of file list columns to show or hide.
+    ColumnConfig,
+    /// Scrollable lines of text over the file list, such as a rename preview.
     InfoPopup,
+    /// Summary of how well tagged the library is, from `:quality-report`.
+    QualityReport,
+    /// Asking whether to go ahead with a rescan, when `confirm_rescan` is set.
+    ConfirmRescan,
+    /// Asking whether to delete a copy picked from [`AppUiMode::Duplicates`].
+    ConfirmDelete,
 }
 
+/// Which subset of the library the file list shows.
 #[derive(PartialEq)]
+pub enum LibraryView {
+    All,
+    MostPlayed,
+    Unplayed,
+    RecentlyAdded(u32),
+    /// Songs that had no cover art when the view was opened. Finding them means reading every
+    /// file's tags, so the list is worked out once rather than on every draw.
+    MissingCovers(Vec<usize>),
+    MissingTags,
+    Favourites,
+    /// Songs whose skip ratio is on the given side of a threshold, most skipped first.
+    SkipRatio(std::cmp::Ordering, f32),
+    /// Every song in the order it was first played. Unplayed songs come last in a random order,
+    /// so the list is worked out once when the view is opened.
+    DiscoveryDate(Vec<usize>),
+}
+
+/// Panel of the file list screen that receives navigation keys.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FocusedPanel {
+    FileList,
+    QueuePanel,
+}
+
+impl FocusedPanel {
+    /// Every panel, in the order `Tab` moves through them.
+    const ALL: [FocusedPanel; 2] = [FocusedPanel::FileList, FocusedPanel::QueuePanel];
+
+    fn cycle(self, forward: bool) -> Self {
+        let ix = Self::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        let len = Self::ALL.len();
+        let next = if forward { ix + 1 } else { ix + len - 1 };
+        Self::ALL[next % len]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PlaybackMode {
     Normal,
     Shuffle,
 }
 
+impl PlaybackMode {
+    /// Short label for the playback bar. Normal playback needs no label.
+    pub fn label(self) -> &'static str {
+        match self {
+            PlaybackMode::Normal => "",
+            PlaybackMode::Shuffle => "⇀ Shuffle",
+        }
+    }
+
+    /// Name for the status bar.
+    pub fn name(self) -> &'static str {
+        match self {
+            PlaybackMode::Normal => "Normal",
+            PlaybackMode::Shuffle => "Shuffle",
+        }
+    }
+}
+
+/// Playback volume, kept between 0.0 for silent and 1.0 for full volume.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Volume(f32);
+
+impl Volume {
+    pub const MAX: Self = Self(1.0);
+
+    /// `volume` clamped to between silent and full. NaN is taken as silent.
+    pub fn new(volume: f32) -> Self {
+        if volume.is_nan() {
+            Self(0.0)
+        } else {
+            Self(volume.clamp(0.0, 1.0))
+        }
+    }
+}
+
+impl From<Volume> for f32 {
+    fn from(volume: Volume) -> Self {
+        volume.0
+    }
+}
+
+impl Add<f32> for Volume {
+    type Output = Self;
+
+    fn add(self, rhs: f32) -> Self {
+        Self::new(self.0 + rhs)
+    }
+}
+
+impl Sub<f32> for Volume {
+    type Output = Self;
+
+    fn sub(self, rhs: f32) -> Self {
+        Self::new(self.0 - rhs)
+    }
+}
+
+/// Songs moved by [`Library::rename_by_template`], and those that couldn't be.
+#[derive(Default)]
+pub struct RenameResult {
+    /// `(from, to)` path of every song moved.
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// Path of every song that couldn't be moved, and why.
+    pub failed: Vec<(PathBuf, std::io::Error)>,
+}
+
+/// Path and size in bytes of one copy of a song that's in the library more than once.
+pub type DuplicateCopy = (PathBuf, u64);
+
 pub struct AppState {
     active_song: Option<SongInfo>,
     playing_file_ix: usize,
     selected_file_ix: usize,
     search_query: Option<String>,
+    search_case_sensitive: bool,
     ui_mode: AppUiMode,
     playback_mode: PlaybackMode,
+    command_input: String,
+    cover_path_input: String,
+    /// Title and lines of text shown in [`AppUiMode::InfoPopup`].
+    info: (String, Vec<String>),
+    info_scroll: usize,
+    /// Template from the last `:rename-preview`, applied by `:rename-confirm`.
+    pending_rename: Option<String>,
+    /// Copy picked for deletion from the duplicates list, until it's confirmed.
+    pending_delete: Option<PathBuf>,
+    status_message: Option<(String, Instant)>,
+    alarm_time: Option<AlarmTime>,
+    alarm_last_checked: u32,
+    shuffle_history: VecDeque<usize>,
+    /// Song picked to play after the current one in shuffle, so it can be shown before it starts.
+    upcoming_shuffle_ix: Option<usize>,
+    /// What was playing, and in which mode, before each of the last few songs picked with Enter
+    /// during shuffle, oldest first.
+    play_history: Vec<(usize, PlaybackMode)>,
+    view: LibraryView,
+    /// Name of the active library profile, if one was chosen.
+    profile: Option<String>,
+    /// Song pairs shown in [`AppUiMode::Duplicates`].
+    duplicates: Vec<(DuplicateCopy, DuplicateCopy)>,
+    /// Paths of the songs marked as favourites.
+    favourites: HashSet<PathBuf>,
+    /// Report shown in [`AppUiMode::QualityReport`].
+    quality_report: Option<QualityReport>,
+    selected_duplicate_ix: usize,
+    focused_panel: FocusedPanel,
+    selected_queue_ix: usize,
+    /// The queue as it was before each of the last few changes to it, oldest first.
+    queue_history: Vec<Queue>,
+    /// File list selection from before the search began, restored once the search is cleared.
+    pre_search_selected_ix: Option<usize>,
+    /// Row of [`AppUiMode::ColumnConfig`] that's selected, as an index into [`Column::ALL`].
+    selected_column_ix: usize,
+    /// Time spent playing this session, not counting while paused.
+    total_listening_time: Duration,
+    /// Listening time counted since the play stats were last saved.
+    unsaved_listening_time: Duration,
+    /// Playback was paused because the terminal lost focus, so should resume when it's back.
+    auto_paused: bool,
+    /// When to save a sort changed this session, if it hasn't been saved yet.
+    sort_save_due: Option<Instant>,
+}
+
+/// A finished waveform analysis and the path of the song it's for.
+type AnalysedWaveform = (PathBuf, Vec<f32>);
+
+/// What a key does on the file list screen.
+type KeyHandler<M> = fn(&mut PlayerApp<M>) -> Result<()>;
+
+/// Keys with a fixed meaning on the file list screen. They take priority over the configurable
+/// [`Keybindings`](config::Keybindings).
+fn file_list_keys<M: AudioManagerTrait>() -> [(KeyCode, KeyModifiers, KeyHandler<M>); 27] {
+    [
+        (KeyCode::Char('q'), KeyModifiers::NONE, |app| {
+            app.quit();
+            Ok(())
+        }),
+        (KeyCode::Char('p'), KeyModifiers::NONE, |app| {
+            if app.app_state.active_song.is_some() {
+                app.am.toggle_playback();
+            }
+            Ok(())
+        }),
+        (KeyCode::Char('a'), KeyModifiers::NONE, |app| {
+            app.queue_selected();
+            Ok(())
+        }),
+        (KeyCode::Char('F'), KeyModifiers::NONE, |app| {
+            app.toggle_selected_favourite();
+            Ok(())
+        }),
+        (
+            KeyCode::Char('R'),
+            KeyModifiers::NONE,
+            PlayerApp::play_random,
+        ),
+        (KeyCode::Char('u'), KeyModifiers::NONE, PlayerApp::undo_play),
+        (KeyCode::Char('f'), KeyModifiers::NONE, |app| {
+            app.toggle_playback_mode();
+            Ok(())
+        }),
+        (KeyCode::Char('c'), KeyModifiers::CONTROL, |app| {
+            app.app_state.ui_mode = AppUiMode::ColumnConfig;
+            Ok(())
+        }),
+        // Not Ctrl+M, which terminals send as Enter
+        (KeyCode::Char('t'), KeyModifiers::CONTROL, |app| {
+            app.set_view(LibraryView::MostPlayed);
+            Ok(())
+        }),
+        (KeyCode::Char('z'), KeyModifiers::CONTROL, |app| {
+            app.undo_queue_change();
+            Ok(())
+        }),
+        (KeyCode::Char('c'), KeyModifiers::NONE, |app| {
+            app.open_cover_path_prompt();
+            Ok(())
+        }),
+        (KeyCode::Esc, KeyModifiers::NONE, |app| {
+            app.set_view(LibraryView::All);
+            Ok(())
+        }),
+        (KeyCode::Down, KeyModifiers::NONE, |app| {
+            app.select_next_file();
+            Ok(())
+        }),
+        (KeyCode::Up, KeyModifiers::NONE, |app| {
+            app.select_previous_file();
+            Ok(())
+        }),
+        (KeyCode::Enter, KeyModifiers::NONE, PlayerApp::play_selected),
+        (KeyCode::Char('/'), KeyModifiers::NONE, |app| {
+            app.open_search();
+            Ok(())
+        }),
+        (KeyCode::Char(':'), KeyModifiers::NONE, |app| {
+            app.open_command_prompt();
+            Ok(())
+        }),
+        (KeyCode::Char('0'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(0)
+        }),
+        (KeyCode::Char('1'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(1)
+        }),
+        (KeyCode::Char('2'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(2)
+        }),
+        (KeyCode::Char('3'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(3)
+        }),
+        (KeyCode::Char('4'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(4)
+        }),
+        (KeyCode::Char('5'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(5)
+        }),
+        (KeyCode::Char('6'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(6)
+        }),
+        (KeyCode::Char('7'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(7)
+        }),
+        (KeyCode::Char('8'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(8)
+        }),
+        (KeyCode::Char('9'), KeyModifiers::NONE, |app| {
+            app.seek_to_tenth(9)
+        }),
+    ]
+}
+
+/// Whether `key` is `code` with `modifiers`. Terminals differ over whether capitals and symbols
+/// come with Shift, so it's ignored for characters.
+fn key_matches(key: KeyEvent, code: KeyCode, modifiers: KeyModifiers) -> bool {
+    let pressed = match key.code {
+        KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+        _ => key.modifiers,
+    };
+    key.code == code && pressed == modifiers
 }
 
-pub struct PlayerApp {
+/// Paths of the selected and playing songs, and of every song by library index, so they can be
+/// found again after the library's indices change.
+struct SongPositions {
+    selected: Option<PathBuf>,
+    paths: Vec<PathBuf>,
+    playing: Option<PathBuf>,
+}
+
+pub struct PlayerApp<M = AudioManager> {
     library: Library,
-    am: AudioManager,
+    am: M,
     alive: bool,
     app_state: AppState,
+    config: Config,
+    play_stats: PlayStatsStore,
+    /// Status messages reported by background tasks.
+    task_messages: (Sender<String>, Receiver<String>),
+    cover_art: CoverArtCache,
+    /// Cover art drawn for the Now Playing panel, by album.
+    artwork: ArtworkCache<Vec<Line<'static>>>,
+    queue: Queue,
+    /// Waveform of the playing song, once the background analysis has finished.
+    waveform: Option<Vec<f32>>,
+    /// Finished waveform analyses, with the path of the song each is for.
+    waveforms: (Sender<AnalysedWaveform>, Receiver<AnalysedWaveform>),
+    /// Size the terminal was last resized to, until the UI has caught up with it.
+    pending_resize: Option<(u16, u16)>,
+    session_start: Instant,
+    /// When [`Self::update`] last ran, to count listening time between ticks.
+    last_tick: Instant,
 }
 
 impl PlayerApp {
-    pub fn new(root_dir: &Path) -> Result<Self> {
-        Ok(Self {
-            library: Library::new(root_dir).with_scan()?,
-            am: AudioManager::new()?,
+    pub fn new(root_dir: &Path, config: Config) -> Result<Self> {
+        let am = AudioManager::new()?.with_skip_silence(config.skip_silence);
+        Self::with_audio_manager(root_dir, config, am)
+    }
+}
+
+impl<M: AudioManagerTrait> PlayerApp<M> {
+    /// A player for the library at `root_dir` that plays through `am`.
+    pub fn with_audio_manager(root_dir: &Path, mut config: Config, am: M) -> Result<Self> {
+        let conflicts = config.keybindings.reset_conflicts();
+        config.saved_searches.extend(config::load_saved_searches());
+        config.profiles.extend(config::load_profiles());
+        // Pick up where the last session left off if its profile is still configured
+        let profile = config::last_profile()
+            .and_then(|name| config.profiles.get(&name).cloned().map(|p| (name, p)));
+        let library = match &profile {
+            Some((_, p)) => Library::from_roots(&p.root_dirs),
+            None => Library::new(root_dir),
+        };
+        let artwork = ArtworkCache::new(config.art_cache_size);
+        let mut app = Self {
+            library: library
+                .with_scan_options(config.scan_options())
+                .with_sort(config.sort_key, config.sort_direction)
+                .with_scan()?,
+            am,
             alive: true,
             app_state: AppState {
                 active_song: None,
                 playing_file_ix: 0,
                 selected_file_ix: 0,
                 search_query: None,
+                search_case_sensitive: false,
                 ui_mode: AppUiMode::FileList,
                 playback_mode: PlaybackMode::Normal,
+                command_input: String::new(),
+                cover_path_input: String::new(),
+                info: (String::new(), Vec::new()),
+                info_scroll: 0,
+                pending_rename: None,
+                pending_delete: None,
+                status_message: None,
+                alarm_time: None,
+                alarm_last_checked: 0,
+                shuffle_history: VecDeque::new(),
+                upcoming_shuffle_ix: None,
+                play_history: Vec::new(),
+                view: LibraryView::All,
+                profile: None,
+                duplicates: Vec::new(),
+                favourites: config::load_favourites(),
+                quality_report: None,
+                selected_duplicate_ix: 0,
+                focused_panel: FocusedPanel::FileList,
+                selected_queue_ix: 0,
+                queue_history: Vec::new(),
+                selected_column_ix: 0,
+                pre_search_selected_ix: None,
+                total_listening_time: Duration::ZERO,
+                unsaved_listening_time: Duration::ZERO,
+                auto_paused: false,
+                sort_save_due: None,
             },
-        })
+            config,
+            play_stats: PlayStatsStore::load()?,
+            task_messages: mpsc::channel(),
+            cover_art: CoverArtCache::default(),
+            artwork,
+            queue: Queue::default(),
+            waveform: None,
+            waveforms: mpsc::channel(),
+            pending_resize: None,
+            session_start: Instant::now(),
+            last_tick: Instant::now(),
+        };
+        if let Some((name, profile)) = profile {
+            app.apply_profile_settings(name, &profile);
+        }
+        if !conflicts.is_empty() {
+            let conflicts = conflicts
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            app.set_status_message(conflicts.join("; "));
+        }
+        Ok(app)
     }
 
-    pub fn library(&self) -> &Library {
-        &self.library
+    pub fn duplicates(&self) -> &[(DuplicateCopy, DuplicateCopy)] {
+        &self.app_state.duplicates
     }
 
-    pub fn audio_manager(&self) -> &AudioManager {
-        &self.am
+    pub fn selected_duplicate_ix(&self) -> usize {
+        self.app_state.selected_duplicate_ix
     }
 
-    pub fn ui_mode(&self) -> &AppUiMode {
-        &self.app_state.ui_mode
+    pub fn is_favourite(&self, song: &SongInfo) -> bool {
+        self.app_state.favourites.contains(&song.file_path)
     }
 
-    pub fn playback_mode(&self) -> &PlaybackMode {
-        &self.app_state.playback_mode
+    /// Add the selected song to the favourites, or take it out if it's already there.
+    fn toggle_selected_favourite(&mut self) {
+        let Some(&ix) = self.visible_indices().get(self.app_state.selected_file_ix) else {
+            return;
+        };
+        let path = self.library.files[ix].file_path.clone();
+        let message = if self.app_state.favourites.remove(&path) {
+            "Removed from favourites"
+        } else {
+            self.app_state.favourites.insert(path);
+            "Added to favourites"
+        };
+        match config::save_favourites(&self.app_state.favourites) {
+            Ok(()) => self.set_status_message(message),
+            Err(e) => self.set_status_message(format!("Couldn't save favourites: {e}")),
+        }
+        // Unfavouriting from the favourites view drops the song from the list
+        self.clamp_file_selection();
     }
 
-    pub fn search_query(&self) -> Option<&str> {
-        self.app_state.search_query.as_deref()
+    pub fn columns(&self) -> ColumnConfig {
+        self.config.columns
     }
 
-    pub fn selected_file_ix(&self) -> usize {
-        self.app_state.selected_file_ix
+    /// Whether to set the window title to the playing song, and whether to clear it on exit.
+    pub fn window_title_settings(&self) -> (bool, bool) {
+        (self.config.set_title, self.config.restore_title)
     }
 
-    pub fn update(&mut self, dt: f64) -> Result<()> {
-        self.am.update(dt);
-        self.handle_events()?;
-        if let Some(s) = &self.app_state.active_song {
-            if self.am.playback_progress >= s.duration {
-                if self.app_state.playback_mode == PlaybackMode::Normal {
-                    if self.app_state.playing_file_ix < self.library().files().len() - 1 {
-                        self.app_state.playing_file_ix += 1;
-                        self.play_at_ix()?;
-                    } else {
-                        self.am.pause();
-                    }
-                } else if self.app_state.playback_mode == PlaybackMode::Shuffle {
-                    let mut next_ix = thread_rng().gen_range(0..self.library().files().len() - 1);
-                    let cur_ix = self.app_state.playing_file_ix;
-                    if next_ix >= cur_ix {
-                        next_ix += 1;
-                    }
-                    self.app_state.playing_file_ix = next_ix;
-                    self.play_at_ix()?;
-                }
-            }
-        }
-        Ok(())
+    pub fn column_widths(&self) -> ColumnWidths {
+        self.config.column_widths
     }
 
-    fn handle_events(&mut self) -> Result<()> {
-        if event::poll(Duration::from_millis(16))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == event::KeyEventKind::Press {
-                    if self.app_state.ui_mode == AppUiMode::FileList {
-                        if key.code == KeyCode::Char('q') {
-                            self.alive = false;
-                        } else if key.code == KeyCode::Char('p') {
-                            if self.app_state.active_song.is_some() {
-                                self.am.toggle_playback();
-                            }
-                        } else if key.code == KeyCode::Char('s') {
-                            self.library.scan()?;
-                        } else if key.code == KeyCode::Char('f') {
-                            self.app_state.playback_mode = match self.app_state.playback_mode {
-                                PlaybackMode::Normal => PlaybackMode::Shuffle,
-                                PlaybackMode::Shuffle => PlaybackMode::Normal,
-                            };
-                        } else if key.code == KeyCode::Down {
-                            self.app_state.selected_file_ix = (self.app_state.selected_file_ix + 1)
-                                .min(self.library().files().len() - 1);
-                        } else if key.code == KeyCode::Up {
-                            self.app_state.selected_file_ix =
-                                self.app_state.selected_file_ix.max(1) - 1;
-                        } else if key.code == KeyCode::Right {
-                            if self.app_state.active_song.is_some() {
-                                if key.modifiers == crossterm::event::KeyModifiers::SHIFT {
-                                    self.am.skip();
-                                } else {
-                                    self.am.seek_forward();
-                                }
-                            }
-                        } else if key.code == KeyCode::Left {
-                            if self.app_state.active_song.is_some() {
-                                self.am.seek_backward();
-                            }
-                        } else if key.code == KeyCode::Enter {
-                            self.app_state.playing_file_ix = self.app_state.selected_file_ix;
-                            self.play_at_ix()?;
-                        } else if key.code == KeyCode::Char('=') {
-                            self.volume_up();
-                        } else if key.code == KeyCode::Char('-') {
-                            self.volume_down();
-                        } else if key.code == KeyCode::Char('/') {
-                            self.app_state.ui_mode = AppUiMode::SearchPopup;
-                        }
-                    } else if self.app_state.ui_mode == AppUiMode::SearchPopup {
-                        if key.code == KeyCode::Enter {
-                            self.app_state.ui_mode = AppUiMode::FileList;
-                        } else if key.code == KeyCode::Backspace {
-                            if let Some(q) = &self.app_state.search_query {
-                                if q.len() == 1 {
-                                    self.app_state.search_query = None;
-                                } else {
-                                    self.app_state.search_query =
-                                        Some(q[..q.len() - 1].to_string());
-                                }
-                            }
-                        } else if let KeyCode::Char(c) = key.code {
-                            let mut query = self.app_state.search_query.clone();
-                            if self.app_state.search_query.is_none() {
-                                query = Some(c.to_string());
-                            } else {
-                                query.as_mut().unwrap().push(c);
-                            }
-                            self.app_state.search_query = query;
-                        }
-                    }
-                }
-            }
-        }
-        Ok(())
+    /// Colour to draw `song` in the file list, going by its genre.
+    pub fn genre_color(&self, song: &SongInfo) -> Option<Color> {
+        self.config.genre_color(song.genre()?)
     }
 
-    fn volume_up(&mut self) {
-        self.am.set_volume((self.am.get_volume() + 0.01).min(1.0));
+    pub fn selected_column_ix(&self) -> usize {
+        self.app_state.selected_column_ix
     }
 
-    fn volume_down(&mut self) {
-        self.am.set_volume((self.am.get_volume() - 0.01).max(0.0));
+    pub fn focused_panel(&self) -> FocusedPanel {
+        self.app_state.focused_panel
     }
 
-    pub fn volume(&self) -> f32 {
-        self.am.get_volume()
+    pub fn queue(&self) -> &Queue {
+        &self.queue
     }
 
-    fn play_at_ix(&mut self) -> Result<()> {
-        let path = PathBuf::from(&self.library().files()[self.app_state.playing_file_ix].file_path);
-        self.am.set_active_source(&path)?;
-        self.app_state.active_song =
-            Some(self.library().files()[self.app_state.playing_file_ix].clone());
-        self.am.play();
-        Ok(())
+    pub fn selected_queue_ix(&self) -> usize {
+        // Songs leave the queue as they play, so the selection can be left past the end
+        self.app_state
+            .selected_queue_ix
+            .min(self.queue.len().saturating_sub(1))
     }
 
-    pub fn active_song(&self) -> Option<&SongInfo> {
-        self.app_state.active_song.as_ref()
+    /// Name of the active library profile, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.app_state.profile.as_deref()
     }
 
-    pub fn is_alive(&self) -> bool {
-        self.alive
+    /// Replace the library with the one for profile `name`, and switch to its playback settings.
+    /// Playback stops, since the playing song may not be in the new library.
+    fn switch_profile(&mut self, name: &str) -> Result<()> {
+        let profile = self
+            .config
+            .profiles
+            .get(name)
+            .cloned()
+            .ok_or_else(|| eyre!("Unknown profile: {name}"))?;
+        self.library = Library::from_roots(&profile.root_dirs)
+            .with_scan_options(self.config.scan_options())
+            .with_sort(self.config.sort_key, self.config.sort_direction)
+            .with_scan()?;
+        self.am.pause();
+        self.app_state.active_song = None;
+        self.app_state.playing_file_ix = 0;
+        self.app_state.shuffle_history.clear();
+        self.app_state.upcoming_shuffle_ix = None;
+        self.queue.clear();
+        // The old library's indices mean nothing in the new one
+        self.app_state.queue_history.clear();
+        self.app_state.play_history.clear();
+        self.set_view(LibraryView::All);
+        self.apply_profile_settings(name.to_string(), &profile);
+        config::save_last_profile(name)
     }
 
-    pub fn is_playing(&self) -> bool {
-        !self.am.sink.is_paused()
+    /// Save the library's folders and the current playback mode and volume as profile `name`,
+    /// replacing any profile already by that name, and make it the active profile.
+    fn save_profile(&mut self, name: &str) -> Result<()> {
+        let profile = ProfileConfig {
+            root_dirs: self.library.root_dirs.clone(),
+            playback_mode: self.app_state.playback_mode,
+            volume: self.volume(),
+        };
+        self.config.profiles.insert(name.to_string(), profile);
+        self.app_state.profile = Some(name.to_string());
+        config::save_profiles(&self.config.profiles)?;
+        config::save_last_profile(name)
     }
-}
 
-pub struct AudioManager {
-    sink: Sink,
-    _stream: OutputStream,
-    _stream_handle: OutputStreamHandle,
-    playback_progress: Duration,
-    active_source_duration: Option<Duration>,
-}
+    fn apply_profile_settings(&mut self, name: String, profile: &ProfileConfig) {
+        self.app_state.playback_mode = profile.playback_mode;
+        self.am.set_volume(profile.volume);
+        self.app_state.profile = Some(name);
+    }
 
-impl AudioManager {
-    pub fn new() -> Result<Self> {
-        let (stream, stream_handle) = OutputStream::try_default()?;
-        let sink = Sink::try_new(&stream_handle)?;
-        sink.pause();
+    pub fn library(&self) -> &Library {
+        &self.library
+    }
 
-        Ok(Self {
-            sink,
-            _stream: stream,
-            _stream_handle: stream_handle,
-            playback_progress: Duration::ZERO,
-            active_source_duration: None,
-        })
+    pub fn audio_manager(&self) -> &M {
+        &self.am
     }
 
-    pub fn toggle_playback(&mut self) {
-        if self.sink.is_paused() {
-            self.play();
-        } else {
-            self.pause();
-        }
+    pub fn ui_mode(&self) -> &AppUiMode {
+        &self.app_state.ui_mode
     }
 
-    pub fn set_active_source(&mut self, path: &PathBuf) -> Result<()> {
-        let source = Decoder::new(BufReader::new(File::open(path)?))?;
-        self.active_source_duration = source.total_duration();
-        self.sink.clear();
-        self.sink.append(source);
-        self.playback_progress = Duration::ZERO;
-        Ok(())
+    pub fn playback_mode(&self) -> &PlaybackMode {
+        &self.app_state.playback_mode
     }
 
-    pub fn skip(&mut self) {
-        self.playback_progress = self
-            .active_source_duration
-            .expect("Already checked if we have an active source.");
+    pub fn search_query(&self) -> Option<&str> {
+        self.app_state.search_query.as_deref()
+    }
+
+    pub fn search_case_sensitive(&self) -> bool {
+        self.app_state.search_case_sensitive
+    }
+
+    pub fn selected_file_ix(&self) -> usize {
+        self.app_state.selected_file_ix
     }
 
-    pub fn seek_forward(&mut self) {
-        let seek_diff = Duration::from_secs(5);
-        if let Ok(()) = self.sink.try_seek(self.playback_progress + seek_diff) {
-            self.playback_progress += seek_diff;
+    /// Library indices of the songs shown in the file list, in display order, after applying the
+    /// active view and search query. [`Self::selected_file_ix`] indexes into this list.
+    pub fn visible_indices(&self) -> Vec<usize> {
+        let indices = match &self.app_state.view {
+            LibraryView::All => self.library.sorted_indices().to_vec(),
+            LibraryView::Favourites => self.library.favourites_view(&self.app_state.favourites),
+            LibraryView::MostPlayed => self
+                .library
+                .most_played_view(&self.play_stats, MOST_PLAYED_LIMIT),
+            LibraryView::Unplayed => self.library.unplayed_view(&self.play_stats),
+            LibraryView::RecentlyAdded(days) => self.library.recently_added(*days),
+            LibraryView::MissingCovers(indices) | LibraryView::DiscoveryDate(indices) => {
+                indices.clone()
+            }
+            LibraryView::SkipRatio(side, threshold) => {
+                self.library
+                    .skip_ratio_view(&self.play_stats, *side, *threshold)
+            }
+            LibraryView::MissingTags => self
+                .library
+                .find_missing_tags()
+                .into_iter()
+                .map(|(ix, _)| ix)
+                .collect(),
+        };
+        match self.search_query() {
+            Some(q) => indices
+                .into_iter()
+                .filter(|ix| {
+                    self.library.files()[*ix].matches_query(q, self.app_state.search_case_sensitive)
+                })
+                .collect(),
+            None => indices,
         }
     }
 
-    pub fn seek_backward(&mut self) {
-        let seek_diff = Duration::from_secs(1);
-        if seek_diff > self.playback_progress {
-            self.playback_progress = Duration::ZERO;
-            let _ = self.sink.try_seek(self.playback_progress);
-        } else if let Ok(()) = self.sink.try_seek(self.playback_progress - seek_diff) {
-            self.playback_progress -= seek_diff;
+    /// Header to show above the file list for the active view, if any.
+    pub fn view_title(&self) -> Option<String> {
+        match &self.app_state.view {
+            LibraryView::All => None,
+            LibraryView::MostPlayed => Some(String::from("Most Played")),
+            LibraryView::Favourites => Some(format!(
+                "Favourites: {}",
+                self.library
+                    .favourites_view(&self.app_state.favourites)
+                    .len()
+            )),
+            LibraryView::Unplayed => Some(format!(
+                "Unplayed: {} / {}",
+                self.library.unplayed_view(&self.play_stats).len(),
+                self.library.files().len()
+            )),
+            LibraryView::RecentlyAdded(days) => Some(format!("Recently Added (last {days} days)")),
+            LibraryView::MissingCovers(indices) => {
+                Some(format!("Missing Covers: {}", indices.len()))
+            }
+            LibraryView::DiscoveryDate(_) => Some(String::from("In Order of Discovery")),
+            LibraryView::SkipRatio(side, threshold) => {
+                let side = if *side == std::cmp::Ordering::Less {
+                    '<'
+                } else {
+                    '>'
+                };
+                Some(format!("Skip Ratio {side} {threshold}"))
+            }
+            LibraryView::MissingTags => Some(format!(
+                "Missing Tags: {}",
+                self.library.find_missing_tags().len()
+            )),
         }
     }
 
-    pub fn play(&mut self) {
-        self.sink.play();
+    fn set_view(&mut self, view: LibraryView) {
+        self.app_state.view = view;
+        self.app_state.selected_file_ix = 0;
     }
 
-    pub fn pause(&mut self) {
-        self.sink.pause();
+    pub fn command_input(&self) -> &str {
+        &self.app_state.command_input
     }
 
-    pub fn update(&mut self, dt: f64) {
-        if !self.sink.is_paused() {
-            self.playback_progress += Duration::from_secs_f64(dt);
-        }
+    pub fn cover_path_input(&self) -> &str {
+        &self.app_state.cover_path_input
     }
 
-    pub fn get_volume(&self) -> f32 {
-        self.sink.volume()
+    /// The copy [`AppUiMode::ConfirmDelete`] is asking about.
+    pub fn pending_delete(&self) -> Option<&Path> {
+        self.app_state.pending_delete.as_deref()
     }
 
-    pub fn set_volume(&mut self, volume: f32) {
-        self.sink.set_volume(volume);
+    pub fn quality_report(&self) -> Option<&QualityReport> {
+        self.app_state.quality_report.as_ref()
     }
 
-    pub fn playback_progress(&self) -> &Duration {
-        &self.playback_progress
+    /// Title and visible lines of the info popup.
+    pub fn info(&self) -> (&str, &[String]) {
+        let (title, lines) = &self.app_state.info;
+        let scroll = self.app_state.info_scroll.min(lines.len());
+        (title, &lines[scroll..])
     }
 
-    pub fn _active_source_duration(&self) -> Option<Duration> {
-        self.active_source_duration
+    fn show_info(&mut self, title: impl Into<String>, lines: Vec<String>) {
+        self.app_state.info = (title.into(), lines);
+        self.app_state.info_scroll = 0;
+        self.app_state.ui_mode = AppUiMode::InfoPopup;
     }
-}
 
-pub struct Library {
-    root_dir: PathBuf,
-    files: Vec<SongInfo>,
-}
+    pub fn alarm_time(&self) -> Option<AlarmTime> {
+        self.app_state.alarm_time
+    }
+
+    /// The most recent status message, if it hasn't timed out yet.
+    pub fn status_message(&self) -> Option<&str> {
+        self.app_state
+            .status_message
+            .as_ref()
+            .filter(|(_, shown_at)| shown_at.elapsed() < STATUS_MESSAGE_TIMEOUT)
+            .map(|(msg, _)| msg.as_str())
+    }
+
+    fn set_status_message(&mut self, msg: impl Into<String>) {
+        self.app_state.status_message = Some((msg.into(), Instant::now()));
+    }
+
+    /// Show the problems [`Config::load`] found with the config file, logging each in full.
+    pub fn report_config_problems(&mut self, problems: &[String]) {
+        for problem in problems {
+            log::warn!("{problem}");
+        }
+        match problems {
+            [] => {}
+            [problem] => self.set_status_message(problem.clone()),
+            [first, rest @ ..] => {
+                self.set_status_message(format!("{first} (and {} more, see the log)", rest.len()));
+            }
+        }
+    }
+
+    pub fn update(&mut self) -> Result<()> {
+        match self.am.update() {
+            OutputStatus::Playing => {}
+            OutputStatus::Reconnecting | OutputStatus::Reconnected => {
+                self.set_status_message("Audio device changed, reconnecting…");
+            }
+            OutputStatus::Lost => {
+                self.quit();
+                return Ok(());
+            }
+        }
+        let now = Instant::now();
+        if self.app_state.active_song.is_some() && !self.am.is_paused() {
+            let dt = now - self.last_tick;
+            self.app_state.total_listening_time += dt;
+            self.play_stats.add_listening_time(dt);
+            self.app_state.unsaved_listening_time += dt;
+            if self.app_state.unsaved_listening_time >= LISTENING_TIME_SAVE_INTERVAL {
+                self.save_listening_time();
+            }
+        }
+        self.last_tick = now;
+        self.handle_events()?;
+        self.check_alarm()?;
+        if self.app_state.sort_save_due.is_some_and(|due| now >= due) {
+            self.save_sort();
+        }
+        while let Ok(msg) = self.task_messages.1.try_recv() {
+            self.set_status_message(msg);
+        }
+        while let Ok((path, waveform)) = self.waveforms.1.try_recv() {
+            // Analyses of songs that were skipped past finish late, so check it's still current
+            if self
+                .app_state
+                .active_song
+                .as_ref()
+                .is_some_and(|s| s.file_path == path)
+            {
+                self.waveform = Some(waveform);
+            }
+        }
+        self.advance_if_finished()?;
+        // Pick the next shuffled song ahead of time, so `next_song_info` can show it
+        if self.app_state.playback_mode == PlaybackMode::Shuffle
+            && self.app_state.active_song.is_some()
+            && self.app_state.upcoming_shuffle_ix.is_none()
+        {
+            self.app_state.upcoming_shuffle_ix = Some(self.next_shuffle_ix());
+        }
+        Ok(())
+    }
+
+    /// Move on to the next song once the playing one has ended, either by reaching the length it
+    /// was expected to have or by running out of audio. Tags can claim a song is longer than its
+    /// audio, so neither is enough alone.
+    fn advance_if_finished(&mut self) -> Result<()> {
+        let Some(s) = &self.app_state.active_song else {
+            return Ok(());
+        };
+        let ended = self.am.is_finished()
+            || self.am.position() >= self.am.effective_end().unwrap_or(s.duration);
+        if !ended {
+            return Ok(());
+        }
+        if let Some(ix) = self.queue.pop_next() {
+            self.app_state.playing_file_ix = ix;
+            self.play_at_ix()?;
+        } else if self.app_state.playback_mode == PlaybackMode::Normal {
+            // The next row of the file list, whatever it's sorted by
+            if let Some(ix) = self.library.next_in_sort(self.app_state.playing_file_ix) {
+                self.app_state.playing_file_ix = ix;
+                self.play_at_ix()?;
+            } else {
+                self.am.pause();
+            }
+        } else if self.app_state.playback_mode == PlaybackMode::Shuffle {
+            self.app_state.playing_file_ix = match self.app_state.upcoming_shuffle_ix.take() {
+                Some(ix) => ix,
+                None => self.next_shuffle_ix(),
+            };
+            self.play_at_ix()?;
+        }
+        Ok(())
+    }
+
+    /// The song that plays once the current one ends: the front of the queue, otherwise the next
+    /// song in the library's sort order, or in shuffle the song already picked to follow. `None` if nothing is
+    /// playing or playback stops at the end of the library.
+    pub fn next_song_info(&self) -> Option<&SongInfo> {
+        self.app_state.active_song.as_ref()?;
+        let ix = match self.queue.iter().next() {
+            Some(ix) => ix,
+            None => match self.app_state.playback_mode {
+                PlaybackMode::Normal => {
+                    self.library.next_in_sort(self.app_state.playing_file_ix)?
+                }
+                PlaybackMode::Shuffle => self.app_state.upcoming_shuffle_ix?,
+            },
+        };
+        self.library().files().get(ix)
+    }
+
+    fn handle_events(&mut self) -> Result<()> {
+        if event::poll(Duration::from_millis(self.config.event_poll_ms))? {
+            match event::read()? {
+                Event::Key(key)
+                    if key.kind == event::KeyEventKind::Press
+                        || (key.kind == event::KeyEventKind::Repeat
+                            && self.accepts_repeat(key)) =>
+                {
+                    match self.app_state.ui_mode {
+                        AppUiMode::FileList => self.handle_file_list_key(key)?,
+                        AppUiMode::SearchPopup => self.handle_search_key(key)?,
+                        AppUiMode::CommandPrompt => self.handle_command_prompt_key(key),
+                        AppUiMode::CoverPathPrompt => self.handle_cover_path_key(key),
+                        AppUiMode::Duplicates => self.handle_duplicates_key(key),
+                        AppUiMode::ColumnConfig => self.handle_column_config_key(key),
+                        AppUiMode::InfoPopup => self.handle_info_popup_key(key),
+                        AppUiMode::ConfirmRescan => self.handle_confirm_rescan_key(key)?,
+                        AppUiMode::ConfirmDelete => self.handle_confirm_delete_key(key),
+                        AppUiMode::QualityReport => {
+                            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+                                self.app_state.ui_mode = AppUiMode::FileList;
+                            }
+                        }
+                    }
+                }
+                Event::Resize(width, height) => self.pending_resize = Some((width, height)),
+                Event::FocusLost => self.handle_focus_change(false),
+                Event::FocusGained => self.handle_focus_change(true),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Pause when the terminal loses focus if [`Config::pause_on_focus_loss`] is set, and resume
+    /// when it regains focus if that's why playback stopped.
+    fn handle_focus_change(&mut self, focused: bool) {
+        if focused {
+            if std::mem::take(&mut self.app_state.auto_paused) {
+                self.am.play();
+            }
+        } else if self.config.pause_on_focus_loss
+            && self.app_state.active_song.is_some()
+            && !self.am.is_paused()
+        {
+            self.am.pause();
+            self.app_state.auto_paused = true;
+        }
+    }
+
+    /// Whether `key` should keep acting while it's held down, for terminals that report
+    /// repeats. Moving around, typing into a prompt, and the volume and seek actions do.
+    /// Everything else, including toggles and anything destructive, waits for a fresh press.
+    fn accepts_repeat(&self, key: KeyEvent) -> bool {
+        let is_navigation = matches!(
+            key.code,
+            KeyCode::Up | KeyCode::Down | KeyCode::PageUp | KeyCode::PageDown
+        );
+        let is_typing = matches!(
+            self.app_state.ui_mode,
+            AppUiMode::SearchPopup | AppUiMode::CommandPrompt | AppUiMode::CoverPathPrompt
+        ) && matches!(key.code, KeyCode::Char(_) | KeyCode::Backspace);
+        let action_repeats = self.app_state.ui_mode == AppUiMode::FileList
+            && self
+                .config
+                .keybindings
+                .action_for(key)
+                .is_some_and(Action::repeats);
+        is_navigation || is_typing || action_repeats
+    }
+
+    fn handle_file_list_key(&mut self, key: KeyEvent) -> Result<()> {
+        if matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+            let forward = key.code == KeyCode::Tab && key.modifiers != KeyModifiers::SHIFT;
+            self.app_state.focused_panel = self.app_state.focused_panel.cycle(forward);
+            return Ok(());
+        }
+        // Navigation keys go to whichever panel has focus
+        let is_panel_key = matches!(
+            key.code,
+            KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Enter
+                | KeyCode::Esc
+                | KeyCode::Delete
+                | KeyCode::Char('a' | 'c' | 'd')
+        );
+        if is_panel_key && self.app_state.focused_panel == FocusedPanel::QueuePanel {
+            return self.handle_queue_panel_key(key);
+        }
+
+        if let Some((_, _, handler)) = file_list_keys()
+            .into_iter()
+            .find(|(code, modifiers, _)| key_matches(key, *code, *modifiers))
+        {
+            handler(self)?;
+        } else if let Some(action) = self.config.keybindings.action_for(key) {
+            self.run_action(action)?;
+        }
+        Ok(())
+    }
+
+    /// Jump to `tenths` tenths of the way through the playing song, if there is one.
+    fn seek_to_tenth(&mut self, tenths: u8) -> Result<()> {
+        if self.app_state.active_song.is_none() {
+            return Ok(());
+        }
+        self.seek_to_fraction(f64::from(tenths) / 10.0)
+    }
+
+    /// Jump to `fraction` of the way through the playing song, clamped to its start and end.
+    pub fn seek_to_fraction(&mut self, fraction: f64) -> Result<()> {
+        if fraction.is_nan() {
+            return Err(eyre!("Can't seek to a fraction of NaN"));
+        }
+        let song = self
+            .app_state
+            .active_song
+            .as_ref()
+            .ok_or_else(|| eyre!("Nothing is playing"))?;
+        let target = song.duration.mul_f64(fraction.clamp(0.0, 1.0));
+        self.am.seek_to(target);
+        Ok(())
+    }
+
+    fn toggle_playback_mode(&mut self) {
+        self.app_state.playback_mode = match self.app_state.playback_mode {
+            PlaybackMode::Normal => PlaybackMode::Shuffle,
+            PlaybackMode::Shuffle => PlaybackMode::Normal,
+        };
+        self.app_state.shuffle_history.clear();
+        self.app_state.upcoming_shuffle_ix = None;
+    }
+
+    /// Ask for an image to embed as the selected song's cover, if the missing covers view is
+    /// open and has songs in it.
+    fn open_cover_path_prompt(&mut self) {
+        if matches!(self.app_state.view, LibraryView::MissingCovers(_))
+            && !self.visible_indices().is_empty()
+        {
+            self.app_state.cover_path_input.clear();
+            self.app_state.ui_mode = AppUiMode::CoverPathPrompt;
+        }
+    }
+
+    fn play_selected(&mut self) -> Result<()> {
+        if self.library.files().is_empty() {
+            let rescan = config::describe_binding(self.config.keybindings.rescan);
+            self.set_status_message(format!("Library is empty. Press '{rescan}' to scan."));
+            return Ok(());
+        }
+        let visible = self.visible_indices();
+        if let Some(ix) = visible.get(self.app_state.selected_file_ix) {
+            self.remember_shuffle_context();
+            self.app_state.playing_file_ix = *ix;
+            self.play_at_ix()?;
+        }
+        Ok(())
+    }
+
+    fn open_search(&mut self) {
+        self.app_state
+            .pre_search_selected_ix
+            .get_or_insert(self.app_state.selected_file_ix);
+        self.app_state.ui_mode = AppUiMode::SearchPopup;
+    }
+
+    fn open_command_prompt(&mut self) {
+        self.app_state.command_input.clear();
+        self.app_state.ui_mode = AppUiMode::CommandPrompt;
+    }
+
+    fn select_next_file(&mut self) {
+        self.app_state.selected_file_ix = (self.app_state.selected_file_ix + 1)
+            .min(self.visible_indices().len().saturating_sub(1));
+    }
+
+    fn select_previous_file(&mut self) {
+        self.app_state.selected_file_ix = self.app_state.selected_file_ix.max(1) - 1;
+    }
+
+    fn run_action(&mut self, action: Action) -> Result<()> {
+        let playing = self.app_state.active_song.is_some();
+        match action {
+            Action::VolumeUp => self.volume_up(),
+            Action::VolumeDown => self.volume_down(),
+            Action::SeekForward if playing => self.am.seek_forward(),
+            Action::SeekBackward if playing => self.am.seek_backward(),
+            Action::Skip if playing => {
+                self.record_early_skip();
+                self.am.skip();
+            }
+            Action::SeekForward | Action::SeekBackward | Action::Skip => {}
+            Action::Rescan if self.config.confirm_rescan => {
+                self.app_state.ui_mode = AppUiMode::ConfirmRescan;
+            }
+            Action::Rescan => self.reload_library()?,
+        }
+        Ok(())
+    }
+
+    /// Remember the song playing in shuffle, so [`Self::undo_play`] can go back to it after the
+    /// user picks another.
+    fn remember_shuffle_context(&mut self) {
+        if self.app_state.playback_mode != PlaybackMode::Shuffle
+            || self.app_state.active_song.is_none()
+        {
+            return;
+        }
+        let history = &mut self.app_state.play_history;
+        history.push((self.app_state.playing_file_ix, self.app_state.playback_mode));
+        if history.len() > PLAY_HISTORY_LEN {
+            history.remove(0);
+        }
+    }
+
+    /// Go back to the song and playback mode from before the last song picked during shuffle.
+    fn undo_play(&mut self) -> Result<()> {
+        let Some((ix, mode)) = self
+            .app_state
+            .play_history
+            .pop()
+            .filter(|(ix, _)| *ix < self.library.files.len())
+        else {
+            self.set_status_message(String::from("Nothing to go back to"));
+            return Ok(());
+        };
+        self.app_state.playback_mode = mode;
+        self.app_state.playing_file_ix = ix;
+        self.play_at_ix()
+    }
+
+    /// Count a skip against the playing song if it's still in its first [`SKIP_THRESHOLD`].
+    fn record_early_skip(&mut self) {
+        let Some(song) = &self.app_state.active_song else {
+            return;
+        };
+        let played = self.am.position().as_secs_f64() / song.duration.as_secs_f64();
+        if played < SKIP_THRESHOLD {
+            self.play_stats.record_skip(&song.file_path);
+            if let Err(e) = self.play_stats.save() {
+                self.set_status_message(format!("Couldn't save play stats: {e}"));
+            }
+        }
+    }
+
+    fn handle_confirm_rescan_key(&mut self, key: KeyEvent) -> Result<()> {
+        self.app_state.ui_mode = AppUiMode::FileList;
+        if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+            self.reload_library()?;
+        }
+        Ok(())
+    }
+
+    fn handle_queue_panel_key(&mut self, key: KeyEvent) -> Result<()> {
+        let selected = self.selected_queue_ix();
+        if key.code == KeyCode::Down {
+            self.app_state.selected_queue_ix =
+                (selected + 1).min(self.queue.len().saturating_sub(1));
+        } else if key.code == KeyCode::Up {
+            self.app_state.selected_queue_ix = selected.saturating_sub(1);
+        } else if key.code == KeyCode::Enter {
+            // Play the song now instead of waiting for its turn
+            if let Some(ix) = self.queue.remove(selected) {
+                self.app_state.playing_file_ix = ix;
+                self.play_at_ix()?;
+            }
+        } else if matches!(key.code, KeyCode::Delete | KeyCode::Char('d')) {
+            if !self.queue.is_empty() {
+                self.save_queue_state();
+                self.queue.remove(selected);
+            }
+        } else if key.code == KeyCode::Esc {
+            self.app_state.focused_panel = FocusedPanel::FileList;
+        }
+        self.app_state.selected_queue_ix = self
+            .app_state
+            .selected_queue_ix
+            .min(self.queue.len().saturating_sub(1));
+        Ok(())
+    }
+
+    fn handle_search_key(&mut self, key: KeyEvent) -> Result<()> {
+        // Terminals send Ctrl+I as Tab
+        let toggle_case = key.code == KeyCode::Tab
+            || (key.code == KeyCode::Char('i') && key.modifiers == KeyModifiers::CONTROL);
+        if toggle_case {
+            self.app_state.search_case_sensitive = !self.app_state.search_case_sensitive;
+        } else if let KeyCode::F(n @ 2..=10) = key.code {
+            if let Some(name) = self.saved_search_names().get(usize::from(n) - 2) {
+                let name = name.clone();
+                self.load_search(&name);
+            }
+        } else if key.code == KeyCode::Enter && key.modifiers == KeyModifiers::CONTROL {
+            self.app_state.ui_mode = AppUiMode::FileList;
+            self.queue_search_results()?;
+        } else if key.code == KeyCode::Enter {
+            self.app_state.ui_mode = AppUiMode::FileList;
+        } else if key.code == KeyCode::Backspace {
+            if let Some(q) = &self.app_state.search_query {
+                if q.len() == 1 {
+                    self.app_state.search_query = None;
+                    if let Some(ix) = self.app_state.pre_search_selected_ix.take() {
+                        self.app_state.selected_file_ix = ix;
+                    }
+                } else {
+                    self.app_state.search_query = Some(q[..q.len() - 1].to_string());
+                }
+            }
+        } else if let KeyCode::Char(c) = key.code {
+            let mut query = self.app_state.search_query.clone();
+            if self.app_state.search_query.is_none() {
+                query = Some(c.to_string());
+            } else {
+                query.as_mut().unwrap().push(c);
+            }
+            self.app_state.search_query = query;
+        }
+        self.clamp_file_selection();
+        Ok(())
+    }
+
+    /// Add every song in the search results to the queue, in the order they're shown. If the
+    /// queue was empty, the first of them starts playing.
+    fn queue_search_results(&mut self) -> Result<()> {
+        let was_empty = self.queue.is_empty();
+        let mut added = 0;
+        for ix in self.visible_indices() {
+            if self.config.deduplicate_queue {
+                added += usize::from(self.queue.push_dedup(ix));
+            } else {
+                self.queue.push(ix);
+                added += 1;
+            }
+        }
+        self.set_status_message(format!("Added {added} songs to queue"));
+        if was_empty {
+            if let Some(ix) = self.queue.pop_next() {
+                self.app_state.playing_file_ix = ix;
+                self.play_at_ix()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the queue with the songs at library indices `indices` and play the first of them
+    /// now. Nothing changes if any of the indices are out of range.
+    pub fn batch_play(&mut self, indices: &[usize]) -> Result<()> {
+        let len = self.library.files().len();
+        let out_of_range = indices
+            .iter()
+            .filter(|ix| **ix >= len)
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if !out_of_range.is_empty() {
+            return Err(eyre!(
+                "Library indices out of range: {}",
+                out_of_range.join(", ")
+            ));
+        }
+        let Some((&first, rest)) = indices.split_first() else {
+            return Ok(());
+        };
+        self.save_queue_state();
+        self.queue.clear();
+        for ix in rest {
+            self.queue.push(*ix);
+        }
+        self.app_state.playing_file_ix = first;
+        self.play_at_ix()
+    }
+
+    /// Set the alarm from `:alarm`'s argument, a time or `off`.
+    fn set_alarm(&mut self, arg: Option<&str>) {
+        match arg {
+            Some("off") => {
+                self.app_state.alarm_time = None;
+                self.set_status_message("Alarm cleared");
+            }
+            Some(t) => match AlarmTime::parse(t) {
+                Some(alarm_time) => {
+                    self.app_state.alarm_time = Some(alarm_time);
+                    self.app_state.alarm_last_checked = alarm::local_seconds_of_day();
+                    self.set_status_message(format!("Alarm set for {alarm_time}"));
+                }
+                None => self.set_status_message(format!("Invalid alarm time: {t}")),
+            },
+            None => self.set_status_message("Usage: :alarm HH:MM | :alarm off"),
+        }
+    }
+
+    /// Run one of the commands for saving and recalling searches, with its name argument.
+    fn run_search_command(&mut self, command: &str, name: Option<&str>) {
+        match command {
+            "search-save" => match name {
+                Some(name) => match self.save_search(name) {
+                    Ok(()) => self.set_status_message(format!("Saved search {name}")),
+                    Err(e) => self.set_status_message(format!("Couldn't save search: {e}")),
+                },
+                None => self.set_status_message("Usage: :search-save NAME"),
+            },
+            "search-load" => match name {
+                Some(name) if self.load_search(name) => {
+                    self.set_status_message(format!("Loaded search {name}"));
+                }
+                Some(name) => self.set_status_message(format!("No saved search named {name}")),
+                None => self.set_status_message("Usage: :search-load NAME"),
+            },
+            "searches" => {
+                let lines = self
+                    .saved_search_names()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        let preset = &self.config.saved_searches[&name];
+                        let case = if preset.case_sensitive {
+                            "[Aa]"
+                        } else {
+                            "[aa]"
+                        };
+                        let key = if i < 9 {
+                            format!("F{} ", i + 2)
+                        } else {
+                            String::new()
+                        };
+                        format!("{key}{name}: {case} {}", preset.query)
+                    })
+                    .collect();
+                self.show_info("Saved Searches", lines);
+            }
+            _ => {}
+        }
+    }
+
+    /// Rescan the library without stopping playback. The playing song, the selection, and the
+    /// queue follow their songs to wherever the rescan put them, and songs that are gone drop
+    /// out of the queue.
+    fn reload_library(&mut self) -> Result<()> {
+        let positions = self.song_positions();
+        let (result, playing_ix) = self
+            .library
+            .scan_preserving_position(positions.playing.as_deref())?;
+        for (path, e) in result.tag_errors.iter().chain(&result.decode_errors) {
+            log::warn!("{}: {e}", path.display());
+        }
+        self.set_status_message(result.summary());
+        self.restore_song_positions(&positions, playing_ix)
+    }
+
+    /// Add the song at `path` from `:add`, or read its tags again if it's already in the library.
+    fn add_file(&mut self, path: &str) {
+        if path.is_empty() {
+            self.set_status_message("Usage: :add PATH");
+            return;
+        }
+        let positions = self.song_positions();
+        match self.library.scan_file(Path::new(path)) {
+            Ok(song) => {
+                let playing_ix = positions
+                    .playing
+                    .as_deref()
+                    .and_then(|p| self.library.find_by_path(p));
+                match self.restore_song_positions(&positions, playing_ix) {
+                    Ok(()) => self.set_status_message(format!("Added {song}")),
+                    Err(e) => self.set_status_message(format!("Couldn't play on: {e}")),
+                }
+            }
+            Err(e) => self.set_status_message(format!("Couldn't add {path}: {e}")),
+        }
+    }
+
+    fn song_positions(&self) -> SongPositions {
+        let files = self.library.files();
+        let path_of = |ix: usize| files.get(ix).map(|s| s.file_path.clone());
+        SongPositions {
+            selected: self
+                .visible_indices()
+                .get(self.app_state.selected_file_ix)
+                .and_then(|ix| path_of(*ix)),
+            paths: files.iter().map(|s| s.file_path.clone()).collect(),
+            playing: self
+                .app_state
+                .active_song
+                .as_ref()
+                .map(|s| s.file_path.clone()),
+        }
+    }
+
+    /// Point the playing index, the selection, and every other stored index back at the songs in
+    /// `positions` after the library changed, with `playing_ix` the playing song's new index. If
+    /// the playing song is gone, the first song after it that's still there plays instead.
+    fn restore_song_positions(
+        &mut self,
+        positions: &SongPositions,
+        playing_ix: Option<usize>,
+    ) -> Result<()> {
+        let new_ixs = positions
+            .paths
+            .iter()
+            .map(|p| self.library.find_by_path(p))
+            .collect::<Vec<_>>();
+        if let Some(ix) = playing_ix {
+            self.app_state.playing_file_ix = ix;
+        } else if positions.playing.is_some() {
+            let next = new_ixs
+                .iter()
+                .skip(self.app_state.playing_file_ix + 1)
+                .find_map(|ix| *ix);
+            if let Some(ix) = next {
+                self.app_state.playing_file_ix = ix;
+                self.play_at_ix()?;
+            } else {
+                self.am.pause();
+                self.app_state.active_song = None;
+                self.app_state.playing_file_ix = 0;
+            }
+        }
+        self.remap_indices(&new_ixs);
+        if let Some(ix) = positions
+            .selected
+            .as_deref()
+            .and_then(|p| self.library.find_by_path(p))
+        {
+            if let Some(pos) = self.visible_indices().iter().position(|v| *v == ix) {
+                self.app_state.selected_file_ix = pos;
+            }
+        }
+        self.clamp_file_selection();
+        Ok(())
+    }
+
+    /// Keep the file list selection on a row that's still shown.
+    fn clamp_file_selection(&mut self) {
+        self.app_state.selected_file_ix = self
+            .app_state
+            .selected_file_ix
+            .min(self.visible_indices().len().saturating_sub(1));
+    }
+
+    /// Names of the saved searches, in the order `F2` to `F10` load them.
+    fn saved_search_names(&self) -> Vec<String> {
+        let mut names = self
+            .config
+            .saved_searches
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Save the current search as `name`, replacing any saved search with that name.
+    fn save_search(&mut self, name: &str) -> Result<()> {
+        let query = self
+            .app_state
+            .search_query
+            .clone()
+            .ok_or_else(|| eyre!("No search to save"))?;
+        self.config.saved_searches.insert(
+            name.to_string(),
+            SearchPreset {
+                query,
+                case_sensitive: self.app_state.search_case_sensitive,
+            },
+        );
+        config::save_saved_searches(&self.config.saved_searches)
+    }
+
+    /// Search with the saved search `name`. Returns whether there is one by that name.
+    fn load_search(&mut self, name: &str) -> bool {
+        let Some(preset) = self.config.saved_searches.get(name).cloned() else {
+            return false;
+        };
+        self.app_state
+            .pre_search_selected_ix
+            .get_or_insert(self.app_state.selected_file_ix);
+        self.app_state.search_query = Some(preset.query).filter(|q| !q.is_empty());
+        self.app_state.search_case_sensitive = preset.case_sensitive;
+        self.clamp_file_selection();
+        true
+    }
+
+    fn handle_command_prompt_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Enter {
+            self.app_state.ui_mode = AppUiMode::FileList;
+            let command = std::mem::take(&mut self.app_state.command_input);
+            self.run_command(&command);
+        } else if key.code == KeyCode::Esc {
+            self.app_state.ui_mode = AppUiMode::FileList;
+            self.app_state.command_input.clear();
+        } else if key.code == KeyCode::Backspace {
+            self.app_state.command_input.pop();
+        } else if let KeyCode::Char(c) = key.code {
+            self.app_state.command_input.push(c);
+        }
+    }
+
+    fn handle_duplicates_key(&mut self, key: KeyEvent) {
+        let selected = self.app_state.selected_duplicate_ix;
+        if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+            self.app_state.ui_mode = AppUiMode::FileList;
+        } else if key.code == KeyCode::Down {
+            self.app_state.selected_duplicate_ix =
+                (selected + 1).min(self.app_state.duplicates.len().saturating_sub(1));
+        } else if key.code == KeyCode::Up {
+            self.app_state.selected_duplicate_ix = selected.saturating_sub(1);
+        } else if let KeyCode::Char(c @ ('1' | '2')) = key.code {
+            if let Some((first, second)) = self.app_state.duplicates.get(selected) {
+                let path = if c == '1' { &first.0 } else { &second.0 }.clone();
+                self.app_state.pending_delete = Some(path);
+                self.app_state.ui_mode = AppUiMode::ConfirmDelete;
+            }
+        }
+    }
+
+    fn handle_confirm_delete_key(&mut self, key: KeyEvent) {
+        self.app_state.ui_mode = AppUiMode::Duplicates;
+        let Some(path) = self.app_state.pending_delete.take() else {
+            return;
+        };
+        if matches!(key.code, KeyCode::Char('y' | 'Y')) {
+            self.delete_duplicate(&path);
+        }
+    }
+
+    /// Add the selected song to the end of the queue.
+    fn queue_selected(&mut self) {
+        let visible = self.visible_indices();
+        let Some(&ix) = visible.get(self.app_state.selected_file_ix) else {
+            return;
+        };
+        if self.config.deduplicate_queue {
+            if !self.queue.push_dedup(ix) {
+                self.set_status_message("Already in queue");
+                return;
+            }
+        } else {
+            self.queue.push(ix);
+        }
+        self.set_status_message(format!("Added to queue ({} queued)", self.queue.len()));
+    }
+
+    /// Delete the song file at `path` and drop it from the library and the duplicates list.
+    fn delete_duplicate(&mut self, path: &Path) {
+        if let Err(e) = std::fs::remove_file(path) {
+            self.set_status_message(format!("Couldn't delete {}: {e}", path.display()));
+            return;
+        }
+        let removed = self.handle_removed_path(path);
+        self.app_state
+            .duplicates
+            .retain(|(a, b)| a.0 != path && b.0 != path);
+        self.app_state.selected_duplicate_ix = self
+            .app_state
+            .selected_duplicate_ix
+            .min(self.app_state.duplicates.len().saturating_sub(1));
+        match removed {
+            Ok(()) => self.set_status_message(format!("Deleted {}", path.display())),
+            Err(e) => self.set_status_message(format!(
+                "Deleted {}, but couldn't play the next song: {e}",
+                path.display()
+            )),
+        }
+    }
+
+    /// Remember the queue as it is now, so the change about to be made to it can be undone.
+    fn save_queue_state(&mut self) {
+        let history = &mut self.app_state.queue_history;
+        if history.len() == QUEUE_HISTORY_LIMIT {
+            history.remove(0);
+        }
+        history.push(self.queue.clone());
+    }
+
+    /// Put the queue back the way it was before the last change saved with
+    /// [`Self::save_queue_state`].
+    fn undo_queue_change(&mut self) {
+        match self.app_state.queue_history.pop() {
+            Some(queue) => {
+                self.queue = queue;
+                self.set_status_message(format!("Queue restored ({} entries)", self.queue.len()));
+            }
+            None => self.set_status_message("Nothing to undo"),
+        }
+    }
+
+    /// Drop the song at `path` from the library after its file has gone, keeping the playing
+    /// position, queue, and selection on the same songs. If it was playing, the next song starts.
+    fn handle_removed_path(&mut self, path: &Path) -> Result<()> {
+        let Some(ix) = self.library.remove_path(path) else {
+            return Ok(());
+        };
+        let new_ixs = (0..=self.library.files.len())
+            .map(|old| match old.cmp(&ix) {
+                std::cmp::Ordering::Less => Some(old),
+                std::cmp::Ordering::Equal => None,
+                std::cmp::Ordering::Greater => Some(old - 1),
+            })
+            .collect::<Vec<_>>();
+        self.remap_indices(&new_ixs);
+        self.clamp_file_selection();
+
+        let was_playing = self
+            .app_state
+            .active_song
+            .as_ref()
+            .is_some_and(|s| s.file_path == path);
+        if ix < self.app_state.playing_file_ix {
+            self.app_state.playing_file_ix -= 1;
+        } else if was_playing {
+            // The next song has moved up into the removed song's place
+            if ix < self.library.files.len() {
+                self.play_at_ix()?;
+            } else {
+                self.am.pause();
+                self.app_state.active_song = None;
+                self.app_state.playing_file_ix = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Point the library indices kept outside the library at the same songs after songs were
+    /// added or removed. `new_ixs[old]` is the new index of the song that was at `old`, or
+    /// `None` if it's gone.
+    fn remap_indices(&mut self, new_ixs: &[Option<usize>]) {
+        let new_ix = |ix: usize| new_ixs.get(ix).copied().flatten();
+        self.queue.remap(new_ix);
+        for queue in &mut self.app_state.queue_history {
+            queue.remap(new_ix);
+        }
+        let state = &mut self.app_state;
+        state.shuffle_history = state
+            .shuffle_history
+            .iter()
+            .filter_map(|ix| new_ix(*ix))
+            .collect();
+        state.play_history = state
+            .play_history
+            .iter()
+            .filter_map(|(ix, mode)| Some((new_ix(*ix)?, *mode)))
+            .collect();
+        state.upcoming_shuffle_ix = state.upcoming_shuffle_ix.and_then(new_ix);
+        if let LibraryView::MissingCovers(indices) | LibraryView::DiscoveryDate(indices) =
+            &mut self.app_state.view
+        {
+            *indices = indices.iter().filter_map(|ix| new_ix(*ix)).collect();
+        }
+    }
+
+    /// Re-sort the library by `column`, keeping the selected song selected, and remember the
+    /// sort in the config.
+    fn sort_library(&mut self, column: Column, direction: SortDirection) {
+        let selected = self.visible_indices().get(self.selected_file_ix()).copied();
+        self.library.set_sort(column, direction);
+        self.config.sort_key = column;
+        self.config.sort_direction = direction;
+        self.app_state.sort_save_due = Some(Instant::now() + SORT_SAVE_DELAY);
+        if let Some(pos) =
+            selected.and_then(|s| self.visible_indices().iter().position(|ix| *ix == s))
+        {
+            self.app_state.selected_file_ix = pos;
+        }
+        self.set_status_message(format!("Sorted by {} ({})", column.key(), direction.key()));
+    }
+
+    /// Save the sort for the next launch.
+    fn save_sort(&mut self) {
+        self.app_state.sort_save_due = None;
+        if let Err(e) = config::save_sort(self.config.sort_key, self.config.sort_direction) {
+            self.set_status_message(format!("Couldn't save the sort: {e}"));
+        }
+    }
+
+    /// Show the tags and location of the selected song.
+    fn show_song_info(&mut self) {
+        let Some(&ix) = self.visible_indices().get(self.selected_file_ix()) else {
+            return;
+        };
+        let song = &self.library.files[ix];
+        let mut lines = vec![
+            format!("Title: {}", song.title().unwrap_or("Unknown")),
+            format!("Artist: {}", song.artist().unwrap_or("Unknown")),
+            format!("Album: {}", song.album().unwrap_or("Unknown")),
+            format!("Path: {}", song.file_path().display()),
+        ];
+        if let Some(stats) = self.play_stats.get(song.file_path()) {
+            lines.push(format!(
+                "Plays: {}, skips: {} (skip ratio {:.2})",
+                stats.play_count,
+                stats.skip_count,
+                stats.skip_ratio()
+            ));
+        }
+        if let Some(link) = song.link_path() {
+            lines.push(format!(
+                "Symlink: {} \u{2192} {}",
+                link.display(),
+                song.file_path().display()
+            ));
+        }
+        self.show_info("Song Info", lines);
+    }
+
+    /// Show every genre, artist, or album in the library in the info popup.
+    fn show_tag_list(&mut self, list: &str) {
+        let (title, names) = match list {
+            "genres" => ("Genres", self.library.genres()),
+            "artists" => ("Artists", self.library.artists()),
+            _ => ("Albums", self.library.albums()),
+        };
+        let lines = names.iter().cloned().collect();
+        self.show_info(title, lines);
+    }
+
+    fn show_quality_report(&mut self) {
+        let report = self.library.quality_report(&mut self.cover_art);
+        self.app_state.quality_report = Some(report);
+        self.app_state.ui_mode = AppUiMode::QualityReport;
+    }
+
+    /// Find songs that are in the library more than once and show them in the duplicates view.
+    /// With a profile name, songs are matched against that profile's library. Otherwise they're
+    /// matched across the current library's root directories.
+    fn show_duplicates(&mut self, profile: Option<&str>) -> Result<()> {
+        let size = |song: &SongInfo| {
+            let path = song.file_path.clone();
+            let len = std::fs::metadata(&path).map_or(0, |m| m.len());
+            (path, len)
+        };
+        let duplicates = match profile {
+            Some(name) => {
+                let profile = self
+                    .config
+                    .profiles
+                    .get(name)
+                    .ok_or_else(|| eyre!("Unknown profile: {name}"))?;
+                let other = Library::from_roots(&profile.root_dirs)
+                    .with_scan_options(self.config.scan_options())
+                    .with_scan()?;
+                self.library
+                    .cross_library_duplicates(&other)
+                    .into_iter()
+                    .map(|(a, b)| (size(&self.library.files[a]), size(&other.files[b])))
+                    .collect()
+            }
+            None => self
+                .library
+                .cross_root_duplicates()
+                .into_iter()
+                .map(|(a, b)| (size(&self.library.files[a]), size(&self.library.files[b])))
+                .collect(),
+        };
+        self.app_state.duplicates = duplicates;
+        self.app_state.selected_duplicate_ix = 0;
+        self.app_state.ui_mode = AppUiMode::Duplicates;
+        Ok(())
+    }
+
+    fn handle_column_config_key(&mut self, key: KeyEvent) {
+        let selected = self.app_state.selected_column_ix;
+        if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+            self.app_state.ui_mode = AppUiMode::FileList;
+        } else if key.code == KeyCode::Down {
+            self.app_state.selected_column_ix = (selected + 1).min(Column::ALL.len() - 1);
+        } else if key.code == KeyCode::Up {
+            self.app_state.selected_column_ix = selected.saturating_sub(1);
+        } else if matches!(key.code, KeyCode::Enter | KeyCode::Char(' ')) {
+            self.config.columns.toggle(Column::ALL[selected]);
+        } else if matches!(key.code, KeyCode::Left | KeyCode::Right) {
+            self.config
+                .column_widths
+                .adjust(Column::ALL[selected], key.code == KeyCode::Right);
+        }
+    }
+
+    fn handle_info_popup_key(&mut self, key: KeyEvent) {
+        if matches!(key.code, KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q')) {
+            self.app_state.ui_mode = AppUiMode::FileList;
+        } else if key.code == KeyCode::Down {
+            self.app_state.info_scroll =
+                (self.app_state.info_scroll + 1).min(self.app_state.info.1.len().saturating_sub(1));
+        } else if key.code == KeyCode::Up {
+            self.app_state.info_scroll = self.app_state.info_scroll.saturating_sub(1);
+        }
+    }
+
+    fn handle_cover_path_key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Enter {
+            self.app_state.ui_mode = AppUiMode::FileList;
+            let image_path = std::mem::take(&mut self.app_state.cover_path_input);
+            self.embed_selected_cover(Path::new(image_path.trim()));
+        } else if key.code == KeyCode::Esc {
+            self.app_state.ui_mode = AppUiMode::FileList;
+            self.app_state.cover_path_input.clear();
+        } else if key.code == KeyCode::Backspace {
+            self.app_state.cover_path_input.pop();
+        } else if let KeyCode::Char(c) = key.code {
+            self.app_state.cover_path_input.push(c);
+        }
+    }
+
+    /// Pick a random song to play next, avoiding the current song and anything in the recent
+    /// shuffle history where possible, and record the pick in the history.
+    fn next_shuffle_ix(&mut self) -> usize {
+        let all = (0..self.library().files().len()).collect::<Vec<_>>();
+        self.shuffle_pick(&all)
+            .unwrap_or(self.app_state.playing_file_ix)
+    }
+
+    /// Pick one of `candidates` like [`Self::next_shuffle_ix`] does. Returns `None` if there are
+    /// no candidates.
+    fn shuffle_pick(&mut self, candidates: &[usize]) -> Option<usize> {
+        let len = self.library().files().len();
+        let cur_ix = self.app_state.playing_file_ix;
+        let history = &mut self.app_state.shuffle_history;
+        let mut rng = thread_rng();
+        let candidates = candidates.iter().copied();
+
+        let next_ix = candidates
+            .clone()
+            .filter(|ix| *ix != cur_ix && !history.contains(ix))
+            .choose(&mut rng)
+            .or_else(|| {
+                candidates
+                    .clone()
+                    .filter(|ix| *ix != cur_ix)
+                    .choose(&mut rng)
+            })
+            .or_else(|| candidates.clone().next())?;
+
+        let capacity = (len / 2).min(self.config.shuffle_avoid_recent);
+        history.push_back(next_ix);
+        while history.len() > capacity {
+            history.pop_front();
+        }
+        Some(next_ix)
+    }
+
+    /// Start playing a random song, picked from the search results while searching and from
+    /// the whole library otherwise. In shuffle mode the pick avoids recently shuffled songs.
+    fn play_random(&mut self) -> Result<()> {
+        let candidates = if self.search_query().is_some() {
+            self.visible_indices()
+        } else {
+            (0..self.library().files().len()).collect()
+        };
+        let pick = if self.app_state.playback_mode == PlaybackMode::Shuffle {
+            self.shuffle_pick(&candidates)
+        } else {
+            candidates.iter().copied().choose(&mut thread_rng())
+        };
+        let Some(ix) = pick else {
+            self.set_status_message("Nothing to play");
+            return Ok(());
+        };
+        self.app_state.playing_file_ix = ix;
+        if let Some(pos) = self.visible_indices().iter().position(|v| *v == ix) {
+            self.app_state.selected_file_ix = pos;
+        }
+        self.play_at_ix()
+    }
+
+    /// Run a command entered in the command prompt, without the leading `:`.
+    fn run_command(&mut self, command: &str) {
+        let mut args = command.split_whitespace();
+        // Everything after the command name, for arguments that may contain spaces
+        let rest = command
+            .trim()
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest.trim());
+        match args.next() {
+            Some("alarm") => self.set_alarm(args.next()),
+            Some("most-played") => self.set_view(LibraryView::MostPlayed),
+            Some("favourites") => self.set_view(LibraryView::Favourites),
+            Some(list @ ("genres" | "artists" | "albums")) => self.show_tag_list(list),
+            Some("unplayed") => self.set_view(LibraryView::Unplayed),
+            Some("discovery") => {
+                let order = self.library.sort_by_discovery_date(&self.play_stats);
+                self.set_view(LibraryView::DiscoveryDate(order));
+            }
+            Some("recent") => match args.next().map(str::parse::<u32>) {
+                Some(Ok(days)) => self.set_view(LibraryView::RecentlyAdded(days)),
+                Some(Err(_)) => self.set_status_message("Usage: :recent [days]"),
+                None => self.set_view(LibraryView::RecentlyAdded(DEFAULT_RECENT_DAYS)),
+            },
+            Some("compute-album-gain") => self.compute_selected_album_gain(),
+            Some("upgrade-tags") => self.upgrade_id3v1_tags(),
+            Some("missing-covers") => {
+                let missing = self.library.find_missing_covers(&mut self.cover_art);
+                self.set_view(LibraryView::MissingCovers(missing));
+            }
+            Some("missing-tags") => self.set_view(LibraryView::MissingTags),
+            Some("skip-ratio") => match args.next().and_then(parse_ratio_filter) {
+                Some((side, threshold)) => self.set_view(LibraryView::SkipRatio(side, threshold)),
+                None => self.set_status_message("Usage: :skip-ratio <RATIO | >RATIO"),
+            },
+            Some("quality-report") => self.show_quality_report(),
+            Some("rename-preview") if !rest.is_empty() => self.preview_rename(rest),
+            Some("rename-preview") => self.set_status_message("Usage: :rename-preview TEMPLATE"),
+            Some("rename-confirm") => self.confirm_rename(),
+            Some(command @ ("search-save" | "search-load" | "searches")) => {
+                self.run_search_command(command, args.next());
+            }
+            Some("profile") => match args.next() {
+                Some(name) => match self.switch_profile(name) {
+                    Ok(()) => self.set_status_message(format!("Switched to profile {name}")),
+                    Err(e) => self.set_status_message(format!("Couldn't switch profile: {e}")),
+                },
+                None => self.set_status_message("Usage: :profile NAME"),
+            },
+            Some("profile-save") => match args.next() {
+                Some(name) => match self.save_profile(name) {
+                    Ok(()) => self.set_status_message(format!("Saved profile {name}")),
+                    Err(e) => self.set_status_message(format!("Couldn't save profile: {e}")),
+                },
+                None => self.set_status_message("Usage: :profile-save NAME"),
+            },
+            Some("duplicates") => {
+                if let Err(e) = self.show_duplicates(args.next()) {
+                    self.set_status_message(format!("Couldn't find duplicates: {e}"));
+                }
+            }
+            Some("sort") => {
+                let column = args.next().map(Column::from_key);
+                let direction = args.next().map_or(Some(SortDirection::Ascending), |d| {
+                    SortDirection::from_key(d)
+                });
+                match (column, direction) {
+                    (Some(Some(column)), Some(direction)) => self.sort_library(column, direction),
+                    _ => self.set_status_message("Usage: :sort COLUMN [asc|desc]"),
+                }
+            }
+            Some("info") => self.show_song_info(),
+            Some("stats") => self.show_listening_stats(),
+            Some("random") => {
+                if let Err(e) = self.play_random() {
+                    self.set_status_message(format!("Couldn't play: {e}"));
+                }
+            }
+            Some("dedup-queue") if self.queue.is_empty() => {
+                self.set_status_message("Queue is empty");
+            }
+            Some("play-all") => {
+                if let Err(e) = self.batch_play(&self.visible_indices()) {
+                    self.set_status_message(format!("Couldn't play: {e}"));
+                }
+            }
+            Some("clear-queue") => {
+                self.save_queue_state();
+                self.queue.clear();
+                self.set_status_message("Queue cleared, Ctrl+Z to undo");
+            }
+            Some("dedup-queue") => {
+                self.save_queue_state();
+                let removed = self.queue.deduplicate();
+                self.set_status_message(format!("Removed {removed} duplicates from the queue"));
+            }
+            Some("export-m3u") => self.export_playlist(rest),
+            Some("add") => self.add_file(rest),
+            Some("organize") => self.organize_library(args),
+            Some(other) => self.set_status_message(format!("Unknown command: {other}")),
+            None => {}
+        }
+    }
+
+    /// Show where each song would be moved to by renaming with `template`, and remember the
+    /// template for `:rename-confirm`.
+    fn preview_rename(&mut self, template: &str) {
+        let plan = match self.library.rename_plan(template) {
+            Ok(plan) => plan,
+            Err(e) => {
+                self.set_status_message(format!("Invalid template: {e}"));
+                return;
+            }
+        };
+        let lines = plan
+            .iter()
+            .map(|(ix, target)| {
+                let source = &self.library.files()[*ix].file_path;
+                let root_dir = self.library.root_for(source);
+                format!(
+                    "{} -> {}",
+                    source.strip_prefix(root_dir).unwrap_or(source).display(),
+                    target.strip_prefix(root_dir).unwrap_or(target).display()
+                )
+            })
+            .collect::<Vec<_>>();
+        let title = format!("Rename {} files (:rename-confirm to apply)", lines.len());
+        self.app_state.pending_rename = Some(template.to_string());
+        self.show_info(title, lines);
+    }
+
+    /// Export the library to the M3U playlist at `path`, with songs relative to the playlist's
+    /// directory if [`Config::m3u_use_relative_paths`] is set.
+    fn export_playlist(&mut self, path: &str) {
+        if path.is_empty() {
+            self.set_status_message("Usage: :export-m3u PATH");
+            return;
+        }
+        let path = Path::new(path);
+        let base_path = self.config.m3u_use_relative_paths.then(|| {
+            path.parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        });
+        match self.library.export_m3u_extended(path, base_path) {
+            Ok(()) => self.set_status_message(format!(
+                "Exported {} songs to {}",
+                self.library.files().len(),
+                path.display()
+            )),
+            Err(e) => self.set_status_message(format!("Export failed: {e}")),
+        }
+    }
+
+    /// Organise the library from `:organize`'s arguments, an optional target directory and
+    /// `--dry-run`.
+    fn organize_library<'a>(&mut self, args: impl Iterator<Item = &'a str>) {
+        let mut dry_run = false;
+        let mut target_root = None;
+        for arg in args {
+            if arg == "--dry-run" {
+                dry_run = true;
+            } else {
+                target_root = Some(PathBuf::from(arg));
+            }
+        }
+        let target_root = target_root.unwrap_or_else(|| self.library.root_dirs[0].clone());
+        match self.library.organize_by_tags(&target_root, dry_run) {
+            Ok(moves) if dry_run => {
+                let lines = moves
+                    .iter()
+                    .map(|(from, to)| format!("{} -> {}", from.display(), to.display()))
+                    .collect::<Vec<_>>();
+                self.show_info(format!("Organise {} files (dry run)", lines.len()), lines);
+            }
+            Ok(moves) => {
+                self.follow_moved_songs(&moves);
+                self.set_status_message(format!("Moved {} files", moves.len()));
+            }
+            Err(e) => self.set_status_message(format!("Organise failed: {e}")),
+        }
+    }
+
+    fn confirm_rename(&mut self) {
+        let Some(template) = self.app_state.pending_rename.take() else {
+            self.set_status_message("Run :rename-preview TEMPLATE first");
+            return;
+        };
+        match self.library.rename_by_template(&template) {
+            Ok(result) => {
+                self.follow_moved_songs(&result.moved);
+                for (path, e) in &result.failed {
+                    log::warn!("Couldn't move {}: {e}", path.display());
+                }
+                let renamed = result.moved.len();
+                self.set_status_message(match result.failed.first() {
+                    None => format!("Renamed {renamed} files"),
+                    Some((path, e)) => format!(
+                        "Renamed {renamed} files, {} failed (first {}: {e})",
+                        result.failed.len(),
+                        path.display()
+                    ),
+                });
+            }
+            Err(e) => self.set_status_message(format!("Rename failed: {e}")),
+        }
+    }
+
+    /// Carry the play stats, favourites, and cached cover of each song in `moves` over to its new
+    /// path, after its file was moved from the first path to the second.
+    fn follow_moved_songs(&mut self, moves: &[(PathBuf, PathBuf)]) {
+        if moves.is_empty() {
+            return;
+        }
+        let mut favourites_moved = false;
+        for (from, to) in moves {
+            self.play_stats.move_song(from, to);
+            self.cover_art.move_song(from, to);
+            if self.app_state.favourites.remove(from) {
+                self.app_state.favourites.insert(to.clone());
+                favourites_moved = true;
+            }
+            if let Some(song) = &mut self.app_state.active_song {
+                if song.file_path == *from {
+                    song.file_path.clone_from(to);
+                }
+            }
+        }
+        if let Err(e) = self.play_stats.save() {
+            log::warn!("Failed to save play stats: {e}");
+        }
+        if favourites_moved {
+            if let Err(e) = config::save_favourites(&self.app_state.favourites) {
+                log::warn!("Failed to save favourites: {e}");
+            }
+        }
+    }
+
+    /// Embed the image at `image_path` as the cover of the selected song, and take the song out of
+    /// the missing covers view.
+    fn embed_selected_cover(&mut self, image_path: &Path) {
+        let visible = self.visible_indices();
+        let Some(&ix) = visible.get(self.app_state.selected_file_ix) else {
+            return;
+        };
+        let song_path = self.library.files()[ix].file_path.clone();
+        match cover_art::embed_cover(&song_path, image_path) {
+            Ok(()) => {
+                self.cover_art.forget(&song_path);
+                if let LibraryView::MissingCovers(indices) = &mut self.app_state.view {
+                    indices.retain(|i| *i != ix);
+                    self.app_state.selected_file_ix = self
+                        .app_state
+                        .selected_file_ix
+                        .min(indices.len().saturating_sub(1));
+                }
+                self.set_status_message(format!("Embedded cover from {}", image_path.display()));
+            }
+            Err(e) => self.set_status_message(format!("Couldn't embed cover: {e}")),
+        }
+    }
+
+    /// Compute the album replay gain for the album of the selected song and write it to every track of
+    /// that album. Decoding a whole album takes a while, so this runs on a background thread.
+    fn compute_selected_album_gain(&mut self) {
+        let visible = self.visible_indices();
+        let Some(selected) = visible
+            .get(self.app_state.selected_file_ix)
+            .map(|ix| &self.library.files()[*ix])
+        else {
+            return;
+        };
+        let Some(album) = selected.album.clone() else {
+            self.set_status_message("Selected song has no album tag");
+            return;
+        };
+        let album_artist = selected.album_artist.as_deref().or(selected.artist());
+        // In album order, as though the album were played through
+        let tracks = self
+            .library
+            .files_sorted_by(|s| (s.disc.0, s.track.0))
+            .into_iter()
+            .filter(|s| {
+                s.album.as_ref() == Some(&album)
+                    && s.album_artist.as_deref().or(s.artist()) == album_artist
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        self.set_status_message(format!("Computing album gain for {album}..."));
+        let tx = self.task_messages.0.clone();
+        thread::spawn(move || {
+            let result = AudioManager::compute_album_gain(&tracks.iter().collect::<Vec<_>>())
+                .and_then(|album_gain| {
+                    for track in &tracks {
+                        replaygain::write_album_gain_tags(&track.file_path, album_gain)?;
+                    }
+                    Ok(album_gain)
+                });
+            let msg = match result {
+                Ok(album_gain) => format!("{album}: album gain {:+.2} dB", album_gain.gain),
+                Err(e) => format!("Failed to compute album gain for {album}: {e}"),
+            };
+            let _ = tx.send(msg);
+        });
+    }
+
+    /// Copy the `ID3v1` tags of the library's MP3s into `ID3v2` tags in the background, reporting
+    /// progress in the status bar.
+    fn upgrade_id3v1_tags(&mut self) {
+        let paths = self.library.id3v1_upgrade_candidates();
+        let tx = self.task_messages.0.clone();
+        thread::spawn(move || {
+            let mut upgraded = 0;
+            for (i, path) in paths.iter().enumerate() {
+                let _ = tx.send(format!("Upgrading tags... {}/{}", i + 1, paths.len()));
+                match id3v1::upgrade(path) {
+                    Ok(true) => upgraded += 1,
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Couldn't upgrade tags of {}: {e}", path.display()),
+                }
+            }
+            let _ = tx.send(format!(
+                "Upgraded {upgraded} files to ID3v2, rescan to see the changes"
+            ));
+        });
+    }
+
+    /// Start playback once the wall clock passes the alarm time, then clear the alarm.
+    fn check_alarm(&mut self) -> Result<()> {
+        let Some(alarm_time) = self.app_state.alarm_time else {
+            return Ok(());
+        };
+        let now = alarm::local_seconds_of_day();
+        let last = std::mem::replace(&mut self.app_state.alarm_last_checked, now);
+        if !alarm_time.passed_between(last, now) {
+            return Ok(());
+        }
+
+        self.app_state.alarm_time = None;
+        if self.app_state.active_song.is_some() {
+            // Resume from wherever playback was paused
+            self.am.play();
+        } else if !self.library().files().is_empty() {
+            self.play_at_ix()?;
+        }
+        Ok(())
+    }
+
+    fn volume_up(&mut self) {
+        self.am.set_volume(self.am.get_volume() + 0.01);
+    }
+
+    fn volume_down(&mut self) {
+        self.am.set_volume(self.am.get_volume() - 0.01);
+    }
+
+    pub fn volume(&self) -> Volume {
+        self.am.get_volume()
+    }
+
+    fn play_at_ix(&mut self) -> Result<()> {
+        let path = PathBuf::from(&self.library().files()[self.app_state.playing_file_ix].file_path);
+        self.am.set_active_source(&path)?;
+        self.app_state.active_song =
+            Some(self.library().files()[self.app_state.playing_file_ix].clone());
+        self.am.play();
+        self.waveform = None;
+        let tx = self.waveforms.0.clone();
+        let waveform_path = path.clone();
+        thread::spawn(move || {
+            match AudioManager::compute_waveform(&waveform_path, WAVEFORM_RESOLUTION) {
+                Ok(waveform) => {
+                    let _ = tx.send((waveform_path, waveform));
+                }
+                Err(e) => log::warn!("Couldn't analyse {}: {e}", waveform_path.display()),
+            }
+        });
+        // Extract the cover now rather than on the first frame that draws it
+        if let Some(song) = &self.app_state.active_song {
+            let _ = self.cover_art.get_or_extract(song);
+        }
+        self.play_stats.record_play(&path);
+        if let Err(e) = self.play_stats.save() {
+            self.set_status_message(format!("Failed to save play stats: {e}"));
+        }
+        Ok(())
+    }
+
+    /// Time spent listening this session, not counting while paused.
+    pub fn elapsed_session_time(&self) -> Duration {
+        self.app_state.total_listening_time
+    }
+
+    /// Save the listening time counted since the last save. Plays are saved as they happen, so
+    /// there's nothing to save if nothing was listened to.
+    pub fn save_listening_time(&mut self) {
+        if self.app_state.unsaved_listening_time.is_zero() {
+            return;
+        }
+        match self.play_stats.save() {
+            Ok(()) => self.app_state.unsaved_listening_time = Duration::ZERO,
+            Err(e) => log::warn!("Failed to save play stats: {e}"),
+        }
+    }
+
+    /// Stop the main loop, saving anything not yet saved.
+    fn quit(&mut self) {
+        self.save_listening_time();
+        if self.app_state.sort_save_due.is_some() {
+            self.save_sort();
+        }
+        self.alive = false;
+    }
+
+    fn show_listening_stats(&mut self) {
+        let mut lines = vec![
+            format!(
+                "Listened this session: {}",
+                format_long_duration(self.elapsed_session_time())
+            ),
+            format!(
+                "Session length: {}",
+                format_long_duration(self.session_start.elapsed())
+            ),
+            format!(
+                "Listened in total: {}",
+                format_long_duration(self.play_stats.listening_time())
+            ),
+            String::new(),
+            String::from("Tracks by artist:"),
+        ];
+        lines.extend(
+            self.library
+                .total_tracks_by_artist()
+                .into_iter()
+                .map(|(artist, count)| format!("  {artist} ({count} tracks)")),
+        );
+        self.show_info("Listening Stats", lines);
+    }
+
+    pub fn active_song(&self) -> Option<&SongInfo> {
+        self.app_state.active_song.as_ref()
+    }
+
+    /// Library index of the playing song, or `None` if nothing has played yet. Songs are popped
+    /// off the queue as they start, so this is also the index of the queued song playing.
+    pub fn active_song_index(&self) -> Option<usize> {
+        self.app_state
+            .active_song
+            .is_some()
+            .then_some(self.app_state.playing_file_ix)
+    }
+
+    /// Amplitude of the playing song over its length, scaled so the loudest point is 1.0.
+    pub fn waveform(&self) -> Option<&[f32]> {
+        self.waveform.as_deref()
+    }
+
+    /// Cover image for the song that's playing, if it has one.
+    pub fn active_cover_art(&mut self) -> Option<PathBuf> {
+        let song = self.app_state.active_song.as_ref()?;
+        self.cover_art.get_or_extract(song)
+    }
+
+    /// Art to draw beside the song that's playing. Only songs without a cover image get any for
+    /// now, which is the placeholder.
+    pub fn active_artwork(&mut self) -> Option<&[Line<'static>]> {
+        if self.active_cover_art().is_some() {
+            return None;
+        }
+        let song = self.app_state.active_song.as_ref()?;
+        Some(
+            self.artwork
+                .get_or_render(song, |_| CoverArtPlaceholder::render()),
+        )
+    }
+
+    /// The terminal's new width and height, if it has been resized since this was last asked.
+    pub fn take_resize(&mut self) -> Option<(u16, u16)> {
+        self.pending_resize.take()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.am.is_paused()
+    }
+
+    pub fn is_buffering(&self) -> bool {
+        self.am.is_buffering()
+    }
+}
+
+/// What happened to the audio output during an [`AudioManager::update`].
+#[derive(PartialEq)]
+pub enum OutputStatus {
+    Playing,
+    /// The output went away and hasn't been reopened yet.
+    Reconnecting,
+    /// The output went away and was reopened.
+    Reconnected,
+    /// The output couldn't be reopened after [`RECONNECT_ATTEMPTS`] tries.
+    Lost,
+}
+
+/// A source that counts the samples taken from it, so the playback position can be read from
+/// what the output has actually consumed.
+///
+/// Seeking moves the count to match the new position.
+pub struct CountingSource<S> {
+    inner: S,
+    samples: Arc<AtomicU64>,
+}
+
+impl<S: Source> CountingSource<S>
+where
+    S::Item: Sample,
+{
+    /// Wrap `inner`, counting into `samples` from whatever it already holds.
+    pub fn new(inner: S, samples: Arc<AtomicU64>) -> Self {
+        Self { inner, samples }
+    }
+}
+
+impl<S: Source> Iterator for CountingSource<S>
+where
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.inner.next()?;
+        self.samples.fetch_add(1, Ordering::Relaxed);
+        Some(sample)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S: Source> Source for CountingSource<S>
+where
+    S::Item: Sample,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.inner.try_seek(pos)?;
+        let samples_per_sec = u64::from(self.sample_rate()) * u64::from(self.channels());
+        self.samples
+            .store(samples_at(pos, samples_per_sec), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Number of samples played by the time a source with `samples_per_sec` reaches `pos`.
+fn samples_at(pos: Duration, samples_per_sec: u64) -> u64 {
+    let samples = pos.as_nanos() * u128::from(samples_per_sec) / 1_000_000_000;
+    u64::try_from(samples).unwrap_or(u64::MAX)
+}
+
+/// The audio output [`PlayerApp`] plays through, so tests can run with an in-memory stand-in
+/// where there's no audio hardware.
+pub trait AudioManagerTrait {
+    /// Start decoding the file at `path` in place of whatever was playing.
+    fn set_active_source(&mut self, path: &Path) -> Result<()>;
+    /// Jump to the end of the active source.
+    fn skip(&mut self);
+    /// Move to `position` in the active source, if it can seek.
+    fn seek_to(&mut self, position: Duration);
+    fn seek_forward(&mut self) {
+        self.seek_to(self.position() + Duration::from_secs(5));
+    }
+    fn seek_backward(&mut self) {
+        self.seek_to(self.position().saturating_sub(Duration::from_secs(1)));
+    }
+    fn play(&mut self);
+    fn pause(&mut self);
+    /// Reopen the audio output if it has gone away, e.g. because the headphones it was playing
+    /// through were unplugged.
+    fn update(&mut self) -> OutputStatus;
+    fn get_volume(&self) -> Volume;
+    fn set_volume(&mut self, volume: Volume);
+    fn is_paused(&self) -> bool;
+    /// How far into the active source the output has got, from the samples it has consumed.
+    fn position(&self) -> Duration;
+    /// Whether the active source is meant to be playing but none of it has been heard yet.
+    fn is_buffering(&self) -> bool;
+    /// How much of the active source is left to play, if its length is known. Never less than
+    /// zero, even if the output has somehow got past the end.
+    fn remaining_duration(&self) -> Option<Duration>;
+    /// Where the active source should be treated as finished, if earlier than its real end.
+    fn effective_end(&self) -> Option<Duration>;
+    /// Whether the output has played every sample of the active source, however long the source
+    /// claimed to be.
+    fn is_finished(&self) -> bool;
+
+    fn toggle_playback(&mut self) {
+        if self.is_paused() {
+            self.play();
+        } else {
+            self.pause();
+        }
+    }
+}
+
+pub struct AudioManager {
+    sink: Sink,
+    _stream: OutputStream,
+    _stream_handle: OutputStreamHandle,
+    /// File the active source was decoded from, for reopening it on a new output.
+    active_path: Option<PathBuf>,
+    failed_reconnects: u32,
+    /// When the last attempt to reopen the output was made.
+    last_reconnect_attempt: Option<Instant>,
+    /// Samples played when the output was last reopened, to spot a source that stops short of
+    /// its length rather than an output that keeps going away.
+    reconnected_at: Option<u64>,
+    /// The samples played count last seen, and when it was last seen to change.
+    last_progress: (u64, Instant),
+    /// The active source stopped giving samples before its end and won't give any more.
+    exhausted: bool,
+    /// A new source has been set but none of it has reached the output yet.
+    buffering: bool,
+    /// Samples of the active source played so far, counted by its [`CountingSource`].
+    samples_played: Arc<AtomicU64>,
+    /// Sample rate times channel count of the active source.
+    samples_per_sec: u64,
+    active_source_duration: Option<Duration>,
+    /// Where the active source should be treated as finished, if earlier than its real end.
+    effective_end: Option<Duration>,
+    skip_silence: bool,
+    /// Silence boundaries of the active source, while they're still being found.
+    silence_scan: Option<Receiver<(Duration, Duration)>>,
+}
+
+impl AudioManager {
+    pub fn new() -> Result<Self> {
+        let (stream, stream_handle) = Self::open_default_output()?;
+        let sink = Sink::try_new(&stream_handle)?;
+        sink.pause();
+
+        Ok(Self {
+            sink,
+            _stream: stream,
+            _stream_handle: stream_handle,
+            active_path: None,
+            failed_reconnects: 0,
+            last_reconnect_attempt: None,
+            reconnected_at: None,
+            last_progress: (0, Instant::now()),
+            exhausted: false,
+            buffering: false,
+            samples_played: Arc::default(),
+            samples_per_sec: 0,
+            active_source_duration: None,
+            effective_end: None,
+            skip_silence: false,
+            silence_scan: None,
+        })
+    }
+
+    /// Open the system's default audio output, explaining the likely cause if there's no output
+    /// device at all.
+    fn open_default_output() -> Result<(OutputStream, OutputStreamHandle)> {
+        use rodio::cpal::traits::HostTrait;
+
+        OutputStream::try_default().map_err(|e| {
+            let has_devices = rodio::cpal::default_host()
+                .output_devices()
+                .is_ok_and(|mut devices| devices.next().is_some());
+            if has_devices {
+                e.into()
+            } else {
+                eyre!("No audio output device found. Is a sound server (PulseAudio, PipeWire) running?")
+            }
+        })
+    }
+
+    /// Skip over leading and trailing silence in every source that's played.
+    pub fn with_skip_silence(mut self, skip_silence: bool) -> Self {
+        self.skip_silence = skip_silence;
+        self
+    }
+
+    /// Find where the audio in `path` starts and stops being silent, looking only at the first
+    /// and last [`SILENCE_SCAN_WINDOW`] of the file.
+    ///
+    /// Returns the offsets of the first and last non-silent samples.
+    pub fn detect_silence_boundaries(path: &Path) -> Result<(Duration, Duration)> {
+        let open = || -> Result<_> { Ok(Decoder::new(BufReader::new(File::open(path)?))?) };
+        let source = open()?;
+        let samples_per_sec = u64::from(source.sample_rate()) * u64::from(source.channels());
+        #[allow(clippy::cast_precision_loss)]
+        let sample_offset = |n: u64| Duration::from_secs_f64(n as f64 / samples_per_sec as f64);
+        let window = SILENCE_SCAN_WINDOW.as_secs() * samples_per_sec;
+
+        let total_duration = source.total_duration();
+        let first_sound = source
+            .convert_samples::<f32>()
+            .take(usize::try_from(window)?)
+            .position(|s| s.abs() > SILENCE_THRESHOLD);
+        let start = first_sound.map_or(SILENCE_SCAN_WINDOW, |n| sample_offset(n as u64));
+
+        // Seek close to the end if we can, otherwise decode the whole file to get there
+        let mut source = open()?;
+        let mut base = Duration::ZERO;
+        if let Some(total) = total_duration {
+            let seek_to = total.saturating_sub(SILENCE_SCAN_WINDOW);
+            if source.try_seek(seek_to).is_ok() {
+                base = seek_to;
+            }
+        }
+        let mut last_sound = None;
+        let mut samples_seen = 0u64;
+        for s in source.convert_samples::<f32>() {
+            samples_seen += 1;
+            if s.abs() > SILENCE_THRESHOLD {
+                last_sound = Some(samples_seen);
+            }
+        }
+        let end = base + sample_offset(last_sound.unwrap_or(samples_seen));
+
+        Ok((start.min(end), end))
+    }
+
+    /// Decode the file at `path` and reduce it to `buckets` RMS amplitudes spread evenly over its
+    /// length, scaled so the loudest is 1.0.
+    pub fn compute_waveform(path: &Path, buckets: usize) -> Result<Vec<f32>> {
+        let source = Decoder::new(BufReader::new(File::open(path)?))?;
+        // Sum of squares for every 10 ms of audio
+        let window_len =
+            (usize::from(source.channels()) * source.sample_rate() as usize / 100).max(1);
+        let mut windows = Vec::new();
+        let mut sum_sq = 0.0f32;
+        for (i, s) in source.convert_samples::<f32>().enumerate() {
+            sum_sq += s * s;
+            if (i + 1) % window_len == 0 {
+                windows.push(sum_sq);
+                sum_sq = 0.0;
+            }
+        }
+        if windows.is_empty() {
+            return Ok(vec![0.0; buckets]);
+        }
+
+        let mut waveform = (0..buckets)
+            .map(|b| {
+                let start = b * windows.len() / buckets;
+                let end = ((b + 1) * windows.len() / buckets).max(start + 1);
+                let window = &windows[start..end.min(windows.len())];
+                #[allow(clippy::cast_precision_loss)]
+                let samples = (window.len() * window_len) as f32;
+                (window.iter().sum::<f32>() / samples).sqrt()
+            })
+            .collect::<Vec<_>>();
+        let max = waveform.iter().copied().fold(0.0, f32::max);
+        if max > 0.0 {
+            for amplitude in &mut waveform {
+                *amplitude /= max;
+            }
+        }
+        Ok(waveform)
+    }
+
+    /// Measure the combined loudness of `tracks` as one continuous stream and derive the album
+    /// replay gain from it.
+    pub fn compute_album_gain(tracks: &[&SongInfo]) -> Result<ReplayGain> {
+        let mut meter = LoudnessMeter::default();
+        for track in tracks {
+            let source = Decoder::new(BufReader::new(File::open(&track.file_path)?))?;
+            let (channels, sample_rate) = (source.channels(), source.sample_rate());
+            meter.add_track(source.convert_samples::<f32>(), channels, sample_rate);
+        }
+        meter.replay_gain().ok_or_else(|| eyre!("album is silent"))
+    }
+
+    /// Open the default output again and carry on playing the active source from where it was.
+    #[allow(clippy::used_underscore_binding)]
+    fn reconnect(&mut self) -> Result<()> {
+        let path = self
+            .active_path
+            .as_ref()
+            .ok_or_else(|| eyre!("Nothing is playing"))?;
+        let (stream, stream_handle) = Self::open_default_output()?;
+        let sink = Sink::try_new(&stream_handle)?;
+        sink.set_volume(self.sink.volume());
+        let source = Decoder::new(BufReader::new(File::open(path)?))?;
+        let position = self.position();
+        let samples_played = Arc::default();
+        sink.append(CountingSource::new(source, Arc::clone(&samples_played)));
+        // Starting again from the beginning would be worse than giving up
+        sink.try_seek(position)
+            .map_err(|e| eyre!("Couldn't return to where playback was: {e}"))?;
+        self.samples_played = samples_played;
+        self.sink = sink;
+        self._stream = stream;
+        self._stream_handle = stream_handle;
+        Ok(())
+    }
+
+    pub fn _active_source_duration(&self) -> Option<Duration> {
+        self.active_source_duration
+    }
+}
+
+impl AudioManagerTrait for AudioManager {
+    fn set_active_source(&mut self, path: &Path) -> Result<()> {
+        let source = Decoder::new(BufReader::new(File::open(path)?))?;
+        self.active_source_duration = source.total_duration();
+        self.samples_per_sec = u64::from(source.sample_rate()) * u64::from(source.channels());
+        // A fresh counter, so samples the old source still hands over don't count
+        self.samples_played = Arc::default();
+        self.sink.clear();
+        self.sink
+            .append(CountingSource::new(source, self.samples_played.clone()));
+        self.active_path = Some(path.to_path_buf());
+        self.effective_end = None;
+        self.buffering = true;
+        self.reconnected_at = None;
+        self.last_reconnect_attempt = None;
+        self.last_progress = (0, Instant::now());
+        self.exhausted = false;
+
+        // Replacing the receiver drops any scan of the previous source
+        self.silence_scan = None;
+        if self.skip_silence {
+            let (tx, rx) = mpsc::channel();
+            let scan_path = path.to_path_buf();
+            thread::spawn(move || {
+                if let Ok(boundaries) = Self::detect_silence_boundaries(&scan_path) {
+                    let _ = tx.send(boundaries);
+                }
+            });
+            self.silence_scan = Some(rx);
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self) {
+        let duration = self
+            .active_source_duration
+            .expect("Already checked if we have an active source.");
+        self.samples_played.store(
+            samples_at(duration, self.samples_per_sec),
+            Ordering::Relaxed,
+        );
+    }
+
+    // Seeks that succeed move the sample count themselves, in `CountingSource::try_seek`
+    fn seek_to(&mut self, position: Duration) {
+        let _ = self.sink.try_seek(position);
+    }
+
+    fn is_paused(&self) -> bool {
+        self.sink.is_paused()
+    }
+
+    fn effective_end(&self) -> Option<Duration> {
+        self.effective_end
+    }
+
+    fn is_finished(&self) -> bool {
+        self.active_path.is_some() && (self.sink.empty() || self.exhausted)
+    }
+
+    fn play(&mut self) {
+        self.sink.play();
+    }
+
+    fn pause(&mut self) {
+        self.sink.pause();
+    }
+
+    fn update(&mut self) -> OutputStatus {
+        if self.buffering && self.samples_played.load(Ordering::Relaxed) > 0 {
+            self.buffering = false;
+        }
+        match self.silence_scan.as_ref().map(Receiver::try_recv) {
+            Some(Ok((start, end))) => {
+                // Only skip the leading silence if it's still playing
+                if self.position() < start {
+                    let _ = self.sink.try_seek(start);
+                }
+                self.effective_end = Some(end);
+                self.silence_scan = None;
+            }
+            Some(Err(mpsc::TryRecvError::Disconnected)) => self.silence_scan = None,
+            Some(Err(mpsc::TryRecvError::Empty)) | None => {}
+        }
+        let now = Instant::now();
+        let samples = self.samples_played.load(Ordering::Relaxed);
+        if samples != self.last_progress.0 || self.sink.is_paused() {
+            self.last_progress = (samples, now);
+        }
+
+        // An output that stops taking samples while there are still some to play has gone away.
+        // A source that has run out leaves the sink empty instead, which is the track ending.
+        let stalled = self.active_path.is_some()
+            && !self.sink.empty()
+            && !self.exhausted
+            && self.remaining_duration().is_none_or(|r| !r.is_zero())
+            && now - self.last_progress.1 >= OUTPUT_STALL_TIMEOUT;
+        if !stalled {
+            return OutputStatus::Playing;
+        }
+        // Stalling again where the last reconnect picked up means the source has no more to give
+        if self.reconnected_at == Some(samples) {
+            self.exhausted = true;
+            return OutputStatus::Playing;
+        }
+        if self
+            .last_reconnect_attempt
+            .is_some_and(|t| now - t < RECONNECT_INTERVAL)
+        {
+            return OutputStatus::Reconnecting;
+        }
+        self.last_reconnect_attempt = Some(now);
+        if self.reconnect().is_ok() {
+            self.failed_reconnects = 0;
+            let samples = self.samples_played.load(Ordering::Relaxed);
+            self.reconnected_at = Some(samples);
+            self.last_progress = (samples, now);
+            return OutputStatus::Reconnected;
+        }
+        self.failed_reconnects += 1;
+        if self.failed_reconnects >= RECONNECT_ATTEMPTS {
+            OutputStatus::Lost
+        } else {
+            OutputStatus::Reconnecting
+        }
+    }
+
+    fn get_volume(&self) -> Volume {
+        Volume::new(self.sink.volume())
+    }
+
+    fn set_volume(&mut self, volume: Volume) {
+        self.sink.set_volume(volume.into());
+    }
+
+    fn position(&self) -> Duration {
+        if self.samples_per_sec == 0 {
+            return Duration::ZERO;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let secs = self.samples_played.load(Ordering::Relaxed) as f64 / self.samples_per_sec as f64;
+        Duration::from_secs_f64(secs)
+    }
+
+    fn is_buffering(&self) -> bool {
+        self.buffering && !self.sink.is_paused()
+    }
+
+    fn remaining_duration(&self) -> Option<Duration> {
+        self.active_source_duration
+            .map(|d| d.saturating_sub(self.position()))
+    }
+}
+
+/// An audio output that keeps its state in memory instead of playing anything.
+#[cfg(test)]
+pub struct MockAudioManager {
+    pub paused: bool,
+    pub volume: Volume,
+    pub position: Duration,
+    pub active_path: Option<PathBuf>,
+    pub active_source_duration: Option<Duration>,
+    /// Whether the active source has run out of audio.
+    pub finished: bool,
+}
+
+#[cfg(test)]
+impl Default for MockAudioManager {
+    /// Paused at full volume with nothing to play, like a fresh [`AudioManager`].
+    fn default() -> Self {
+        Self {
+            paused: true,
+            volume: Volume::MAX,
+            position: Duration::ZERO,
+            active_path: None,
+            active_source_duration: None,
+            finished: false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl AudioManagerTrait for MockAudioManager {
+    fn set_active_source(&mut self, path: &Path) -> Result<()> {
+        self.active_path = Some(path.to_path_buf());
+        self.position = Duration::ZERO;
+        self.finished = false;
+        Ok(())
+    }
+
+    fn skip(&mut self) {
+        if let Some(duration) = self.active_source_duration {
+            self.position = duration;
+        }
+    }
+
+    fn seek_to(&mut self, position: Duration) {
+        self.position = position;
+    }
+
+    fn play(&mut self) {
+        self.paused = false;
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    fn update(&mut self) -> OutputStatus {
+        OutputStatus::Playing
+    }
+
+    fn get_volume(&self) -> Volume {
+        self.volume
+    }
+
+    fn set_volume(&mut self, volume: Volume) {
+        self.volume = volume;
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn position(&self) -> Duration {
+        self.position
+    }
+
+    fn is_buffering(&self) -> bool {
+        false
+    }
+
+    fn remaining_duration(&self) -> Option<Duration> {
+        self.active_source_duration
+            .map(|d| d.saturating_sub(self.position))
+    }
+
+    fn effective_end(&self) -> Option<Duration> {
+        None
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Standard MP3 bitrates that [`QualityReport::bitrate_distribution`] groups songs by, in kbps.
+pub const BITRATE_BUCKETS: [u32; 4] = [128, 192, 256, 320];
+
+/// How complete the library's tags are and what it's encoded as, from
+/// [`Library::quality_report`].
+#[derive(Debug, Default, PartialEq)]
+pub struct QualityReport {
+    pub total_tracks: usize,
+    pub tracks_missing_title: usize,
+    pub tracks_missing_artist: usize,
+    pub tracks_missing_album: usize,
+    pub tracks_missing_year: usize,
+    pub tracks_missing_genre: usize,
+    pub tracks_missing_cover: usize,
+    /// Number of songs of each file extension, lowercased.
+    pub format_distribution: HashMap<String, usize>,
+    /// Number of songs whose average bitrate is closest to each of [`BITRATE_BUCKETS`]. Songs
+    /// with no known length, or whose file can't be read, aren't counted.
+    pub bitrate_distribution: HashMap<u32, usize>,
+}
+
+/// File extensions scanned for songs unless configured otherwise.
+pub const DEFAULT_SCAN_EXTENSIONS: [&str; 7] = ["mp3", "flac", "ogg", "opus", "wav", "aiff", "m4a"];
+
+/// Settings controlling how [`Library::scan`] walks the filesystem.
+#[allow(clippy::struct_excessive_bools)]
+pub struct ScanOptions {
+    /// Extensions of the files to scan, matched case-insensitively.
+    pub extensions: Vec<String>,
+    /// Follow symbolic links to files and directories. Loops are detected and skipped.
+    pub follow_symlinks: bool,
+    /// Scan directories on other filesystems than their root, like network shares mounted
+    /// inside the library. Only Unix can tell, so elsewhere every directory is scanned.
+    pub follow_mounts: bool,
+    /// Directories this many levels below the root or deeper aren't scanned.
+    pub max_scan_depth: usize,
+    /// Scan files whose names start with `.`.
+    pub scan_hidden_files: bool,
+    /// Scan directories whose names start with `.`, like `.Trash`.
+    pub scan_hidden_dirs: bool,
+    /// Files bigger than this many bytes are skipped without reading their tags.
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            extensions: DEFAULT_SCAN_EXTENSIONS.map(String::from).to_vec(),
+            follow_symlinks: true,
+            follow_mounts: true,
+            max_scan_depth: 10,
+            scan_hidden_files: false,
+            scan_hidden_dirs: false,
+            max_file_size: None,
+        }
+    }
+}
+
+/// What a [`Library::scan`] found.
+#[derive(Debug, Default)]
+pub struct ScanResult {
+    /// Music files found, whether or not they could be loaded.
+    pub total_seen: usize,
+    /// Songs in the library after the scan.
+    pub loaded: usize,
+    /// Files whose tags couldn't be read, and why.
+    pub tag_errors: Vec<(PathBuf, String)>,
+    /// Songs that were loaded but whose audio couldn't be made sense of, and why.
+    pub decode_errors: Vec<(PathBuf, String)>,
+    /// Files with a scanned extension that neither the tag reader nor the decoder supports.
+    pub unsupported: Vec<PathBuf>,
+    /// Files skipped for being bigger than [`ScanOptions::max_file_size`], with their sizes.
+    pub oversized: Vec<(PathBuf, u64)>,
+}
+
+impl ScanResult {
+    /// One line summing up the scan, like "Loaded 40 of 42 files (2 unreadable tags)".
+    pub fn summary(&self) -> String {
+        let problems = [
+            (self.tag_errors.len(), "unreadable tags"),
+            (self.decode_errors.len(), "unknown length"),
+            (self.unsupported.len(), "unsupported"),
+            (self.oversized.len(), "too large"),
+        ]
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, what)| format!("{n} {what}"))
+        .collect::<Vec<_>>();
+        let summary = format!("Loaded {} of {} files", self.loaded, self.total_seen);
+        if problems.is_empty() {
+            summary
+        } else {
+            format!("{summary} ({})", problems.join(", "))
+        }
+    }
+}
+
+pub struct Library {
+    /// Directories scanned for songs. There's always at least one.
+    root_dirs: Vec<PathBuf>,
+    /// Each of [`Self::root_dirs`] with every symlink and `..` resolved, or as it's written if it
+    /// couldn't be resolved.
+    canonical_roots: Vec<PathBuf>,
+    files: Vec<SongInfo>,
+    /// Music files found by the last scan whose tags couldn't be read, so aren't in
+    /// [`Self::files`].
+    unreadable_files: Vec<PathBuf>,
+    scan_options: ScanOptions,
+    sort: (Column, SortDirection),
+    /// Indices into [`Self::files`] in [`Self::sort`] order.
+    sorted_indices: Vec<usize>,
+    /// Index into [`Self::files`] of each song's path.
+    path_index: HashMap<PathBuf, usize>,
+    /// Combined length of [`Self::files`], kept up to date as they change.
+    total_duration: Duration,
+    /// Built the first time they're asked for after each change to [`Self::files`].
+    genres_cache: OnceCell<BTreeSet<String>>,
+    artists_cache: OnceCell<BTreeSet<String>>,
+    albums_cache: OnceCell<BTreeSet<String>>,
+    /// Index into [`Self::files`] of one song by each credited artist, and how many songs they
+    /// have, sorted by artist.
+    artist_counts_cache: OnceCell<Vec<(usize, usize)>>,
+}
+
+impl Library {
+    pub fn new(root_dir: &Path) -> Self {
+        Self::from_roots(&[root_dir.to_path_buf()])
+    }
+
+    /// A library made up of the songs under every directory in `root_dirs`. Falls back to the
+    /// current directory if `root_dirs` is empty.
+    pub fn from_roots(root_dirs: &[PathBuf]) -> Self {
+        let root_dirs = if root_dirs.is_empty() {
+            vec![PathBuf::from(".")]
+        } else {
+            root_dirs.to_vec()
+        };
+        let canonical_roots = root_dirs
+            .iter()
+            .map(|r| std::fs::canonicalize(r).unwrap_or_else(|_| r.clone()))
+            .collect();
+        Self {
+            root_dirs,
+            canonical_roots,
+            files: vec![],
+            unreadable_files: vec![],
+            scan_options: ScanOptions::default(),
+            sort: (Column::Artist, SortDirection::Ascending),
+            sorted_indices: vec![],
+            path_index: HashMap::new(),
+            total_duration: Duration::ZERO,
+            genres_cache: OnceCell::new(),
+            artists_cache: OnceCell::new(),
+            albums_cache: OnceCell::new(),
+            artist_counts_cache: OnceCell::new(),
+        }
+    }
+
+    /// The root directory that `path` is under, or the first root if it's under none of them.
+    fn root_for(&self, path: &Path) -> &Path {
+        self.root_dirs
+            .iter()
+            .find(|r| path.starts_with(r))
+            .unwrap_or(&self.root_dirs[0])
+    }
+
+    fn is_scanned_extension(&self, path: &Path) -> bool {
+        path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+            self.scan_options
+                .extensions
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(e))
+        })
+    }
+
+    pub fn with_scan_options(mut self, scan_options: ScanOptions) -> Self {
+        self.scan_options = scan_options;
+        self
+    }
+
+    pub fn with_sort(mut self, column: Column, direction: SortDirection) -> Self {
+        self.sort = (column, direction);
+        self
+    }
+
+    pub fn with_scan(mut self) -> Result<Self> {
+        let _ = self.scan()?;
+        Ok(self)
+    }
+
+    pub fn files(&self) -> &[SongInfo] {
+        &self.files
+    }
+
+    /// Library indices of every song, sorted by the column and direction last given to
+    /// [`Self::set_sort`].
+    pub fn sorted_indices(&self) -> &[usize] {
+        &self.sorted_indices
+    }
+
+    /// Library index of the song after the one at `ix` in [`Self::sorted_indices`] order, if
+    /// it isn't the last.
+    pub fn next_in_sort(&self, ix: usize) -> Option<usize> {
+        let pos = self.sorted_indices.iter().position(|i| *i == ix)?;
+        self.sorted_indices.get(pos + 1).copied()
+    }
+
+    /// Every genre in the library, trimmed and title-cased so differently written copies of the
+    /// same genre are listed once. Songs without a genre count as "Unknown".
+    pub fn genres(&self) -> &BTreeSet<String> {
+        self.genres_cache.get_or_init(|| {
+            self.files
+                .iter()
+                .map(|s| title_case(s.genre.as_deref().unwrap_or("Unknown").trim()))
+                .collect()
+        })
+    }
+
+    /// Every artist in the library, from all of each song's artist tags.
+    pub fn artists(&self) -> &BTreeSet<String> {
+        self.artists_cache.get_or_init(|| {
+            self.files
+                .iter()
+                .flat_map(|s| &s.artists)
+                .map(|a| a.trim().to_string())
+                .collect()
+        })
+    }
+
+    /// Every album title in the library.
+    pub fn albums(&self) -> &BTreeSet<String> {
+        self.albums_cache.get_or_init(|| {
+            self.files
+                .iter()
+                .filter_map(|s| s.album.as_deref())
+                .map(|a| a.trim().to_string())
+                .collect()
+        })
+    }
+
+    /// How many songs each artist has, sorted by artist. Songs are credited to their album artist,
+    /// or their first artist without one, and to "Unknown" without either.
+    pub fn total_tracks_by_artist(&self) -> Vec<(&str, usize)> {
+        self.artist_counts_cache
+            .get_or_init(|| {
+                let mut counts = HashMap::<&str, (usize, usize)>::new();
+                for (ix, song) in self.files.iter().enumerate() {
+                    counts.entry(song.credited_artist()).or_insert((ix, 0)).1 += 1;
+                }
+                let mut counts = counts.into_values().collect::<Vec<_>>();
+                counts.sort_by_key(|(ix, _)| self.files[*ix].credited_artist());
+                counts
+            })
+            .iter()
+            .map(|(ix, count)| (self.files[*ix].credited_artist(), *count))
+            .collect()
+    }
+
+    fn clear_caches(&mut self) {
+        self.genres_cache = OnceCell::new();
+        self.artists_cache = OnceCell::new();
+        self.albums_cache = OnceCell::new();
+        self.artist_counts_cache = OnceCell::new();
+    }
+
+    /// Combined length of every song in the library.
+    pub fn total_duration(&self) -> Duration {
+        self.total_duration
+    }
+
+    /// Sort by `column` in `direction`. Songs with equal values keep their artist, album, and
+    /// track order.
+    pub fn set_sort(&mut self, column: Column, direction: SortDirection) {
+        self.sort = (column, direction);
+        self.resort();
+    }
+
+    /// Index into [`Self::files`] of the song at `path`, if it's in the library.
+    pub fn find_by_path(&self, path: &Path) -> Option<usize> {
+        self.path_index.get(path).copied()
+    }
+
+    /// Repopulate [`Self::path_index`] from [`Self::files`] after they've changed in any way
+    /// other than one song being moved.
+    fn rebuild_index(&mut self) {
+        self.path_index = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(ix, s)| (s.file_path.clone(), ix))
+            .collect();
+    }
+
+    fn resort(&mut self) {
+        let (column, direction) = self.sort;
+        let files = &self.files;
+        self.sorted_indices = (0..files.len()).collect();
+        self.sorted_indices.sort_by(|a, b| {
+            let order = files[*a].cmp_by(&files[*b], column);
+            match direction {
+                SortDirection::Ascending => order,
+                SortDirection::Descending => order.reverse(),
+            }
+        });
+    }
+
+    /// Every song ordered by `key`, leaving the library's own order alone. Songs with equal keys
+    /// stay in library order.
+    pub fn files_sorted_by<F, K: Ord>(&self, key: F) -> Vec<&SongInfo>
+    where
+        F: Fn(&SongInfo) -> K,
+    {
+        self.indices_sorted_by(key)
+            .into_iter()
+            .map(|ix| &self.files[ix])
+            .collect()
+    }
+
+    /// Library indices of every song, in [`Self::files_sorted_by`] order.
+    fn indices_sorted_by<K: Ord>(&self, key: impl Fn(&SongInfo) -> K) -> Vec<usize> {
+        let mut indices = (0..self.files.len()).collect::<Vec<_>>();
+        indices.sort_by_key(|ix| key(&self.files[*ix]));
+        indices
+    }
+
+    /// Library indices of the `limit` most played songs, most played first. Songs that have
+    /// never been played are left out.
+    pub fn most_played_view(&self, store: &PlayStatsStore, limit: usize) -> Vec<usize> {
+        let play_count = |s: &SongInfo| store.play_count(&s.file_path);
+        let mut indices = self.indices_sorted_by(|s| std::cmp::Reverse(play_count(s)));
+        indices.retain(|ix| play_count(&self.files[*ix]) > 0);
+        indices.truncate(limit);
+        indices
+    }
+
+    /// Library indices of songs that have been played or skipped, whose skip ratio compares to
+    /// `threshold` as `side`, most skipped first.
+    pub fn skip_ratio_view(
+        &self,
+        store: &PlayStatsStore,
+        side: std::cmp::Ordering,
+        threshold: f32,
+    ) -> Vec<usize> {
+        let ratio = |ix: usize| store.skip_ratio(&self.files[ix].file_path);
+        let mut indices = (0..self.files.len())
+            .filter(|ix| store.get(&self.files[*ix].file_path).is_some())
+            .filter(|ix| ratio(*ix).partial_cmp(&threshold) == Some(side))
+            .collect::<Vec<_>>();
+        indices.sort_by(|a, b| ratio(*b).total_cmp(&ratio(*a)));
+        indices
+    }
+
+    /// MP3 files that might only have an `ID3v1` tag: those whose tags couldn't be read at all,
+    /// since only `ID3v2` tags are read, and the rest of the library's MP3s to be sure.
+    pub fn id3v1_upgrade_candidates(&self) -> Vec<PathBuf> {
+        self.unreadable_files
+            .iter()
+            .chain(self.files.iter().map(|s| &s.file_path))
+            .filter(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("mp3")))
+            .cloned()
+            .collect()
+    }
+
+    /// Library indices of songs whose files were modified in the last `days` days, newest first.
+    pub fn recently_added(&self, days: u32) -> Vec<usize> {
+        let cutoff = SystemTime::now()
+            .checked_sub(Duration::from_secs(u64::from(days) * 86400))
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut indices = self.indices_sorted_by(|s| std::cmp::Reverse(s.file_modified));
+        indices.retain(|ix| self.files[*ix].file_modified > cutoff);
+        indices
+    }
+
+    /// Songs whose paths would change when renamed with `template`, with their new paths. See
+    /// [`rename::render_template`] for the template syntax.
+    pub fn rename_plan(&self, template: &str) -> Result<Vec<(usize, PathBuf)>> {
+        let mut plan = Vec::new();
+        for (ix, song) in self.files.iter().enumerate() {
+            let target = self
+                .root_for(&song.file_path)
+                .join(rename::render_template(template, song)?);
+            if target != song.file_path {
+                plan.push((ix, target));
+            }
+        }
+        Ok(plan)
+    }
+
+    /// Write every song, in sort order, to an extended M3U playlist at `path`. With `base_path`,
+    /// songs are written relative to it where they can be. Separators are always `/`.
+    pub fn export_m3u_extended(&self, path: &Path, base_path: Option<&Path>) -> Result<()> {
+        let mut contents = String::from("#EXTM3U\n");
+        for song in self.sorted_indices.iter().map(|ix| &self.files[*ix]) {
+            writeln!(
+                contents,
+                "#EXTINF:{},{} - {}",
+                song.duration.as_secs(),
+                song.display_artist(),
+                song.display_title()
+            )?;
+            let song_path = base_path
+                .and_then(|base| relative_path(&song.file_path, base))
+                .unwrap_or_else(|| song.file_path.clone());
+            let song_path = song_path.to_string_lossy();
+            writeln!(
+                contents,
+                "{}",
+                song_path.replace(std::path::MAIN_SEPARATOR, "/")
+            )?;
+        }
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Move every song to the path given by `template`, relative to its root directory, creating
+    /// directories as needed. Songs whose new path is already taken are left where they are.
+    /// A song that can't be moved doesn't stop the others from being moved.
+    ///
+    /// Fails without moving anything if the template is invalid.
+    pub fn rename_by_template(&mut self, template: &str) -> Result<RenameResult> {
+        let mut result = RenameResult::default();
+        for (ix, target) in self.rename_plan(template)? {
+            if target.exists() {
+                continue;
+            }
+            let source = self.files[ix].file_path.clone();
+            let moved = target
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|()| std::fs::rename(&source, &target));
+            if let Err(e) = moved {
+                result.failed.push((source, e));
+                continue;
+            }
+            self.path_index.remove(&source);
+            self.path_index.insert(target.clone(), ix);
+            self.files[ix].file_path.clone_from(&target);
+            result.moved.push((source, target));
+        }
+        Ok(result)
+    }
+
+    /// Move every song to `target_root/artist/album/`, keeping its file name, or with `dry_run`
+    /// just work out where each would go. Songs that would collide with another file get a
+    /// counter added to their name. Songs that can't be moved stay where they are and are left
+    /// out of the result.
+    ///
+    /// Returns the `(from, to)` path of every song moved, or to be moved.
+    pub fn organize_by_tags(
+        &mut self,
+        target_root: &Path,
+        dry_run: bool,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        if !dry_run {
+            std::fs::create_dir_all(target_root)?;
+        }
+        let mut moves = Vec::new();
+        let mut taken = HashSet::new();
+        for song in &mut self.files {
+            let Some(file_name) = song.file_path.file_name() else {
+                continue;
+            };
+            let artist = song
+                .album_artist
+                .as_deref()
+                .or(song.artist())
+                .unwrap_or("Unknown Artist");
+            let album = song.album.as_deref().unwrap_or("Unknown Album");
+            let dir = target_root
+                .join(rename::sanitize_component(artist))
+                .join(rename::sanitize_component(album));
+            let mut target = dir.join(file_name);
+            if target == song.file_path {
+                continue;
+            }
+            let mut counter = 1;
+            while target.exists() || taken.contains(&target) {
+                let stem = Path::new(file_name).file_stem().unwrap_or(file_name);
+                let mut name = stem.to_os_string();
+                name.push(format!(" ({counter})"));
+                if let Some(ext) = Path::new(file_name).extension() {
+                    name.push(".");
+                    name.push(ext);
+                }
+                target = dir.join(name);
+                counter += 1;
+            }
+
+            if !dry_run {
+                let result = std::fs::create_dir_all(&dir)
+                    .and_then(|()| std::fs::rename(&song.file_path, &target));
+                if let Err(e) = result {
+                    log::warn!("Couldn't move {}: {e}", song.file_path.display());
+                    continue;
+                }
+            }
+            taken.insert(target.clone());
+            moves.push((song.file_path.clone(), target.clone()));
+            if !dry_run {
+                song.file_path = target;
+            }
+        }
+        if !dry_run {
+            self.rebuild_index();
+        }
+        Ok(moves)
+    }
+
+    /// Remove the song at `path` from the library, returning the index it had.
+    pub fn remove_path(&mut self, path: &Path) -> Option<usize> {
+        let ix = self.find_by_path(path)?;
+        let song = self.files.remove(ix);
+        self.rebuild_index();
+        self.total_duration = self.total_duration.saturating_sub(song.duration);
+        self.clear_caches();
+        self.resort();
+        Some(ix)
+    }
+
+    /// Index pairs of songs in this library and `other` that look like copies of each other.
+    pub fn cross_library_duplicates(&self, other: &Library) -> Vec<(usize, usize)> {
+        let mut by_key = HashMap::<_, Vec<usize>>::new();
+        for (ix, song) in other.files.iter().enumerate() {
+            if let Some(key) = song.duplicate_key() {
+                by_key.entry(key).or_default().push(ix);
+            }
+        }
+        let mut pairs = Vec::new();
+        for (ix, song) in self.files.iter().enumerate() {
+            let Some(candidates) = song.duplicate_key().and_then(|k| by_key.get(&k)) else {
+                continue;
+            };
+            pairs.extend(
+                candidates
+                    .iter()
+                    .filter(|o| song.is_duplicate_of(&other.files[**o]))
+                    .map(|o| (ix, *o)),
+            );
+        }
+        pairs
+    }
+
+    /// Index pairs of songs that look like copies of each other and are under different root
+    /// directories of this library.
+    pub fn cross_root_duplicates(&self) -> Vec<(usize, usize)> {
+        self.cross_library_duplicates(self)
+            .into_iter()
+            .filter(|(a, b)| {
+                a < b
+                    && self.root_for(&self.files[*a].file_path)
+                        != self.root_for(&self.files[*b].file_path)
+            })
+            .collect()
+    }
+
+    /// Library indices of songs missing a title, artist, or album tag, along with the names of
+    /// the missing tags. Songs with [tag warnings](SongInfo::has_tag_warnings) are included too.
+    pub fn find_missing_tags(&self) -> Vec<(usize, Vec<&'static str>)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(ix, song)| (ix, song.missing_tags(), song.has_tag_warnings()))
+            .filter(|(_, missing, warned)| !missing.is_empty() || *warned)
+            .map(|(ix, missing, _)| (ix, missing))
+            .collect()
+    }
+
+    /// Library indices of songs with neither embedded cover art nor a cover image in their
+    /// directory.
+    pub fn find_missing_covers(&self, cover_art: &mut CoverArtCache) -> Vec<usize> {
+        (0..self.files.len())
+            .filter(|ix| !cover_art.has_cover(&self.files[*ix]))
+            .collect()
+    }
+
+    /// Count the gaps in the library's tags and break it down by format and bitrate.
+    pub fn quality_report(&self, cover_art: &mut CoverArtCache) -> QualityReport {
+        let mut report = QualityReport {
+            total_tracks: self.files.len(),
+            ..QualityReport::default()
+        };
+        for song in &self.files {
+            let count = |missing: bool| usize::from(missing);
+            report.tracks_missing_title += count(song.title.is_none());
+            report.tracks_missing_artist += count(song.artists.is_empty());
+            report.tracks_missing_album += count(song.album.is_none());
+            report.tracks_missing_year += count(song.year.is_none());
+            report.tracks_missing_genre += count(song.genre.is_none());
+            report.tracks_missing_cover += count(!cover_art.has_cover(song));
+
+            let format = song
+                .file_path
+                .extension()
+                .map_or(String::from("unknown"), |e| {
+                    e.to_string_lossy().to_lowercase()
+                });
+            *report.format_distribution.entry(format).or_default() += 1;
+
+            if let Some(bucket) = std::fs::metadata(&song.file_path)
+                .ok()
+                .and_then(|m| bitrate_bucket(m.len(), song.duration))
+            {
+                *report.bitrate_distribution.entry(bucket).or_default() += 1;
+            }
+        }
+        report
+    }
+
+    /// Library indices of the songs in `favourites`, in library order.
+    pub fn favourites_view(&self, favourites: &HashSet<PathBuf>) -> Vec<usize> {
+        self.sorted_indices
+            .iter()
+            .copied()
+            .filter(|ix| favourites.contains(&self.files[*ix].file_path))
+            .collect()
+    }
+
+    /// Library indices of every song, in the order they were first played. Songs that have never
+    /// been played follow in a random order.
+    pub fn sort_by_discovery_date(&self, store: &PlayStatsStore) -> Vec<usize> {
+        let first_played =
+            |song: &SongInfo| store.get(&song.file_path).and_then(|s| s.first_played);
+        let (mut played, mut unplayed): (Vec<_>, Vec<_>) = self
+            .indices_sorted_by(first_played)
+            .into_iter()
+            .partition(|ix| first_played(&self.files[*ix]).is_some());
+        unplayed.shuffle(&mut thread_rng());
+        played.extend(unplayed);
+        played
+    }
+
+    /// Library indices of songs that have never been played.
+    pub fn unplayed_view(&self, store: &PlayStatsStore) -> Vec<usize> {
+        (0..self.files.len())
+            .filter(|ix| store.play_count(&self.files[*ix].file_path) == 0)
+            .collect()
+    }
+
+    /// The resolved path of the file at `path` and, if that's different because a symlink below
+    /// its root was followed to get there, `path` itself. A root that's reached through a
+    /// symlink itself doesn't count, and resolved paths under a root are written starting with
+    /// the root as it was given, so they can still be matched against it.
+    fn resolve_links(&self, path: PathBuf) -> (PathBuf, Option<PathBuf>) {
+        if !self.scan_options.follow_symlinks {
+            return (path, None);
+        }
+        let Ok(resolved) = std::fs::canonicalize(&path) else {
+            return (path, None);
+        };
+        let unlinked = self
+            .root_dirs
+            .iter()
+            .zip(&self.canonical_roots)
+            .find_map(|(root, canonical)| Some(canonical.join(path.strip_prefix(root).ok()?)))
+            .or_else(|| std::path::absolute(&path).ok());
+        if unlinked.as_ref() == Some(&resolved) {
+            return (path, None);
+        }
+        let respelled = self
+            .root_dirs
+            .iter()
+            .zip(&self.canonical_roots)
+            .find_map(|(root, canonical)| Some(root.join(resolved.strip_prefix(canonical).ok()?)))
+            .unwrap_or(resolved);
+        (respelled, Some(path))
+    }
+
+    /// The order songs are kept in after a scan: by artist, then album, then track number.
+    fn scan_order_key(song: &SongInfo) -> (String, String, u16) {
+        (
+            song.artist().unwrap_or("Unknown").to_string(),
+            song.album.clone().unwrap_or("Unknown".to_string()),
+            song.track.0.unwrap_or(0),
+        )
+    }
+
+    /// Read the tags of the song at `path` and add it to the library in scan order, without
+    /// rescanning everything. A song already in the library is replaced, and moves if its tags
+    /// changed. Indices of the songs after it shift.
+    ///
+    /// Returns the song as it was added.
+    pub fn scan_file(&mut self, path: &Path) -> Result<SongInfo> {
+        let (file_path, link_path) = self.resolve_links(path.to_path_buf());
+        let tag = Tag::new().read_from_path(&file_path)?;
+        let mut song = SongInfo::new(&file_path, &*tag);
+        song.link_path = link_path;
+
+        if let Some(ix) = self.find_by_path(&file_path) {
+            let old = self.files.remove(ix);
+            self.total_duration = self.total_duration.saturating_sub(old.duration);
+        }
+        let key = Self::scan_order_key(&song);
+        let ix = self
+            .files
+            .partition_point(|f| Self::scan_order_key(f) <= key);
+        self.files.insert(ix, song.clone());
+        self.unreadable_files.retain(|p| *p != file_path);
+        self.total_duration += song.duration;
+        self.rebuild_index();
+        self.clear_caches();
+        self.resort();
+        Ok(song)
+    }
+
+    /// Read the tags of the song at `file_path` and add it to the library, noting any problems
+    /// in `result`.
+    fn load_song(
+        &mut self,
+        file_path: PathBuf,
+        link_path: Option<PathBuf>,
+        result: &mut ScanResult,
+    ) {
+        if let Some(max_size) = self.scan_options.max_file_size {
+            let size = std::fs::metadata(&file_path).map_or(0, |m| m.len());
+            if size > max_size {
+                log::warn!(
+                    "Not reading {}: {size} bytes is over the maximum file size",
+                    file_path.display()
+                );
+                result.oversized.push((file_path, size));
+                return;
+            }
+        }
+        let mut song = match Tag::new().read_from_path(&file_path) {
+            Ok(tag) => SongInfo::new(&file_path, &*tag),
+            // Formats without tag support are still loaded, just without tags
+            Err(
+                audiotags::Error::UnknownFileExtension(_) | audiotags::Error::UnsupportedFormat(_),
+            ) => {
+                if let Some(song) = SongInfo::from_audio(&file_path) {
+                    song
+                } else {
+                    result.unsupported.push(file_path.clone());
+                    self.unreadable_files.push(file_path);
+                    return;
+                }
+            }
+            Err(e) => {
+                result.tag_errors.push((file_path.clone(), e.to_string()));
+                self.unreadable_files.push(file_path);
+                return;
+            }
+        };
+        if song.duration.is_zero() {
+            result
+                .decode_errors
+                .push((file_path, String::from("Couldn't work out its length")));
+        }
+        song.link_path = link_path;
+        self.files.push(song);
+    }
+
+    /// Scan [`Self::root_dirs`] for audio files.
+    pub fn scan(&mut self) -> Result<ScanResult> {
+        self.files.clear();
+        self.scan_incremental()
+    }
+
+    /// [`Self::scan_incremental`], then find the song at `current_path` in the rescanned list.
+    ///
+    /// Returns what the scan found and the song's new index, if it's still in the library.
+    pub fn scan_preserving_position(
+        &mut self,
+        current_path: Option<&Path>,
+    ) -> Result<(ScanResult, Option<usize>)> {
+        let result = self.scan_incremental()?;
+        let ix = current_path.and_then(|p| self.find_by_path(p));
+        Ok((result, ix))
+    }
+
+    /// Rescan [`Self::root_dirs`], keeping already loaded songs whose files haven't been modified
+    /// since they were read instead of reading their tags again.
+    ///
+    /// Returns the same result as [`Self::scan`].
+    pub fn scan_incremental(&mut self) -> Result<ScanResult> {
+        let mut previous = self
+            .files
+            .drain(..)
+            .map(|s| (s.file_path.clone(), s))
+            .collect::<HashMap<_, _>>();
+        let mut result = ScanResult::default();
+        // Reversed so the first root is scanned first
+        let mut to_scan = self
+            .root_dirs
+            .iter()
+            .rev()
+            .map(|r| (r.clone(), 0usize, device_id(r)))
+            .collect::<Vec<_>>();
+        // Canonical paths of every directory queued so far, so symlink loops are only walked once
+        let mut visited = self
+            .root_dirs
+            .iter()
+            .map(std::fs::canonicalize)
+            .collect::<std::io::Result<HashSet<_>>>()?;
+        // Resolved paths of every song found, so a file linked to more than once is listed once
+        let mut seen_files = HashSet::new();
+        self.unreadable_files.clear();
+        while let Some((dir, depth, root_device)) = to_scan.pop() {
+            for p in std::fs::read_dir(dir)?.flatten() {
+                let path = p.path();
+                let is_hidden = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with('.'));
+                let mut file_type = p.file_type()?;
+                if file_type.is_symlink() {
+                    if !self.scan_options.follow_symlinks {
+                        continue;
+                    }
+                    // Skip broken links
+                    let Ok(target) = std::fs::canonicalize(&path) else {
+                        continue;
+                    };
+                    file_type = std::fs::metadata(target)?.file_type();
+                }
+
+                if file_type.is_dir() {
+                    if is_hidden && !self.scan_options.scan_hidden_dirs {
+                        continue;
+                    }
+                    if depth + 1 >= self.scan_options.max_scan_depth {
+                        log::warn!(
+                            "Not scanning {}: deeper than max_scan_depth ({})",
+                            path.display(),
+                            self.scan_options.max_scan_depth
+                        );
+                    } else if !self.scan_options.follow_mounts && device_id(&path) != root_device {
+                        log::warn!("Not scanning {}: on another filesystem", path.display());
+                    } else if visited.insert(std::fs::canonicalize(&path)?) {
+                        to_scan.push((path, depth + 1, root_device));
+                    }
+                } else if file_type.is_file()
+                    && (!is_hidden || self.scan_options.scan_hidden_files)
+                    && self.is_scanned_extension(&path)
+                {
+                    let (file_path, link_path) = self.resolve_links(path);
+                    if !seen_files.insert(file_path.clone()) {
+                        continue;
+                    }
+                    result.total_seen += 1;
+                    let modified = std::fs::metadata(&file_path)
+                        .and_then(|m| m.modified())
+                        .ok();
+                    if let Some(song) = previous
+                        .remove(&file_path)
+                        .filter(|s| Some(s.file_modified) == modified)
+                    {
+                        self.files.push(song);
+                        continue;
+                    }
+                    self.load_song(file_path, link_path, &mut result);
+                }
+            }
+        }
+
+        self.files.sort_by_key(Self::scan_order_key);
+        self.rebuild_index();
+        self.resort();
+        self.total_duration = self.files.iter().map(|f| f.duration).sum();
+        self.clear_caches();
+        result.loaded = self.files.len();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+impl Library {
+    /// Replace the library's songs without scanning for them.
+    fn set_files(&mut self, files: Vec<SongInfo>) {
+        self.files = files;
+        self.rebuild_index();
+        self.resort();
+    }
+}
+
+/// The player's state apart from its audio output, for tests to compare against.
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct AppSnapshot {
+    pub library: Vec<SongInfo>,
+    pub active_song: Option<SongInfo>,
+    pub playing_file_ix: usize,
+    pub selected_file_ix: usize,
+    pub playback_mode: PlaybackMode,
+    pub volume: Volume,
+}
+
+#[cfg(test)]
+impl<M: AudioManagerTrait> PlayerApp<M> {
+    pub fn snapshot(&self) -> AppSnapshot {
+        AppSnapshot {
+            library: self.library.files().to_vec(),
+            active_song: self.app_state.active_song.clone(),
+            playing_file_ix: self.app_state.playing_file_ix,
+            selected_file_ix: self.app_state.selected_file_ix,
+            playback_mode: self.app_state.playback_mode,
+            volume: self.volume(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use std::fs::{create_dir, File};
+    use tempdir::TempDir;
+
+    use super::*;
+
+    fn test_song(path: &str) -> SongInfo {
+        SongInfo::untagged(Path::new(path))
+    }
+
+    fn test_app(root_dir: &Path, config: Config) -> Result<PlayerApp<MockAudioManager>> {
+        PlayerApp::with_audio_manager(root_dir, config, MockAudioManager::default())
+    }
+
+    #[test]
+    fn test_song_info_apply_mp4_atoms() {
+        let atoms = HashMap::from([
+            (String::from("©nam"), String::from("Title")),
+            (String::from("©ART"), String::from("Artist")),
+            (String::from("©day"), String::from("2004-05-01T07:00:00Z")),
+            (String::from("trkn"), String::from("3/12")),
+            (String::from("disk"), String::from("1/")),
+        ]);
+        let mut song = test_song("a.m4a");
+        song.apply_mp4_atoms(&atoms);
+        assert_eq!(song.title(), Some("Title"));
+        assert_eq!(song.artist(), Some("Artist"));
+        assert_eq!(song.album(), None);
+        assert_eq!(song.year, Some(2004));
+        assert_eq!(song.track, (Some(3), Some(12)));
+        assert_eq!(song.disc, (Some(1), None));
+    }
+
+    #[test]
+    fn test_normalise_track_number() {
+        for (raw, expected) in [
+            ("1", (Some(1), None)),
+            ("01", (Some(1), None)),
+            (" 7 ", (Some(7), None)),
+            ("1/12", (Some(1), Some(12))),
+            ("01/12", (Some(1), Some(12))),
+            ("1 / 12", (Some(1), Some(12))),
+            ("3/", (Some(3), None)),
+            ("/12", (None, Some(12))),
+            ("1 of 12", (Some(1), Some(12))),
+            ("01 OF 12", (Some(1), Some(12))),
+            ("Track 1", (Some(1), None)),
+            ("track 05", (Some(5), None)),
+            ("Track #5", (Some(5), None)),
+            ("Track 1 of 12", (Some(1), Some(12))),
+            ("Track 1/12", (Some(1), Some(12))),
+            ("", (None, None)),
+            ("Track", (None, None)),
+            ("A1", (None, None)),
+            ("-1", (None, None)),
+            ("70000", (None, None)),
+        ] {
+            assert_eq!(normalise_track_number(raw), expected, "parsing {raw:?}");
+        }
+    }
+
+    #[test]
+    fn test_song_info_rejects_implausible_years() {
+        for (year, plausible) in [
+            (0, false),
+            (1876, false),
+            (1877, true),
+            (1999, true),
+            (current_year() + 1, true),
+            (current_year() + 2, false),
+            (9999, false),
+        ] {
+            let mut song = test_song("a.mp3");
+            song.year = Some(year);
+            song.check_year();
+            assert_eq!(song.year().is_some(), plausible, "checking {year}");
+            assert_eq!(song.has_tag_warnings(), !plausible, "checking {year}");
+        }
+        assert!(current_year() >= 2024);
+    }
+
+    #[test]
+    fn test_song_info_display() {
+        let mut song = test_song("music/01 Intro.mp3");
+        assert_eq!(song.to_string(), "01 Intro.mp3");
+        song.title = Some(String::from("Intro"));
+        assert_eq!(song.to_string(), "Intro");
+        song.artists = vec![String::from("Band"), String::from("Guest")];
+        assert_eq!(song.to_string(), "Band – Intro");
+    }
+
+    #[test]
+    fn test_song_info_display_fields() {
+        let mut song = test_song("music/01 Intro.mp3");
+        assert_eq!(song.display_title(), "01 Intro");
+        assert_eq!(song.display_artist(), "Unknown Artist");
+        assert_eq!(song.display_album(), "Unknown Album");
+        song.title = Some(String::from("Intro"));
+        assert_eq!(song.display_title(), "Intro");
+    }
+
+    #[test]
+    fn test_song_info_matches_any_artist() {
+        let mut song = test_song("a.flac");
+        song.artists = vec![String::from("First"), String::from("Featured")];
+        assert_eq!(song.artist(), Some("First"));
+        assert!(song.matches_query("feat", false));
+        assert!(!song.matches_query("feat", true));
+        assert!(!song.matches_query("Someone Else", false));
+    }
+
+    #[test]
+    fn test_library_scans_empty_dir() {
+        let td = TempDir::new("tempdir").unwrap();
+        let l = Library::new(td.path());
+        assert!(l.files().is_empty());
+    }
+
+    #[test]
+    fn test_library_scans_flat_dir_no_valid_files() {
+        let td = TempDir::new("tempdir").unwrap();
+        let file_path = td.path().join("test_file.mp3");
+        let _file = File::create(file_path).unwrap();
+        let mut l = Library::new(td.path());
+        assert_eq!(l.scan().unwrap().total_seen, 1);
+        // We don't add files unless they can be parsed as valid mp3/flac
+        assert!(l.files().is_empty());
+    }
+
+    #[test]
+    fn test_library_scans_nested_dir_no_valid_files() {
+        let td = TempDir::new("tempdir").unwrap();
+        create_dir(td.path().join("subdir_1")).unwrap();
+        let file_path = td.path().join("subdir_1").join("test_file.mp3");
+        let _file = File::create(file_path).unwrap();
+        create_dir(td.path().join("subdir_2")).unwrap();
+        let file_path = td.path().join("subdir_2").join("test_file.mp3");
+        let _file = File::create(file_path).unwrap();
+        create_dir(td.path().join("subdir_3")).unwrap();
+        let file_path = td.path().join("subdir_3").join("test_file.mp3");
+        let _file = File::create(file_path).unwrap();
+        create_dir(td.path().join("subdir_1").join("subsubdir_1")).unwrap();
+        let file_path = td
+            .path()
+            .join("subdir_1")
+            .join("subsubdir_1")
+            .join("test_file.mp3");
+        let _file = File::create(file_path).unwrap();
+        let mut l = Library::new(td.path());
+        assert_eq!(l.scan().unwrap().total_seen, 4);
+        // We don't add files unless they can be parsed as valid mp3/flac
+        assert!(l.files().is_empty());
+    }
+
+    #[test]
+    fn test_library_most_played_view() {
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![test_song("a.mp3"), test_song("b.mp3"), test_song("c.mp3")];
+        let mut store = PlayStatsStore::default();
+        store.record_play(Path::new("c.mp3"));
+        store.record_play(Path::new("c.mp3"));
+        store.record_play(Path::new("a.mp3"));
+        assert_eq!(l.most_played_view(&store, 10), vec![2, 0]);
+        assert_eq!(l.most_played_view(&store, 1), vec![2]);
+    }
+
+    #[test]
+    fn test_library_skip_ratio_view() {
+        use std::cmp::Ordering;
+
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![test_song("a.mp3"), test_song("b.mp3"), test_song("c.mp3")];
+        let mut store = PlayStatsStore::default();
+        // a: 1/3, b: 0, c: never played
+        store.record_play(Path::new("a.mp3"));
+        store.record_skip(Path::new("a.mp3"));
+        store.record_play(Path::new("b.mp3"));
+        assert_eq!(l.skip_ratio_view(&store, Ordering::Less, 0.3), vec![1]);
+        assert_eq!(l.skip_ratio_view(&store, Ordering::Greater, 0.3), vec![0]);
+        assert_eq!(l.skip_ratio_view(&store, Ordering::Less, 0.5), vec![0, 1]);
+
+        assert_eq!(parse_ratio_filter("<0.3"), Some((Ordering::Less, 0.3)));
+        assert_eq!(parse_ratio_filter(">0.5"), Some((Ordering::Greater, 0.5)));
+        assert_eq!(parse_ratio_filter("0.5"), None);
+        assert_eq!(parse_ratio_filter("<lots"), None);
+    }
+
+    #[test]
+    fn test_library_favourites_view() {
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![test_song("b.mp3"), test_song("a.mp3"), test_song("c.mp3")];
+        l.files[0].title = Some(String::from("B"));
+        l.files[1].title = Some(String::from("A"));
+        l.set_sort(Column::Title, SortDirection::Ascending);
+        let favourites = HashSet::from([PathBuf::from("a.mp3"), PathBuf::from("b.mp3")]);
+        assert_eq!(l.favourites_view(&favourites), vec![1, 0]);
+        assert!(l.favourites_view(&HashSet::new()).is_empty());
+    }
+
+    #[test]
+    fn test_probe_duration() {
+        let td = TempDir::new("tempdir").unwrap();
+        // 100 silent MPEG-1 Layer III frames at 128 kbps and 44.1 kHz, 1152 samples each
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        let path = td.path().join("silence.mp3");
+        std::fs::write(&path, frame.repeat(100)).unwrap();
+        let duration = probe_duration(&path).unwrap();
+        assert_eq!(duration.as_millis(), 100 * 1152 * 1000 / 44_100);
+
+        std::fs::write(&path, b"not audio").unwrap();
+        assert_eq!(probe_duration(&path), None);
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/music/a/b.mp3"), Path::new("/music/lists")),
+            Some(PathBuf::from("../a/b.mp3"))
+        );
+        assert_eq!(
+            relative_path(Path::new("/music/a/b.mp3"), Path::new("/music")),
+            Some(PathBuf::from("a/b.mp3"))
+        );
+        assert_eq!(
+            relative_path(Path::new("/b.mp3"), Path::new("/music/lists")),
+            Some(PathBuf::from("../../b.mp3"))
+        );
+    }
+
+    #[test]
+    fn test_library_export_m3u_extended() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        let mut tagged = test_song("/music/a/one.mp3");
+        tagged.title = Some(String::from("One"));
+        tagged.artists = vec![String::from("Artist")];
+        l.set_files(vec![tagged, test_song("/music/b/two.flac")]);
+        let playlist = td.path().join("library.m3u");
+
+        l.export_m3u_extended(&playlist, None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&playlist).unwrap(),
+            "#EXTM3U\n\
+             #EXTINF:180,Unknown Artist - two\n/music/b/two.flac\n\
+             #EXTINF:180,Artist - One\n/music/a/one.mp3\n"
+        );
+
+        l.export_m3u_extended(&playlist, Some(Path::new("/music/lists")))
+            .unwrap();
+        let contents = std::fs::read_to_string(&playlist).unwrap();
+        assert_eq!(
+            contents
+                .lines()
+                .filter(|l| !l.starts_with('#'))
+                .collect::<Vec<_>>(),
+            ["../b/two.flac", "../a/one.mp3"]
+        );
+    }
+
+    #[test]
+    fn test_title_case() {
+        assert_eq!(title_case("rock"), "Rock");
+        assert_eq!(title_case("HIP-HOP"), "Hip-Hop");
+        assert_eq!(title_case("drum and  bass"), "Drum And  Bass");
+        assert_eq!(title_case("électro/pop"), "Électro/Pop");
+        assert_eq!(title_case(""), "");
+    }
+
+    #[test]
+    fn test_library_total_tracks_by_artist() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        l.files = vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+            test_song("d.mp3"),
+        ];
+        l.files[0].artists = vec![String::from("Queen"), String::from("David Bowie")];
+        l.files[1].artists = vec![String::from("Freddie Mercury")];
+        l.files[1].album_artist = Some(String::from("Queen"));
+        l.files[2].artists = vec![String::from("Abba")];
+        assert_eq!(
+            l.total_tracks_by_artist(),
+            [("Abba", 1), ("Queen", 2), ("Unknown", 1)]
+        );
+
+        // Rescanning the empty directory drops the cached counts
+        l.scan().unwrap();
+        assert!(l.total_tracks_by_artist().is_empty());
+    }
+
+    #[test]
+    fn test_library_genres_artists_albums() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        l.files = vec![test_song("a.mp3"), test_song("b.mp3"), test_song("c.mp3")];
+        l.files[0].genre = Some(String::from(" rock "));
+        l.files[0].artists = vec![String::from("Queen"), String::from("David Bowie")];
+        l.files[0].album = Some(String::from("Hot Space"));
+        l.files[1].genre = Some(String::from("Rock"));
+        l.files[1].artists = vec![String::from("Queen")];
+        assert_eq!(l.genres().iter().collect::<Vec<_>>(), ["Rock", "Unknown"]);
+        assert_eq!(
+            l.artists().iter().collect::<Vec<_>>(),
+            ["David Bowie", "Queen"]
+        );
+        assert_eq!(l.albums().iter().collect::<Vec<_>>(), ["Hot Space"]);
+
+        // Rescanning the empty directory drops the cached lists
+        l.scan().unwrap();
+        assert!(l.genres().is_empty());
+        assert!(l.artists().is_empty());
+        assert!(l.albums().is_empty());
+    }
+
+    #[test]
+    fn test_library_unplayed_view() {
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![test_song("a.mp3"), test_song("b.mp3"), test_song("c.mp3")];
+        let mut store = PlayStatsStore::default();
+        assert_eq!(l.unplayed_view(&store), vec![0, 1, 2]);
+        store.record_play(Path::new("b.mp3"));
+        assert_eq!(l.unplayed_view(&store), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_library_sort_by_discovery_date() {
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+            test_song("d.mp3"),
+        ];
+        let mut store = PlayStatsStore::default();
+        store.record_play(Path::new("c.mp3"));
+        std::thread::sleep(Duration::from_millis(2));
+        store.record_play(Path::new("a.mp3"));
+        // Playing a song again doesn't move it
+        store.record_play(Path::new("c.mp3"));
+        let order = l.sort_by_discovery_date(&store);
+        assert_eq!(order[..2], [2, 0]);
+        let mut unplayed = order[2..].to_vec();
+        unplayed.sort_unstable();
+        assert_eq!(unplayed, [1, 3]);
+    }
+
+    #[test]
+    fn test_library_find_missing_tags() {
+        let mut l = Library::new(Path::new("."));
+        let mut tagged = test_song("a.mp3");
+        tagged.title = Some(String::from("Title"));
+        tagged.artists = vec![String::from("Artist")];
+        tagged.album = Some(String::from("Album"));
+        let mut untitled = tagged.clone();
+        untitled.title = None;
+        l.files = vec![tagged, untitled, test_song("c.mp3")];
+        assert_eq!(
+            l.find_missing_tags(),
+            vec![(1, vec!["title"]), (2, vec!["title", "artist", "album"])]
+        );
+    }
+
+    #[test]
+    fn test_render_template() {
+        let mut song = test_song("music/a.flac");
+        song.artists = vec![String::from("AC/DC")];
+        song.album = Some(String::from("Back in Black"));
+        song.title = Some(String::from("What Do You Do for Money Honey?"));
+        song.track = (Some(5), Some(10));
+        assert_eq!(
+            rename::render_template("{artist}/{album}/{track:02} - {title}", &song).unwrap(),
+            PathBuf::from("AC_DC/Back in Black/05 - What Do You Do for Money Honey_.flac")
+        );
+        // Missing tags fall back to placeholders, and an existing extension isn't repeated
+        assert_eq!(
+            rename::render_template("{album_artist}/{disc}-{title}.flac", &test_song("a.flac"))
+                .unwrap(),
+            PathBuf::from("Unknown Artist/0-Unknown Title.flac")
+        );
+        assert!(rename::render_template("{composer}", &song).is_err());
+        assert!(rename::render_template("{title", &song).is_err());
+        // Tags can't climb out of the library
+        song.artists = vec![String::from("..")];
+        song.album = Some(String::from(" . "));
+        assert_eq!(
+            rename::render_template("{artist}/{album}/{title}", &song).unwrap(),
+            PathBuf::from("_/_/What Do You Do for Money Honey_.flac")
+        );
+        // Blank tags get the placeholder rather than rendering as `/...`
+        song.artists = vec![String::new()];
+        song.album = Some(String::from("  "));
+        assert_eq!(
+            rename::render_template("{artist}/{album}/{title}", &song).unwrap(),
+            PathBuf::from("Unknown Artist/Unknown Album/What Do You Do for Money Honey_.flac")
+        );
+        // Neither can the template itself
+        assert!(rename::render_template("../{title}", &song).is_err());
+        assert!(rename::render_template("/{title}", &song).is_err());
+        assert!(rename::render_template("{album}/../../{title}", &song).is_err());
+    }
+
+    #[test]
+    fn test_library_rename_by_template() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        let source = td.path().join("a.mp3");
+        File::create(&source).unwrap();
+        let mut song = test_song(source.to_str().unwrap());
+        song.artists = vec![String::from("Artist")];
+        song.title = Some(String::from("Title"));
+        l.files = vec![song];
+
+        let result = l.rename_by_template("{artist}/{title}").unwrap();
+        let target = td.path().join("Artist").join("Title.mp3");
+        assert_eq!(result.moved, [(source.clone(), target.clone())]);
+        assert!(target.exists());
+        assert!(!source.exists());
+        assert_eq!(l.files()[0].file_path(), target);
+        // Already in place
+        assert!(l
+            .rename_by_template("{artist}/{title}")
+            .unwrap()
+            .moved
+            .is_empty());
+
+        // A file that's gone is reported, and doesn't stop the rest from moving
+        let other = td.path().join("b.mp3");
+        File::create(&other).unwrap();
+        let mut missing = test_song(td.path().join("gone.mp3").to_str().unwrap());
+        missing.title = Some(String::from("Gone"));
+        let mut song = test_song(other.to_str().unwrap());
+        song.title = Some(String::from("Other"));
+        l.files = vec![missing, song];
+        l.rebuild_index();
+        let result = l.rename_by_template("{title}").unwrap();
+        assert_eq!(result.failed.len(), 1);
+        assert_eq!(result.failed[0].0, td.path().join("gone.mp3"));
+        assert_eq!(result.moved.len(), 1);
+        assert!(td.path().join("Other.mp3").exists());
+    }
+
+    #[test]
+    fn test_library_organize_by_tags() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        for dir in ["x", "y"] {
+            std::fs::create_dir(td.path().join(dir)).unwrap();
+            File::create(td.path().join(dir).join("01.mp3")).unwrap();
+        }
+        let mut a = test_song(td.path().join("x/01.mp3").to_str().unwrap());
+        a.artists = vec![String::from("Artist")];
+        a.album = Some(String::from("Album"));
+        let mut b = a.clone();
+        b.file_path = td.path().join("y/01.mp3");
+        l.files = vec![a, b];
+
+        let target_root = td.path().join("sorted");
+        let album_dir = target_root.join("Artist").join("Album");
+        let expected = vec![
+            (td.path().join("x/01.mp3"), album_dir.join("01.mp3")),
+            (td.path().join("y/01.mp3"), album_dir.join("01 (1).mp3")),
+        ];
+        assert_eq!(l.organize_by_tags(&target_root, true).unwrap(), expected);
+        assert!(!target_root.exists());
+
+        assert_eq!(l.organize_by_tags(&target_root, false).unwrap(), expected);
+        assert!(album_dir.join("01.mp3").exists());
+        assert!(album_dir.join("01 (1).mp3").exists());
+        assert_eq!(l.files()[1].file_path(), album_dir.join("01 (1).mp3"));
+    }
+
+    #[test]
+    fn test_library_cross_library_duplicates() {
+        let song = |path: &str, title: &str, secs: u64| {
+            let mut s = test_song(path);
+            s.title = Some(String::from(title));
+            s.artists = vec![String::from("Artist")];
+            s.duration = Duration::from_secs(secs);
+            s
+        };
+        let mut home = Library::new(Path::new("home"));
+        home.files = vec![song("home/a.mp3", "A", 180), song("home/b.mp3", "B", 200)];
+        let mut backup = Library::new(Path::new("backup"));
+        backup.files = vec![
+            song("backup/b.mp3", "b", 201),
+            song("backup/a.mp3", "A", 190),
+            test_song("backup/untagged.mp3"),
+        ];
+        assert_eq!(home.cross_library_duplicates(&backup), vec![(1, 0)]);
+
+        let mut both = Library::from_roots(&[PathBuf::from("home"), PathBuf::from("backup")]);
+        both.files = home.files.iter().chain(&backup.files).cloned().collect();
+        assert_eq!(both.cross_root_duplicates(), vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_library_remove_path() {
+        let mut l = Library::new(Path::new("."));
+        l.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        assert_eq!(l.remove_path(Path::new("b.mp3")), Some(1));
+        assert_eq!(l.remove_path(Path::new("b.mp3")), None);
+        assert_eq!(l.files()[1].file_path(), Path::new("c.mp3"));
+    }
+
+    #[test]
+    fn test_library_rebuild_index() {
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![test_song("a.mp3"), test_song("b.mp3"), test_song("c.mp3")];
+        // Filled in directly, so the index hasn't caught up yet
+        assert_eq!(l.find_by_path(Path::new("a.mp3")), None);
+        l.rebuild_index();
+        for (ix, path) in ["a.mp3", "b.mp3", "c.mp3"].iter().enumerate() {
+            assert_eq!(l.find_by_path(Path::new(path)), Some(ix));
+        }
+    }
+
+    #[test]
+    fn test_library_find_by_path() {
+        let assert_consistent = |l: &Library| {
+            for (ix, song) in l.files().iter().enumerate() {
+                assert_eq!(l.find_by_path(song.file_path()), Some(ix));
+            }
+            assert_eq!(l.path_index.len(), l.files().len());
+        };
+        let mut l = Library::new(Path::new("."));
+        assert_eq!(l.find_by_path(Path::new("a.mp3")), None);
+        l.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        assert_consistent(&l);
+        l.remove_path(Path::new("a.mp3"));
+        assert_consistent(&l);
+        assert_eq!(l.find_by_path(Path::new("a.mp3")), None);
+        assert_eq!(l.find_by_path(Path::new("c.mp3")), Some(1));
+
+        // A rescan that finds nothing empties the index too
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        l.set_files(vec![test_song("a.mp3")]);
+        l.scan().unwrap();
+        assert_consistent(&l);
+        assert_eq!(l.find_by_path(Path::new("a.mp3")), None);
+    }
+
+    #[test]
+    fn test_bitrate_bucket() {
+        let minute = Duration::from_mins(1);
+        // 128 kbps is 960 KB a minute
+        assert_eq!(bitrate_bucket(960_000, minute), Some(128));
+        assert_eq!(bitrate_bucket(1_500_000, minute), Some(192));
+        assert_eq!(bitrate_bucket(2_400_000, minute), Some(320));
+        // Lossless files land in the top bucket
+        assert_eq!(bitrate_bucket(7_000_000, minute), Some(320));
+        assert_eq!(bitrate_bucket(100, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn test_library_quality_report() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        // 180 seconds at 320 kbps
+        let c = td.path().join("c.flac");
+        std::fs::write(&c, vec![0; 7_200_000]).unwrap();
+        l.files = vec![
+            test_song("a.mp3"),
+            test_song("b.MP3"),
+            test_song(c.to_str().unwrap()),
+        ];
+        l.files[0].title = Some(String::from("Title"));
+        l.files[0].year = Some(1999);
+        let report = l.quality_report(&mut CoverArtCache::default());
+        assert_eq!(report.total_tracks, 3);
+        assert_eq!(report.tracks_missing_title, 2);
+        assert_eq!(report.tracks_missing_artist, 3);
+        assert_eq!(report.tracks_missing_year, 2);
+        assert_eq!(report.tracks_missing_cover, 3);
+        assert_eq!(
+            report.format_distribution,
+            HashMap::from([(String::from("mp3"), 2), (String::from("flac"), 1)])
+        );
+        // The MP3s don't exist, so only the FLAC's bitrate is known
+        assert_eq!(report.bitrate_distribution, HashMap::from([(320, 1)]));
+    }
+
+    #[test]
+    fn test_library_files_sorted_by() {
+        let mut l = Library::new(Path::new("."));
+        let mut songs = ["c.mp3", "a.mp3", "b.mp3"].map(test_song);
+        songs[0].year = Some(1990);
+        songs[2].year = Some(1980);
+        l.set_files(songs.to_vec());
+
+        let paths = |files: Vec<&SongInfo>| {
+            files
+                .iter()
+                .map(|s| s.file_path.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths(l.files_sorted_by(|s| s.year)),
+            ["a.mp3", "b.mp3", "c.mp3"]
+        );
+        // Ties keep library order, and the library itself isn't reordered
+        assert_eq!(
+            paths(l.files_sorted_by(|_| ())),
+            ["c.mp3", "a.mp3", "b.mp3"]
+        );
+        assert_eq!(l.files()[0].file_path, Path::new("c.mp3"));
+    }
+
+    #[test]
+    fn test_library_sorted_indices() {
+        let mut l = Library::new(Path::new("."));
+        l.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        l.files[0].year = Some(2001);
+        l.files[1].year = Some(1999);
+        l.files[2].year = Some(2001);
+        l.set_sort(Column::Year, SortDirection::Ascending);
+        assert_eq!(l.sorted_indices(), [1, 0, 2]);
+        // Ties keep the order they were scanned in, whichever the direction
+        l.set_sort(Column::Year, SortDirection::Descending);
+        assert_eq!(l.sorted_indices(), [0, 2, 1]);
+        l.remove_path(Path::new("a.mp3"));
+        assert_eq!(l.sorted_indices(), [1, 0]);
+        assert_eq!(l.next_in_sort(1), Some(0));
+        assert_eq!(l.next_in_sort(0), None);
+    }
+
+    #[test]
+    fn test_library_recently_added() {
+        let mut l = Library::new(Path::new("."));
+        l.files = vec![test_song("a.mp3"), test_song("b.mp3"), test_song("c.mp3")];
+        let now = SystemTime::now();
+        l.files[0].file_modified = now - Duration::from_hours(2);
+        l.files[2].file_modified = now - Duration::from_hours(1);
+        // b.mp3 was modified at the epoch, far outside the window
+        assert_eq!(l.recently_added(7), vec![2, 0]);
+    }
 
-impl Library {
-    pub fn new(root_dir: &Path) -> Self {
-        Self {
-            root_dir: root_dir.to_path_buf(),
-            files: vec![],
+    /// Write a mono 16-bit PCM WAV file.
+    fn write_test_wav(path: &Path, samples: &[i16], sample_rate: u32) {
+        let data_len = u32::try_from(samples.len() * 2).unwrap();
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // Mono
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for s in samples {
+            bytes.extend_from_slice(&s.to_le_bytes());
         }
+        std::fs::write(path, bytes).unwrap();
     }
 
-    pub fn with_scan(mut self) -> Result<Self> {
-        let _ = self.scan()?;
-        Ok(self)
+    #[test]
+    fn test_detect_silence_boundaries() {
+        let td = TempDir::new("tempdir").unwrap();
+        let path = td.path().join("padded.wav");
+        let sample_rate = 8000;
+        let silence = vec![0i16; 8000];
+        let tone = (0..8000)
+            .map(|i| if i % 2 == 0 { 8000 } else { -8000 })
+            .collect::<Vec<_>>();
+        write_test_wav(
+            &path,
+            &[&silence[..], &tone, &silence].concat(),
+            sample_rate,
+        );
+
+        let (start, end) = AudioManager::detect_silence_boundaries(&path).unwrap();
+        assert!(
+            (start.as_secs_f64() - 1.0).abs() < 0.01,
+            "start was {start:?}"
+        );
+        assert!((end.as_secs_f64() - 2.0).abs() < 0.01, "end was {end:?}");
     }
 
-    pub fn files(&self) -> &[SongInfo] {
-        &self.files
+    #[test]
+    fn test_compute_waveform() {
+        let td = TempDir::new("tempdir").unwrap();
+        let path = td.path().join("fade_in.wav");
+        let quiet = (0..8000)
+            .map(|i| if i % 2 == 0 { 2000 } else { -2000 })
+            .collect::<Vec<_>>();
+        let loud = quiet.iter().map(|s| s * 4).collect::<Vec<_>>();
+        write_test_wav(&path, &[&vec![0; 8000][..], &quiet, &loud].concat(), 8000);
+
+        let waveform = AudioManager::compute_waveform(&path, 3).unwrap();
+        assert_eq!(waveform.len(), 3);
+        assert!(waveform[0] < 0.01);
+        assert!((waveform[1] - 0.25).abs() < 0.01, "got {waveform:?}");
+        assert!((waveform[2] - 1.0).abs() < 0.01);
     }
 
-    /// Scan [`Self::root_dir`] for audio files.
-    ///
-    /// If successful, returns a [`Result`] containing the number of total files scanned.
-    /// The number of files successfully loaded is just the size of [`Self::files`].
-    pub fn scan(&mut self) -> Result<usize> {
-        self.files.clear();
-        let mut total_files_seen = 0usize;
-        let mut to_scan = vec![self.root_dir.clone()];
-        while let Some(dir) = to_scan.pop() {
-            for p in std::fs::read_dir(dir)?.flatten() {
-                let path = p.path();
-                if p.file_type()?.is_dir() {
-                    to_scan.push(path);
-                } else if p.file_type()?.is_file()
-                    && p.path()
-                        .extension()
-                        .is_some_and(|e| ["mp3", "flac"].contains(&e.to_str().unwrap_or("")))
-                {
-                    total_files_seen += 1;
-                    let Ok(tag) = Tag::new().read_from_path(&p.path()) else {
-                        continue;
-                    };
-                    self.files.push(SongInfo::new(&p.path(), &*tag));
-                }
-            }
-        }
+    #[cfg(unix)]
+    #[test]
+    fn test_library_scan_follows_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let td = TempDir::new("tempdir").unwrap();
+        let music = td.path().join("music");
+        create_dir(&music).unwrap();
+        let _file = File::create(music.join("test_file.mp3")).unwrap();
+        let root = td.path().join("root");
+        create_dir(&root).unwrap();
+        symlink(&music, root.join("linked_music")).unwrap();
+        // A loop back to the root must not be walked forever
+        symlink(&root, root.join("loop")).unwrap();
+        // Nor should a second link to the same file count twice
+        symlink(music.join("test_file.mp3"), root.join("alias.mp3")).unwrap();
 
-        self.files.sort_by_key(|f| {
+        let mut l = Library::new(&root);
+        assert_eq!(l.scan().unwrap().total_seen, 1);
+        assert_eq!(
+            l.resolve_links(root.join("alias.mp3")),
             (
-                f.artist.clone().unwrap_or("Unknown".to_string()),
-                f.album.clone().unwrap_or("Unknown".to_string()),
-                f.track.0.unwrap_or(0),
+                music.canonicalize().unwrap().join("test_file.mp3"),
+                Some(root.join("alias.mp3"))
             )
+        );
+        let plain = music.canonicalize().unwrap().join("test_file.mp3");
+        assert_eq!(l.resolve_links(plain.clone()), (plain, None));
+
+        let mut l = Library::new(&root).with_scan_options(ScanOptions {
+            follow_symlinks: false,
+            ..ScanOptions::default()
         });
-        Ok(total_files_seen)
+        assert_eq!(l.scan().unwrap().total_seen, 0);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    extern crate tempdir;
+    #[cfg(unix)]
+    #[test]
+    fn test_library_root_through_symlink() {
+        use std::os::unix::fs::symlink;
 
-    use std::fs::{create_dir, File};
-    use tempdir::TempDir;
+        let td = TempDir::new("tempdir").unwrap();
+        let music = td.path().join("music");
+        create_dir(&music).unwrap();
+        File::create(music.join("a.mp3")).unwrap();
+        symlink(music.join("a.mp3"), music.join("alias.mp3")).unwrap();
+        let linked_root = td.path().join("linked");
+        symlink(&music, &linked_root).unwrap();
 
-    use super::*;
+        // Neither a linked root nor one written with `..` makes its songs links
+        for root in [linked_root.clone(), music.join("..").join("music")] {
+            let l = Library::new(&root);
+            assert_eq!(
+                l.resolve_links(root.join("a.mp3")),
+                (root.join("a.mp3"), None)
+            );
+            // A link below the root is still followed, and kept under the root's spelling
+            assert_eq!(
+                l.resolve_links(root.join("alias.mp3")),
+                (root.join("a.mp3"), Some(root.join("alias.mp3")))
+            );
+            assert_eq!(l.root_for(&root.join("a.mp3")), root);
+        }
+    }
 
     #[test]
-    fn test_library_scans_empty_dir() {
+    fn test_library_scans_every_root() {
+        let work = TempDir::new("tempdir").unwrap();
+        let home = TempDir::new("tempdir").unwrap();
+        let _file = File::create(work.path().join("a.mp3")).unwrap();
+        let _file = File::create(home.path().join("b.mp3")).unwrap();
+        let _file = File::create(home.path().join("c.mp3")).unwrap();
+
+        let mut l = Library::from_roots(&[work.path().to_path_buf(), home.path().to_path_buf()]);
+        assert_eq!(l.scan().unwrap().total_seen, 3);
+        assert_eq!(l.root_for(&home.path().join("b.mp3")), home.path());
+    }
+
+    #[test]
+    fn test_library_scans_configured_extensions() {
         let td = TempDir::new("tempdir").unwrap();
-        let l = Library::new(td.path());
-        assert!(l.files().is_empty());
+        let _file = File::create(td.path().join("a.mp3")).unwrap();
+        let _file = File::create(td.path().join("b.WMA")).unwrap();
+        let _file = File::create(td.path().join("c.txt")).unwrap();
+
+        let mut l = Library::new(td.path());
+        assert_eq!(l.scan().unwrap().total_seen, 1);
+
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            extensions: vec![String::from("mp3"), String::from("wma")],
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 2);
     }
 
     #[test]
-    fn test_library_scans_flat_dir_no_valid_files() {
+    fn test_library_scan_skips_hidden_files_and_dirs() {
         let td = TempDir::new("tempdir").unwrap();
-        let file_path = td.path().join("test_file.mp3");
-        let _file = File::create(file_path).unwrap();
+        let hidden_dir = td.path().join(".Trash");
+        create_dir(&hidden_dir).unwrap();
+        let _file = File::create(hidden_dir.join("test_file.mp3")).unwrap();
+        let _file = File::create(td.path().join(".hidden_file.mp3")).unwrap();
+        let _file = File::create(td.path().join("test_file.mp3")).unwrap();
+
         let mut l = Library::new(td.path());
-        assert_eq!(l.scan().unwrap(), 1);
-        // We don't add files unless they can be parsed as valid mp3/flac
-        assert!(l.files().is_empty());
+        assert_eq!(l.scan().unwrap().total_seen, 1);
+
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            scan_hidden_files: true,
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 2);
+
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            scan_hidden_dirs: true,
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 2);
+
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            scan_hidden_files: true,
+            scan_hidden_dirs: true,
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 3);
     }
 
     #[test]
-    fn test_library_scans_nested_dir_no_valid_files() {
+    fn test_library_scan_incremental_rereads_modified_files() {
         let td = TempDir::new("tempdir").unwrap();
-        create_dir(td.path().join("subdir_1")).unwrap();
-        let file_path = td.path().join("subdir_1").join("test_file.mp3");
-        let _file = File::create(file_path).unwrap();
-        create_dir(td.path().join("subdir_2")).unwrap();
-        let file_path = td.path().join("subdir_2").join("test_file.mp3");
-        let _file = File::create(file_path).unwrap();
-        create_dir(td.path().join("subdir_3")).unwrap();
-        let file_path = td.path().join("subdir_3").join("test_file.mp3");
-        let _file = File::create(file_path).unwrap();
-        create_dir(td.path().join("subdir_1").join("subsubdir_1")).unwrap();
-        let file_path = td
-            .path()
-            .join("subdir_1")
-            .join("subsubdir_1")
-            .join("test_file.mp3");
-        let _file = File::create(file_path).unwrap();
+        let path = td.path().join("test_file.mp3");
+        let _file = File::create(&path).unwrap();
         let mut l = Library::new(td.path());
-        assert_eq!(l.scan().unwrap(), 4);
-        // We don't add files unless they can be parsed as valid mp3/flac
+
+        // Pretend the file was loaded earlier. It isn't a valid mp3, so a real read would drop it.
+        let mut song = test_song(path.to_str().unwrap());
+        song.file_modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        l.files = vec![song];
+        assert_eq!(l.scan_incremental().unwrap().total_seen, 1);
+        assert_eq!(l.files().len(), 1);
+
+        // Once the mtime no longer matches, the tags are read again
+        l.files[0].file_modified = SystemTime::UNIX_EPOCH;
+        assert_eq!(l.scan_incremental().unwrap().total_seen, 1);
         assert!(l.files().is_empty());
     }
 
+    #[test]
+    fn test_library_scan_result() {
+        let td = TempDir::new("tempdir").unwrap();
+        File::create(td.path().join("empty.mp3")).unwrap();
+        File::create(td.path().join("song.ogg")).unwrap();
+        File::create(td.path().join("cover.jpg")).unwrap();
+        // The tag reader can't read WAVs, but they play, so they're loaded without tags
+        let wav = td.path().join("untagged.wav");
+        write_test_wav(&wav, &[0; 8000], 8000);
+        let mut l = Library::new(td.path());
+        let result = l.scan().unwrap();
+        assert_eq!(result.total_seen, 3);
+        assert_eq!(result.loaded, l.files().len());
+        assert_eq!(l.files()[0].file_path(), wav);
+        assert!(l.files()[0].title.is_none());
+        assert_eq!(l.files()[0].duration, Duration::from_secs(1));
+        assert_eq!(result.unsupported, [td.path().join("song.ogg")]);
+        assert_eq!(result.tag_errors.len(), 1);
+        assert_eq!(result.tag_errors[0].0, td.path().join("empty.mp3"));
+        assert_eq!(
+            result.summary(),
+            "Loaded 1 of 3 files (1 unreadable tags, 1 unsupported)"
+        );
+        assert_eq!(ScanResult::default().summary(), "Loaded 0 of 0 files");
+    }
+
+    #[test]
+    fn test_library_scan_skips_oversized_files() {
+        let td = TempDir::new("tempdir").unwrap();
+        let path = td.path().join("video.mp3");
+        std::fs::write(&path, [0; 16]).unwrap();
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            max_file_size: Some(8),
+            ..ScanOptions::default()
+        });
+        let result = l.scan().unwrap();
+        assert_eq!(result.oversized, [(path, 16)]);
+        // Skipped before its tags were read, so it isn't counted as unreadable too
+        assert!(result.tag_errors.is_empty());
+        assert_eq!(result.summary(), "Loaded 0 of 1 files (1 too large)");
+    }
+
+    #[test]
+    fn test_library_scan_file() {
+        use id3::TagLike;
+
+        let td = TempDir::new("tempdir").unwrap();
+        let write_song = |name: &str, artist: &str| {
+            let path = td.path().join(name);
+            File::create(&path).unwrap();
+            let mut tag = id3::Tag::new();
+            tag.set_artist(artist);
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            path
+        };
+        let mut l = Library::new(td.path());
+        l.set_files(vec![test_song("a.mp3"), test_song("b.mp3")]);
+        let b_ix = l.find_by_path(Path::new("b.mp3")).unwrap();
+
+        // Untagged test songs sort as "Unknown", so "Zed" goes after them
+        let path = write_song("new.mp3", "Zed");
+        let song = l.scan_file(&path).unwrap();
+        assert_eq!(song.artist(), Some("Zed"));
+        assert_eq!(l.files().len(), 3);
+        assert_eq!(l.find_by_path(&path), Some(2));
+        assert_eq!(l.find_by_path(Path::new("b.mp3")), Some(b_ix));
+
+        // Scanning it again with new tags replaces it and moves it to its new place
+        write_song("new.mp3", "Abba");
+        l.scan_file(&path).unwrap();
+        assert_eq!(l.files().len(), 3);
+        assert_eq!(l.find_by_path(&path), Some(0));
+        assert_eq!(l.files()[0].artist(), Some("Abba"));
+        assert_eq!(l.artists().len(), 1);
+
+        let missing = td.path().join("missing.mp3");
+        assert!(l.scan_file(&missing).is_err());
+        assert_eq!(l.files().len(), 3);
+    }
+
+    #[test]
+    fn test_library_scan_preserving_position() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut l = Library::new(td.path());
+        let mut songs = Vec::new();
+        for name in ["a.mp3", "b.mp3", "c.mp3"] {
+            let path = td.path().join(name);
+            File::create(&path).unwrap();
+            // Loaded earlier, as in test_library_scan_incremental_rereads_modified_files
+            let mut song = test_song(path.to_str().unwrap());
+            song.file_modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+            songs.push(song);
+        }
+        l.files = songs;
+
+        let playing = td.path().join("c.mp3");
+        std::fs::remove_file(td.path().join("a.mp3")).unwrap();
+        let (result, ix) = l.scan_preserving_position(Some(&playing)).unwrap();
+        assert_eq!(result.total_seen, 2);
+        assert_eq!(result.loaded, l.files().len());
+        assert_eq!(l.files()[ix.unwrap()].file_path(), playing);
+        // Both remaining songs are three minutes long
+        assert_eq!(l.total_duration(), Duration::from_mins(6));
+        l.remove_path(&playing);
+        assert_eq!(l.total_duration(), Duration::from_mins(3));
+
+        let gone = td.path().join("a.mp3");
+        assert_eq!(l.scan_preserving_position(Some(&gone)).unwrap().1, None);
+        assert_eq!(l.scan_preserving_position(None).unwrap().1, None);
+    }
+
+    #[test]
+    fn test_library_scan_depth_limit() {
+        let td = TempDir::new("tempdir").unwrap();
+        let shallow = td.path().join("a");
+        let deep = shallow.join("b");
+        create_dir(&shallow).unwrap();
+        create_dir(&deep).unwrap();
+        let _file = File::create(shallow.join("test_file.mp3")).unwrap();
+        let _file = File::create(deep.join("test_file.mp3")).unwrap();
+
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            max_scan_depth: 2,
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 1);
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            max_scan_depth: 3,
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_library_scan_follow_mounts() {
+        // /dev/shm is usually its own tmpfs, so a link to it crosses onto another filesystem
+        let Ok(other_fs) = TempDir::new_in("/dev/shm", "tempdir") else {
+            return;
+        };
+        let td = TempDir::new("tempdir").unwrap();
+        if device_id(other_fs.path()) == device_id(td.path()) {
+            return;
+        }
+        File::create(other_fs.path().join("song.mp3")).unwrap();
+        std::os::unix::fs::symlink(other_fs.path(), td.path().join("mounted")).unwrap();
+
+        let mut l = Library::new(td.path());
+        assert_eq!(l.scan().unwrap().total_seen, 1);
+        let mut l = Library::new(td.path()).with_scan_options(ScanOptions {
+            follow_mounts: false,
+            ..ScanOptions::default()
+        });
+        assert_eq!(l.scan().unwrap().total_seen, 0);
+    }
+
+    #[test]
+    fn test_search_keeps_selection_in_results() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.files = ["Alpha", "Beta", "Gamma"]
+            .into_iter()
+            .map(|title| {
+                let mut song = test_song(&format!("{title}.mp3"));
+                song.title = Some(title.to_string());
+                song
+            })
+            .collect();
+        app.library
+            .set_sort(Column::Artist, SortDirection::Ascending);
+        app.app_state.selected_file_ix = 2;
+
+        app.handle_file_list_key(KeyEvent::from(KeyCode::Char('/')))
+            .unwrap();
+        app.handle_search_key(KeyEvent::from(KeyCode::Char('b')))
+            .unwrap();
+        assert_eq!(app.visible_indices().len(), 1);
+        assert_eq!(app.selected_file_ix(), 0);
+        // Clearing the search goes back to where the selection was before it
+        app.handle_search_key(KeyEvent::from(KeyCode::Backspace))
+            .unwrap();
+        assert_eq!(app.selected_file_ix(), 2);
+    }
+
+    #[test]
+    fn test_navigating_empty_library() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library = Library::new(td.path());
+        for code in [KeyCode::Down, KeyCode::Up, KeyCode::Down] {
+            app.handle_file_list_key(KeyEvent::from(code)).unwrap();
+            assert_eq!(app.selected_file_ix(), 0);
+        }
+        app.handle_file_list_key(KeyEvent::from(KeyCode::Enter))
+            .unwrap();
+        assert!(app.active_song().is_none());
+        assert_eq!(
+            app.status_message(),
+            Some("Library is empty. Press 'Ctrl+s' to scan.")
+        );
+    }
+
+    #[test]
+    fn test_play_history_is_kept_during_shuffle() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.app_state.active_song = Some(test_song("a.mp3"));
+        app.remember_shuffle_context();
+        assert!(app.app_state.play_history.is_empty());
+
+        app.app_state.playback_mode = PlaybackMode::Shuffle;
+        for ix in 0..=PLAY_HISTORY_LEN {
+            app.app_state.playing_file_ix = ix;
+            app.remember_shuffle_context();
+        }
+        let history = &app.app_state.play_history;
+        assert_eq!(history.len(), PLAY_HISTORY_LEN);
+        // The oldest song made way for the newest
+        assert_eq!(history[0].0, 1);
+        assert_eq!(history[PLAY_HISTORY_LEN - 1].0, PLAY_HISTORY_LEN);
+
+        app.app_state.play_history.clear();
+        app.undo_play().unwrap();
+        assert_eq!(app.status_message(), Some("Nothing to go back to"));
+    }
+
+    #[test]
+    fn test_batch_play_checks_indices() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.files = vec![test_song("a.mp3"), test_song("b.mp3")];
+        app.queue.push(1);
+        let e = app.batch_play(&[1, 2, 0, 5]).unwrap_err();
+        assert_eq!(e.to_string(), "Library indices out of range: 2, 5");
+        assert_eq!(app.queue.iter().collect::<Vec<_>>(), [1]);
+        app.batch_play(&[]).unwrap();
+        assert_eq!(app.queue.len(), 1);
+    }
+
+    #[test]
+    fn test_format_long_duration() {
+        let secs = |s| format_long_duration(Duration::from_secs(s));
+        assert_eq!(secs(0), "0m");
+        assert_eq!(secs(59), "0m");
+        assert_eq!(secs(23 * 60 + 5), "23m");
+        assert_eq!(secs(3600), "1h 0m");
+        assert_eq!(secs((42 * 24 + 7) * 3600 + 23 * 60), "42d 7h 23m");
+    }
+
+    #[test]
+    fn test_active_song_index() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library
+            .set_files(vec![test_song("a.mp3"), test_song("b.mp3")]);
+        app.app_state.playing_file_ix = 1;
+        assert_eq!(app.active_song_index(), None);
+        app.app_state.active_song = Some(test_song("b.mp3"));
+        assert_eq!(app.active_song_index(), Some(1));
+    }
+
+    #[test]
+    fn test_removed_song_leaves_cached_views() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        app.app_state.view = LibraryView::DiscoveryDate(vec![2, 0, 1]);
+        app.queue.push(2);
+
+        app.handle_removed_path(Path::new("b.mp3")).unwrap();
+        assert_eq!(app.visible_indices(), [1, 0]);
+        assert_eq!(app.queue.iter().collect::<Vec<_>>(), [1]);
+        app.handle_removed_path(Path::new("c.mp3")).unwrap();
+        assert_eq!(app.visible_indices(), [0]);
+        assert!(app.queue.is_empty());
+    }
+
+    #[test]
+    fn test_library_changes_remap_cached_views() {
+        use id3::TagLike;
+
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library
+            .set_files(vec![test_song("a.mp3"), test_song("b.mp3")]);
+        app.app_state.view = LibraryView::MissingCovers(vec![1]);
+        app.app_state.play_history = vec![(1, PlaybackMode::Normal)];
+
+        // "Abba" sorts ahead of the untagged songs, moving them both along
+        let path = td.path().join("new.mp3");
+        File::create(&path).unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_artist("Abba");
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+        app.add_file(path.to_str().unwrap());
+        assert_eq!(app.library.find_by_path(Path::new("b.mp3")), Some(2));
+        assert_eq!(app.visible_indices(), [2]);
+        assert_eq!(app.app_state.play_history, [(2, PlaybackMode::Normal)]);
+
+        // Rescanning finds only the new song, so the view loses the others
+        app.reload_library().unwrap();
+        assert!(app.visible_indices().is_empty());
+        assert!(app.app_state.play_history.is_empty());
+    }
+
+    #[test]
+    fn test_rescan_without_playing_song_plays_on() {
+        let td = TempDir::new("tempdir").unwrap();
+        let kept = td.path().join("kept.wav");
+        write_test_wav(&kept, &[0; 800], 8000);
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.set_files(vec![
+            test_song("gone.mp3"),
+            test_song(kept.to_str().unwrap()),
+        ]);
+        app.app_state.active_song = Some(test_song("gone.mp3"));
+        app.app_state.playing_file_ix = 0;
+        app.reload_library().unwrap();
+        assert_eq!(app.app_state.playing_file_ix, 0);
+        assert_eq!(
+            app.active_song().map(SongInfo::file_path),
+            Some(kept.as_path())
+        );
+
+        // Nothing after it is left, so playback stops
+        app.library.set_files(vec![
+            test_song(kept.to_str().unwrap()),
+            test_song("gone.mp3"),
+        ]);
+        app.app_state.active_song = Some(test_song("gone.mp3"));
+        app.app_state.playing_file_ix = 1;
+        app.reload_library().unwrap();
+        assert!(app.active_song().is_none());
+        assert_eq!(app.app_state.playing_file_ix, 0);
+        assert!(app.am.is_paused());
+    }
+
+    #[test]
+    fn test_removed_song_leaves_play_histories() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        app.app_state.play_history = vec![(2, PlaybackMode::Normal), (1, PlaybackMode::Shuffle)];
+        app.app_state.shuffle_history = VecDeque::from([2, 1, 0]);
+        app.app_state.upcoming_shuffle_ix = Some(1);
+
+        app.handle_removed_path(Path::new("b.mp3")).unwrap();
+        assert_eq!(app.app_state.play_history, [(1, PlaybackMode::Normal)]);
+        assert_eq!(app.app_state.shuffle_history, [1, 0]);
+        assert_eq!(app.app_state.upcoming_shuffle_ix, None);
+
+        // A stale entry is dropped rather than played
+        app.app_state.play_history.push((5, PlaybackMode::Normal));
+        app.undo_play().unwrap();
+        assert!(app.app_state.active_song.is_none());
+        assert_eq!(app.status_message(), Some("Nothing to go back to"));
+    }
+
+    #[test]
+    fn test_song_ends_when_audio_runs_out() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library
+            .set_files(vec![test_song("a.mp3"), test_song("b.mp3")]);
+        app.app_state.active_song = Some(test_song("a.mp3"));
+        app.am.position = Duration::from_secs(10);
+        app.advance_if_finished().unwrap();
+        assert_eq!(app.app_state.playing_file_ix, 0);
+
+        // Tagged as three minutes long, but its audio stops early
+        app.am.finished = true;
+        app.advance_if_finished().unwrap();
+        assert_eq!(app.app_state.playing_file_ix, 1);
+        assert_eq!(app.am.active_path.as_deref(), Some(Path::new("b.mp3")));
+        assert!(!app.am.finished);
+    }
+
+    #[test]
+    fn test_normal_playback_follows_sort() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        app.library
+            .set_sort(Column::Track, SortDirection::Descending);
+        app.library.files[0].track.0 = Some(1);
+        app.library.files[1].track.0 = Some(3);
+        app.library.files[2].track.0 = Some(2);
+        app.library.resort();
+        // Shown as b, c, a
+        app.app_state.playing_file_ix = 1;
+        app.app_state.active_song = Some(test_song("b.mp3"));
+        assert_eq!(
+            app.next_song_info().unwrap().file_path(),
+            Path::new("c.mp3")
+        );
+        app.am.finished = true;
+        app.advance_if_finished().unwrap();
+        assert_eq!(app.app_state.playing_file_ix, 2);
+        assert_eq!(
+            app.next_song_info().unwrap().file_path(),
+            Path::new("a.mp3")
+        );
+        app.am.finished = true;
+        app.advance_if_finished().unwrap();
+        assert!(app.next_song_info().is_none());
+    }
+
+    #[test]
+    fn test_next_song_info() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library.set_files(vec![
+            test_song("a.mp3"),
+            test_song("b.mp3"),
+            test_song("c.mp3"),
+        ]);
+        assert!(app.next_song_info().is_none());
+
+        app.app_state.active_song = Some(test_song("a.mp3"));
+        let next =
+            |app: &PlayerApp<MockAudioManager>| app.next_song_info().map(|s| s.file_path.clone());
+        assert_eq!(next(&app), Some(PathBuf::from("b.mp3")));
+        app.queue.push(2);
+        assert_eq!(next(&app), Some(PathBuf::from("c.mp3")));
+        app.queue.clear();
+
+        // Nothing follows the last song
+        app.app_state.playing_file_ix = 2;
+        app.app_state.active_song = Some(test_song("c.mp3"));
+        assert!(app.next_song_info().is_none());
+
+        // Looking doesn't pick the next shuffled song, which is left to `update`
+        app.toggle_playback_mode();
+        assert!(app.next_song_info().is_none());
+        app.app_state.upcoming_shuffle_ix = Some(0);
+        assert_eq!(next(&app), Some(PathBuf::from("a.mp3")));
+        assert_eq!(next(&app), Some(PathBuf::from("a.mp3")));
+    }
+
+    #[test]
+    fn test_volume_is_clamped() {
+        assert!(f32::from(Volume::new(-0.5)).abs() < f32::EPSILON);
+        assert!((f32::from(Volume::new(1.5)) - 1.0).abs() < f32::EPSILON);
+        assert!(f32::from(Volume::new(f32::NAN)).abs() < f32::EPSILON);
+        assert_eq!(Volume::MAX + 0.01, Volume::MAX);
+        assert_eq!(Volume::new(0.005) - 0.01, Volume::new(0.0));
+
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.volume_up();
+        assert_eq!(app.volume(), Volume::MAX);
+        app.am.set_volume(Volume::new(0.0));
+        app.volume_down();
+        assert_eq!(app.volume(), Volume::new(0.0));
+    }
+
+    #[test]
+    fn test_seek_to_fraction() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        assert!(app.seek_to_fraction(0.5).is_err());
+
+        app.app_state.active_song = Some(test_song("a.mp3"));
+        app.seek_to_fraction(0.5).unwrap();
+        assert_eq!(app.am.position(), Duration::from_secs(90));
+        app.seek_to_fraction(2.0).unwrap();
+        assert_eq!(app.am.position(), Duration::from_mins(3));
+        assert!(app.seek_to_fraction(f64::NAN).is_err());
+
+        app.handle_file_list_key(KeyEvent::from(KeyCode::Char('3')))
+            .unwrap();
+        assert_eq!(app.am.position(), Duration::from_secs(54));
+        app.am.seek_backward();
+        assert_eq!(app.am.position(), Duration::from_secs(53));
+    }
+
+    #[test]
+    fn test_pause_on_focus_loss() {
+        let td = TempDir::new("tempdir").unwrap();
+        let config = Config {
+            pause_on_focus_loss: true,
+            ..Config::default()
+        };
+        let mut app = test_app(td.path(), config).unwrap();
+        app.app_state.active_song = Some(test_song("a.mp3"));
+        app.am.play();
+        app.handle_focus_change(false);
+        assert!(!app.is_playing());
+        app.handle_focus_change(true);
+        assert!(app.is_playing());
+
+        // Songs paused by hand stay paused
+        app.am.pause();
+        app.handle_focus_change(false);
+        app.handle_focus_change(true);
+        assert!(!app.is_playing());
+    }
+
+    #[test]
+    fn test_accepts_repeat() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        let key = |code| KeyEvent::from(code);
+        assert!(app.accepts_repeat(key(KeyCode::Down)));
+        assert!(!app.accepts_repeat(key(KeyCode::Char('q'))));
+        assert!(!app.accepts_repeat(key(KeyCode::Enter)));
+        let (code, modifiers) = app.config.keybindings.binding(Action::VolumeUp);
+        assert!(app.accepts_repeat(KeyEvent::new(code, modifiers)));
+        let (code, modifiers) = app.config.keybindings.binding(Action::Skip);
+        assert!(!app.accepts_repeat(KeyEvent::new(code, modifiers)));
+
+        app.app_state.ui_mode = AppUiMode::SearchPopup;
+        assert!(app.accepts_repeat(key(KeyCode::Char('q'))));
+        assert!(app.accepts_repeat(key(KeyCode::Backspace)));
+        assert!(!app.accepts_repeat(key(KeyCode::Enter)));
+    }
+
+    #[test]
+    fn test_key_matches() {
+        let shift_f = KeyEvent::new(KeyCode::Char('F'), KeyModifiers::SHIFT);
+        assert!(key_matches(shift_f, KeyCode::Char('F'), KeyModifiers::NONE));
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(key_matches(
+            ctrl_c,
+            KeyCode::Char('c'),
+            KeyModifiers::CONTROL
+        ));
+        assert!(!key_matches(ctrl_c, KeyCode::Char('c'), KeyModifiers::NONE));
+        let shift_up = KeyEvent::new(KeyCode::Up, KeyModifiers::SHIFT);
+        assert!(!key_matches(shift_up, KeyCode::Up, KeyModifiers::NONE));
+
+        // Every fixed key does one thing
+        let keys = file_list_keys::<MockAudioManager>();
+        for (i, (code, modifiers, _)) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..]
+                .iter()
+                .any(|(c, m, _)| c == code && m == modifiers));
+        }
+    }
+
+    #[test]
+    fn test_report_config_problems() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.report_config_problems(&[]);
+        assert_eq!(app.status_message(), None);
+        let problems = [
+            String::from("Config line 1: unknown setting 'volume'"),
+            String::from("Config line 2: expected `key = value`"),
+        ];
+        app.report_config_problems(&problems[..1]);
+        assert_eq!(app.status_message(), Some(problems[0].as_str()));
+        app.report_config_problems(&problems);
+        assert_eq!(
+            app.status_message(),
+            Some("Config line 1: unknown setting 'volume' (and 1 more, see the log)")
+        );
+    }
+
+    #[test]
+    fn test_file_list_keys() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        let press = |app: &mut PlayerApp<MockAudioManager>, code, modifiers| {
+            app.handle_file_list_key(KeyEvent::new(code, modifiers))
+                .unwrap();
+        };
+        press(&mut app, KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(*app.ui_mode() == AppUiMode::ColumnConfig);
+        app.app_state.ui_mode = AppUiMode::FileList;
+        // Only the missing covers view has covers to add
+        press(&mut app, KeyCode::Char('c'), KeyModifiers::NONE);
+        assert!(*app.ui_mode() == AppUiMode::FileList);
+        press(&mut app, KeyCode::Char('f'), KeyModifiers::NONE);
+        assert!(*app.playback_mode() == PlaybackMode::Shuffle);
+        press(&mut app, KeyCode::Char('t'), KeyModifiers::CONTROL);
+        assert!(app.app_state.view == LibraryView::MostPlayed);
+        press(&mut app, KeyCode::Char(':'), KeyModifiers::SHIFT);
+        assert!(*app.ui_mode() == AppUiMode::CommandPrompt);
+        app.app_state.ui_mode = AppUiMode::FileList;
+        press(&mut app, KeyCode::Char('q'), KeyModifiers::NONE);
+        assert!(!app.is_alive());
+    }
+
+    #[test]
+    fn test_toggle_playback_without_audio_hardware() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        let p = KeyEvent::from(KeyCode::Char('p'));
+        // Nothing to toggle until a song has played
+        app.handle_file_list_key(p).unwrap();
+        assert!(!app.is_playing());
+
+        app.app_state.active_song = Some(test_song("a.mp3"));
+        app.handle_file_list_key(p).unwrap();
+        assert!(app.is_playing());
+        app.handle_file_list_key(p).unwrap();
+        assert!(app.audio_manager().paused);
+    }
+
+    #[test]
+    fn test_snapshot_follows_keys() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        app.library
+            .set_files(vec![test_song("a.mp3"), test_song("b.mp3")]);
+        let before = app.snapshot();
+        assert_eq!(before.library.len(), 2);
+        assert!(before.active_song.is_none());
+
+        app.handle_file_list_key(KeyEvent::from(KeyCode::Down))
+            .unwrap();
+        app.handle_file_list_key(KeyEvent::from(KeyCode::Char('f')))
+            .unwrap();
+        let after = app.snapshot();
+        assert_eq!(after.selected_file_ix, before.selected_file_ix + 1);
+        assert_eq!(after.playback_mode, PlaybackMode::Shuffle);
+        assert_eq!(after.playing_file_ix, before.playing_file_ix);
+        assert_eq!(after.volume, before.volume);
+    }
+
+    #[test]
+    fn test_duplicate_deletion_confirmation() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(td.path(), Config::default()).unwrap();
+        let first = td.path().join("a.mp3");
+        let second = td.path().join("b.mp3");
+        File::create(&first).unwrap();
+        File::create(&second).unwrap();
+        app.app_state.duplicates = vec![((first.clone(), 0), (second.clone(), 0))];
+        app.app_state.ui_mode = AppUiMode::Duplicates;
+
+        app.handle_duplicates_key(KeyEvent::from(KeyCode::Char('2')));
+        assert!(*app.ui_mode() == AppUiMode::ConfirmDelete);
+        assert_eq!(app.pending_delete(), Some(second.as_path()));
+        app.handle_confirm_delete_key(KeyEvent::from(KeyCode::Enter));
+        assert!(*app.ui_mode() == AppUiMode::Duplicates);
+        assert!(second.exists());
+
+        app.handle_duplicates_key(KeyEvent::from(KeyCode::Char('2')));
+        app.handle_confirm_delete_key(KeyEvent::from(KeyCode::Char('y')));
+        assert!(!second.exists());
+        assert!(first.exists());
+        assert!(app.duplicates().is_empty());
+        assert!(app.pending_delete().is_none());
+    }
+
+    #[test]
+    fn test_rescan_confirmation() {
+        let td = TempDir::new("tempdir").unwrap();
+        let mut app = test_app(
+            td.path(),
+            Config {
+                confirm_rescan: true,
+                ..Config::default()
+            },
+        )
+        .unwrap();
+        File::create(td.path().join("new.mp3")).unwrap();
+        let ctrl_s = KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL);
+
+        app.handle_file_list_key(ctrl_s).unwrap();
+        assert!(*app.ui_mode() == AppUiMode::ConfirmRescan);
+        app.handle_confirm_rescan_key(KeyEvent::from(KeyCode::Char('n')))
+            .unwrap();
+        assert!(*app.ui_mode() == AppUiMode::FileList);
+        // A bare 's' no longer rescans
+        app.handle_file_list_key(KeyEvent::from(KeyCode::Char('s')))
+            .unwrap();
+        assert!(*app.ui_mode() == AppUiMode::FileList);
+
+        app.handle_file_list_key(ctrl_s).unwrap();
+        app.handle_confirm_rescan_key(KeyEvent::from(KeyCode::Char('y')))
+            .unwrap();
+        assert!(*app.ui_mode() == AppUiMode::FileList);
+        // The new file was scanned, though it isn't a readable song
+        assert_eq!(app.library().unreadable_files, [td.path().join("new.mp3")]);
+    }
+
+    #[test]
+    fn test_counting_source() {
+        let samples = Arc::new(AtomicU64::new(0));
+        // One second of stereo audio at 10 Hz
+        let buffer = rodio::buffer::SamplesBuffer::new(2, 10, vec![0i16; 20]);
+        let mut source = CountingSource::new(buffer, samples.clone());
+        assert_eq!(source.by_ref().take(6).count(), 6);
+        assert_eq!(samples.load(Ordering::Relaxed), 6);
+        source.try_seek(Duration::from_millis(500)).unwrap();
+        assert_eq!(samples.load(Ordering::Relaxed), 10);
+        assert_eq!(source.count(), 10);
+        assert_eq!(samples.load(Ordering::Relaxed), 20);
+    }
+
     #[test]
     fn test_audio_manager_toggle_playback() {
         let mut am = AudioManager::new().unwrap();
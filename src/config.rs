@@ -0,0 +1,1101 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use eyre::Result;
+use ratatui::style::Color;
+
+use crate::app::{PlaybackMode, ScanOptions, Volume, DEFAULT_SCAN_EXTENSIONS};
+
+/// A column of the file list.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Column {
+    Track,
+    Title,
+    Artist,
+    Album,
+    Year,
+    Genre,
+    Duration,
+}
+
+impl Column {
+    /// Every column, in the order they're shown.
+    pub const ALL: [Column; 7] = [
+        Column::Track,
+        Column::Title,
+        Column::Artist,
+        Column::Album,
+        Column::Year,
+        Column::Genre,
+        Column::Duration,
+    ];
+
+    pub fn header(self) -> &'static str {
+        match self {
+            Column::Track => "#",
+            Column::Title => "Title",
+            Column::Artist => "Artist",
+            Column::Album => "Album",
+            Column::Year => "Year",
+            Column::Genre => "Genre",
+            Column::Duration => "Length",
+        }
+    }
+
+    /// Name used to refer to the column in commands and settings.
+    pub fn key(self) -> &'static str {
+        match self {
+            Column::Track => "track",
+            Column::Title => "title",
+            Column::Artist => "artist",
+            Column::Album => "album",
+            Column::Year => "year",
+            Column::Genre => "genre",
+            Column::Duration => "length",
+        }
+    }
+
+    /// The column whose [`Self::key`] is `key`.
+    pub fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.key() == key)
+    }
+
+    /// Longer name for the column toggle list, where `#` alone isn't clear.
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Track => "Track number",
+            Column::Duration => "Length",
+            _ => self.header(),
+        }
+    }
+}
+
+/// Which way the file list is sorted.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+impl SortDirection {
+    pub fn key(self) -> &'static str {
+        match self {
+            SortDirection::Ascending => "asc",
+            SortDirection::Descending => "desc",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "asc" => Some(SortDirection::Ascending),
+            "desc" => Some(SortDirection::Descending),
+            _ => None,
+        }
+    }
+}
+
+/// Which columns the file list shows.
+#[derive(Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ColumnConfig {
+    pub track: bool,
+    pub title: bool,
+    pub artist: bool,
+    pub album: bool,
+    pub year: bool,
+    pub genre: bool,
+    pub duration: bool,
+}
+
+impl Default for ColumnConfig {
+    fn default() -> Self {
+        Self {
+            track: true,
+            title: true,
+            artist: true,
+            album: true,
+            year: true,
+            genre: false,
+            duration: true,
+        }
+    }
+}
+
+impl ColumnConfig {
+    fn flag(&mut self, column: Column) -> &mut bool {
+        match column {
+            Column::Track => &mut self.track,
+            Column::Title => &mut self.title,
+            Column::Artist => &mut self.artist,
+            Column::Album => &mut self.album,
+            Column::Year => &mut self.year,
+            Column::Genre => &mut self.genre,
+            Column::Duration => &mut self.duration,
+        }
+    }
+
+    pub fn is_visible(mut self, column: Column) -> bool {
+        *self.flag(column)
+    }
+
+    pub fn toggle(&mut self, column: Column) {
+        let flag = self.flag(column);
+        *flag = !*flag;
+    }
+
+    /// The visible columns, in display order.
+    pub fn visible(self) -> Vec<Column> {
+        Column::ALL
+            .into_iter()
+            .filter(|c| self.is_visible(*c))
+            .collect()
+    }
+}
+
+/// Share of the file list's width each column gets, relative to the others.
+#[derive(Clone, Copy)]
+pub struct ColumnWidths {
+    pub track: u16,
+    pub title: u16,
+    pub artist: u16,
+    pub album: u16,
+    pub year: u16,
+    pub genre: u16,
+    pub duration: u16,
+}
+
+impl Default for ColumnWidths {
+    fn default() -> Self {
+        Self {
+            track: 1,
+            title: 5,
+            artist: 5,
+            album: 5,
+            year: 1,
+            genre: 3,
+            duration: 2,
+        }
+    }
+}
+
+impl ColumnWidths {
+    /// Largest weight a column can be given.
+    const MAX_WEIGHT: u16 = 20;
+
+    fn weight_mut(&mut self, column: Column) -> &mut u16 {
+        match column {
+            Column::Track => &mut self.track,
+            Column::Title => &mut self.title,
+            Column::Artist => &mut self.artist,
+            Column::Album => &mut self.album,
+            Column::Year => &mut self.year,
+            Column::Genre => &mut self.genre,
+            Column::Duration => &mut self.duration,
+        }
+    }
+
+    pub fn weight(mut self, column: Column) -> u16 {
+        *self.weight_mut(column)
+    }
+
+    /// Widen (or with `wider` false, narrow) `column` by one step. Columns never get narrower
+    /// than a weight of 1.
+    pub fn adjust(&mut self, column: Column, wider: bool) {
+        let weight = self.weight_mut(column);
+        *weight = if wider {
+            (*weight + 1).min(Self::MAX_WEIGHT)
+        } else {
+            weight.saturating_sub(1).max(1)
+        };
+    }
+}
+
+/// A key press, with the modifiers that have to be held with it.
+pub type KeyBinding = (KeyCode, KeyModifiers);
+
+/// `binding` as it's written in hints and messages, like `Ctrl+s` or `Shift+Right`.
+pub fn describe_binding((code, modifiers): KeyBinding) -> String {
+    let key = match code {
+        KeyCode::Char(c) => c.to_string(),
+        code => format!("{code:?}"),
+    };
+    [
+        (KeyModifiers::CONTROL, "Ctrl"),
+        (KeyModifiers::ALT, "Alt"),
+        (KeyModifiers::SHIFT, "Shift"),
+    ]
+    .into_iter()
+    .filter(|(modifier, _)| modifiers.contains(*modifier))
+    .map(|(_, name)| name)
+    .chain([key.as_str()])
+    .collect::<Vec<_>>()
+    .join("+")
+}
+
+/// Parse a binding written the way [`describe_binding`] writes it, like `Ctrl+s` or
+/// `Shift+Right`.
+pub fn parse_binding(s: &str) -> Option<KeyBinding> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut key = s;
+    // An empty key after the `+` means the key is `+` itself
+    while let Some((modifier, rest)) = key.split_once('+').filter(|(_, rest)| !rest.is_empty()) {
+        modifiers |= match modifier {
+            "Ctrl" => KeyModifiers::CONTROL,
+            "Alt" => KeyModifiers::ALT,
+            "Shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        key = rest;
+    }
+    let mut chars = key.chars();
+    let code = match (chars.next()?, chars.next()) {
+        (c, None) => KeyCode::Char(c),
+        _ => match key {
+            "Left" => KeyCode::Left,
+            "Right" => KeyCode::Right,
+            "Up" => KeyCode::Up,
+            "Down" => KeyCode::Down,
+            "Home" => KeyCode::Home,
+            "End" => KeyCode::End,
+            "PageUp" => KeyCode::PageUp,
+            "PageDown" => KeyCode::PageDown,
+            "Enter" => KeyCode::Enter,
+            "Esc" => KeyCode::Esc,
+            "Tab" => KeyCode::Tab,
+            "Backspace" => KeyCode::Backspace,
+            "Delete" => KeyCode::Delete,
+            "Insert" => KeyCode::Insert,
+            _ => {
+                let n = key.strip_prefix('F')?.trim_matches(['(', ')']);
+                KeyCode::F(n.parse().ok()?)
+            }
+        },
+    };
+    Some((code, modifiers))
+}
+
+/// Something a key in the file list can be bound to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    VolumeUp,
+    VolumeDown,
+    SeekForward,
+    SeekBackward,
+    Skip,
+    Rescan,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::VolumeUp,
+        Action::VolumeDown,
+        Action::SeekForward,
+        Action::SeekBackward,
+        Action::Skip,
+        Action::Rescan,
+    ];
+
+    /// The action whose [`Self::name`] is `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::VolumeUp => "volume_up",
+            Action::VolumeDown => "volume_down",
+            Action::SeekForward => "seek_forward",
+            Action::SeekBackward => "seek_backward",
+            Action::Skip => "skip",
+            Action::Rescan => "rescan",
+        }
+    }
+
+    /// Whether holding the key down keeps running the action. Skips and rescans can't be
+    /// taken back, so each one needs a fresh press.
+    pub fn repeats(self) -> bool {
+        matches!(
+            self,
+            Action::VolumeUp | Action::VolumeDown | Action::SeekForward | Action::SeekBackward
+        )
+    }
+}
+
+/// Keys bound to each [`Action`].
+#[derive(Clone, Copy)]
+pub struct Keybindings {
+    pub volume_up: KeyBinding,
+    pub volume_down: KeyBinding,
+    pub seek_forward: KeyBinding,
+    pub seek_backward: KeyBinding,
+    pub skip: KeyBinding,
+    /// Rescan the library. Not a bare letter by default, since a scan can be slow to sit through
+    /// when pressed by mistake.
+    pub rescan: KeyBinding,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            volume_up: (KeyCode::Char('='), KeyModifiers::NONE),
+            volume_down: (KeyCode::Char('-'), KeyModifiers::NONE),
+            seek_forward: (KeyCode::Right, KeyModifiers::NONE),
+            seek_backward: (KeyCode::Left, KeyModifiers::NONE),
+            skip: (KeyCode::Right, KeyModifiers::SHIFT),
+            rescan: (KeyCode::Char('s'), KeyModifiers::CONTROL),
+        }
+    }
+}
+
+/// Two actions bound to the same key. Only one of them could ever run.
+#[derive(Debug, PartialEq)]
+pub struct BindingConflict {
+    pub binding: KeyBinding,
+    pub actions: (Action, Action),
+}
+
+impl fmt::Display for BindingConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Keybinding conflict: '{}' bound to both {} and {}",
+            describe_binding(self.binding),
+            self.actions.0.name(),
+            self.actions.1.name()
+        )
+    }
+}
+
+impl Keybindings {
+    fn binding_mut(&mut self, action: Action) -> &mut KeyBinding {
+        match action {
+            Action::VolumeUp => &mut self.volume_up,
+            Action::VolumeDown => &mut self.volume_down,
+            Action::SeekForward => &mut self.seek_forward,
+            Action::SeekBackward => &mut self.seek_backward,
+            Action::Skip => &mut self.skip,
+            Action::Rescan => &mut self.rescan,
+        }
+    }
+
+    pub fn binding(mut self, action: Action) -> KeyBinding {
+        *self.binding_mut(action)
+    }
+
+    /// The action bound to `key`, if any.
+    pub fn action_for(self, key: KeyEvent) -> Option<Action> {
+        Action::ALL
+            .into_iter()
+            .find(|a| self.binding(*a) == (key.code, key.modifiers))
+    }
+
+    /// Every pair of actions that share a key.
+    pub fn validate(self) -> Vec<BindingConflict> {
+        let mut conflicts = Vec::new();
+        for (i, first) in Action::ALL.into_iter().enumerate() {
+            for second in Action::ALL.into_iter().skip(i + 1) {
+                if self.binding(first) == self.binding(second) {
+                    conflicts.push(BindingConflict {
+                        binding: self.binding(first),
+                        actions: (first, second),
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Put every action involved in a conflict back on its default key. Returns the conflicts
+    /// that were found.
+    pub fn reset_conflicts(&mut self) -> Vec<BindingConflict> {
+        let conflicts = self.validate();
+        let defaults = Self::default();
+        for conflict in &conflicts {
+            for action in [conflict.actions.0, conflict.actions.1] {
+                *self.binding_mut(action) = defaults.binding(action);
+            }
+        }
+        conflicts
+    }
+}
+
+/// A search that can be recalled by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchPreset {
+    pub query: String,
+    pub case_sensitive: bool,
+}
+
+/// A named library with its own playback settings, for keeping separate collections apart.
+#[derive(Clone)]
+pub struct ProfileConfig {
+    /// Directories scanned for this profile's library.
+    pub root_dirs: Vec<PathBuf>,
+    pub playback_mode: PlaybackMode,
+    /// Volume to switch to.
+    pub volume: Volume,
+}
+
+/// User-tunable settings for the player.
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+    /// How many recently shuffled songs to avoid repeating. The window is further capped at half
+    /// the library size so small libraries still have songs to choose from.
+    pub shuffle_avoid_recent: usize,
+    /// Skip leading and trailing silence when playing a track.
+    pub skip_silence: bool,
+    /// Follow symbolic links while scanning the library.
+    pub follow_symlinks: bool,
+    /// Scan directories that are mount points for other filesystems. Has no effect off Unix.
+    pub scan_follow_mounts: bool,
+    /// How many directory levels below the root to scan.
+    pub max_scan_depth: usize,
+    /// Extensions of the files to scan for songs, without the dot.
+    pub scan_extensions: Vec<String>,
+    /// Scan files whose names start with `.`.
+    pub scan_hidden_files: bool,
+    /// Scan directories whose names start with `.`.
+    pub scan_hidden_dirs: bool,
+    /// Skip files bigger than this many megabytes when scanning, or scan any size if `None`.
+    pub max_file_size_mb: Option<u64>,
+    /// Refuse to queue a song that's already in the queue.
+    pub deduplicate_queue: bool,
+    /// Columns shown in the file list.
+    pub columns: ColumnConfig,
+    pub column_widths: ColumnWidths,
+    /// Column the library is sorted by.
+    pub sort_key: Column,
+    pub sort_direction: SortDirection,
+    /// Library profiles that `:profile` can switch between, by name.
+    pub profiles: HashMap<String, ProfileConfig>,
+    pub keybindings: Keybindings,
+    /// Colour to draw the file list rows of each genre in. Genres are matched
+    /// case-insensitively.
+    pub genre_colors: HashMap<String, Color>,
+    /// Searches saved with `:search-save`, by name.
+    pub saved_searches: HashMap<String, SearchPreset>,
+    /// How many albums' drawn cover art to keep around.
+    pub art_cache_size: usize,
+    /// Ask before rescanning the library.
+    pub confirm_rescan: bool,
+    /// Pause while the terminal doesn't have focus, and carry on once it's back.
+    pub pause_on_focus_loss: bool,
+    /// Write songs in exported M3U playlists relative to the playlist, so the playlist still
+    /// works when the music is moved along with it.
+    pub m3u_use_relative_paths: bool,
+    /// Show the playing song in the terminal's window title.
+    pub set_title: bool,
+    /// Clear the window title on exit, if it was set.
+    pub restore_title: bool,
+    /// Longest to wait for a key press before redrawing, in milliseconds. Lower values redraw
+    /// the playback bar more smoothly but use more CPU.
+    pub event_poll_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            shuffle_avoid_recent: 20,
+            skip_silence: false,
+            follow_symlinks: true,
+            scan_follow_mounts: true,
+            max_scan_depth: 10,
+            scan_extensions: DEFAULT_SCAN_EXTENSIONS.map(String::from).to_vec(),
+            scan_hidden_files: false,
+            scan_hidden_dirs: false,
+            max_file_size_mb: None,
+            deduplicate_queue: true,
+            columns: ColumnConfig::default(),
+            column_widths: ColumnWidths::default(),
+            sort_key: Column::Artist,
+            sort_direction: SortDirection::Ascending,
+            profiles: HashMap::new(),
+            keybindings: Keybindings::default(),
+            genre_colors: [
+                ("Rock", Color::Red),
+                ("Jazz", Color::Blue),
+                ("Classical", Color::Magenta),
+                ("Electronic", Color::Cyan),
+                ("Hip-Hop", Color::Green),
+            ]
+            .into_iter()
+            .map(|(genre, color)| (genre.to_string(), color))
+            .collect(),
+            saved_searches: HashMap::new(),
+            art_cache_size: 20,
+            confirm_rescan: false,
+            pause_on_focus_loss: false,
+            m3u_use_relative_paths: false,
+            set_title: true,
+            restore_title: true,
+            event_poll_ms: 16,
+        }
+    }
+}
+
+impl Config {
+    /// The settings in the config file, with the sort saved by the last session. Settings the
+    /// file leaves out keep their defaults, and a missing file gives the defaults.
+    ///
+    /// Returns a description of every line that couldn't be used, along with the settings.
+    pub fn load() -> (Self, Vec<String>) {
+        let contents = config_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default();
+        let (mut config, problems) = Self::parse(&contents);
+        if let Some((column, direction)) = load_sort() {
+            config.sort_key = column;
+            config.sort_direction = direction;
+        }
+        (config, problems)
+    }
+
+    /// Apply each `key = value` line of `contents` over the defaults. Blank lines and lines
+    /// starting with `#` are skipped, and so are lines that can't be used, which are described in
+    /// the problems returned.
+    fn parse(contents: &str) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut problems = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let applied = match line.split_once('=') {
+                Some((key, value)) => config.set(key.trim(), value.trim()),
+                None => Err(String::from("expected `key = value`")),
+            };
+            if let Err(e) = applied {
+                problems.push(format!("Config line {}: {e}", n + 1));
+            }
+        }
+        (config, problems)
+    }
+
+    /// Change the setting called `key` to `value`, as written in the config file.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let invalid = || format!("'{value}' isn't a valid value for {key}");
+        if let Some(genre) = key.strip_prefix("genre_color.") {
+            let color = Color::from_str(value).map_err(|_| invalid())?;
+            self.genre_colors.insert(genre.to_string(), color);
+            return Ok(());
+        }
+        if let Some(column) = key.strip_prefix("column_width.") {
+            let column =
+                Column::from_key(column).ok_or_else(|| format!("unknown column '{column}'"))?;
+            let weight = parse_value::<u16>(value)
+                .filter(|w| (1..=ColumnWidths::MAX_WEIGHT).contains(w))
+                .ok_or_else(invalid)?;
+            *self.column_widths.weight_mut(column) = weight;
+            return Ok(());
+        }
+        if let Some(action) = key.strip_prefix("key.") {
+            let action =
+                Action::from_name(action).ok_or_else(|| format!("unknown action '{action}'"))?;
+            *self.keybindings.binding_mut(action) = parse_binding(value).ok_or_else(invalid)?;
+            return Ok(());
+        }
+        match key {
+            "shuffle_avoid_recent" => {
+                self.shuffle_avoid_recent = parse_value(value).ok_or_else(invalid)?;
+            }
+            "skip_silence" => self.skip_silence = parse_value(value).ok_or_else(invalid)?,
+            "follow_symlinks" => self.follow_symlinks = parse_value(value).ok_or_else(invalid)?,
+            "scan_follow_mounts" => {
+                self.scan_follow_mounts = parse_value(value).ok_or_else(invalid)?;
+            }
+            "max_scan_depth" => self.max_scan_depth = parse_value(value).ok_or_else(invalid)?,
+            "scan_extensions" => {
+                self.scan_extensions = value
+                    .split(',')
+                    .map(|e| e.trim().trim_start_matches('.').to_string())
+                    .filter(|e| !e.is_empty())
+                    .collect();
+            }
+            "scan_hidden_files" => {
+                self.scan_hidden_files = parse_value(value).ok_or_else(invalid)?;
+            }
+            "scan_hidden_dirs" => self.scan_hidden_dirs = parse_value(value).ok_or_else(invalid)?,
+            "max_file_size_mb" => {
+                self.max_file_size_mb = match value {
+                    "none" => None,
+                    _ => Some(parse_value(value).ok_or_else(invalid)?),
+                };
+            }
+            "deduplicate_queue" => {
+                self.deduplicate_queue = parse_value(value).ok_or_else(invalid)?;
+            }
+            "columns" => {
+                let mut columns = ColumnConfig {
+                    track: false,
+                    title: false,
+                    artist: false,
+                    album: false,
+                    year: false,
+                    genre: false,
+                    duration: false,
+                };
+                for column in value.split(',').map(str::trim) {
+                    let column = Column::from_key(column)
+                        .ok_or_else(|| format!("unknown column '{column}'"))?;
+                    *columns.flag(column) = true;
+                }
+                self.columns = columns;
+            }
+            "sort_key" => self.sort_key = Column::from_key(value).ok_or_else(invalid)?,
+            "sort_direction" => {
+                self.sort_direction = SortDirection::from_key(value).ok_or_else(invalid)?;
+            }
+            "art_cache_size" => self.art_cache_size = parse_value(value).ok_or_else(invalid)?,
+            "confirm_rescan" => self.confirm_rescan = parse_value(value).ok_or_else(invalid)?,
+            "pause_on_focus_loss" => {
+                self.pause_on_focus_loss = parse_value(value).ok_or_else(invalid)?;
+            }
+            "m3u_use_relative_paths" => {
+                self.m3u_use_relative_paths = parse_value(value).ok_or_else(invalid)?;
+            }
+            "set_title" => self.set_title = parse_value(value).ok_or_else(invalid)?,
+            "restore_title" => self.restore_title = parse_value(value).ok_or_else(invalid)?,
+            "event_poll_ms" => self.event_poll_ms = parse_value(value).ok_or_else(invalid)?,
+            _ => return Err(format!("unknown setting '{key}'")),
+        }
+        Ok(())
+    }
+
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            extensions: self.scan_extensions.clone(),
+            follow_symlinks: self.follow_symlinks,
+            follow_mounts: self.scan_follow_mounts,
+            max_scan_depth: self.max_scan_depth,
+            scan_hidden_files: self.scan_hidden_files,
+            scan_hidden_dirs: self.scan_hidden_dirs,
+            max_file_size: self
+                .max_file_size_mb
+                .map(|mb| mb.saturating_mul(1024 * 1024)),
+        }
+    }
+
+    /// Colour configured for songs of `genre`, if any.
+    pub fn genre_color(&self, genre: &str) -> Option<Color> {
+        self.genre_colors
+            .iter()
+            .find(|(g, _)| g.eq_ignore_ascii_case(genre))
+            .map(|(_, color)| *color)
+    }
+}
+
+/// `value` as a number or `true`/`false`, if it is one.
+fn parse_value<T: FromStr>(value: &str) -> Option<T> {
+    value.parse().ok()
+}
+
+/// The settings file read by [`Config::load`].
+fn config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .map(|d| d.join("rustplayer").join("config"))
+}
+
+/// Directory for persistent player data such as play statistics.
+pub fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/share")))
+        .map(|d| d.join("rustplayer"))
+}
+
+/// Directory for data that can be regenerated, like extracted cover art.
+pub fn cache_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))
+        .map(|d| d.join("rustplayer"))
+}
+
+fn last_profile_path() -> Option<PathBuf> {
+    data_dir().map(|d| d.join("profile"))
+}
+
+/// Name of the profile that was active when the player last switched profiles.
+pub fn last_profile() -> Option<String> {
+    let name = fs::read_to_string(last_profile_path()?).ok()?;
+    Some(name.trim().to_string()).filter(|n| !n.is_empty())
+}
+
+/// Remember `name` as the active profile for the next launch.
+pub fn save_last_profile(name: &str) -> Result<()> {
+    let Some(path) = last_profile_path() else {
+        return Ok(());
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, name)?;
+    Ok(())
+}
+
+fn sort_path() -> Option<PathBuf> {
+    data_dir().map(|d| d.join("sort"))
+}
+
+/// Parse a sort written as a column and a direction, like `artist asc`.
+fn parse_sort(contents: &str) -> Option<(Column, SortDirection)> {
+    let (column, direction) = contents.trim().split_once(' ')?;
+    Some((
+        Column::from_key(column)?,
+        SortDirection::from_key(direction)?,
+    ))
+}
+
+/// The sort last chosen with `:sort`, if it was saved.
+fn load_sort() -> Option<(Column, SortDirection)> {
+    parse_sort(&fs::read_to_string(sort_path()?).ok()?)
+}
+
+/// Remember the sort for the next launch.
+pub fn save_sort(column: Column, direction: SortDirection) -> Result<()> {
+    let Some(path) = sort_path() else {
+        return Ok(());
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, format!("{} {}", column.key(), direction.key()))?;
+    Ok(())
+}
+
+fn profiles_path() -> Option<PathBuf> {
+    data_dir().map(|d| d.join("profiles.tsv"))
+}
+
+/// Parse a profile from a line of `name, playback mode, volume, root directories...`.
+fn parse_profile(line: &str) -> Option<(String, ProfileConfig)> {
+    let mut fields = line.split('\t');
+    let name = fields.next().filter(|n| !n.is_empty())?.to_string();
+    let playback_mode = match fields.next()? {
+        "Normal" => PlaybackMode::Normal,
+        "Shuffle" => PlaybackMode::Shuffle,
+        _ => return None,
+    };
+    let volume = Volume::new(fields.next()?.parse().ok()?);
+    let root_dirs = fields.map(PathBuf::from).collect::<Vec<_>>();
+    if root_dirs.is_empty() {
+        return None;
+    }
+    Some((
+        name,
+        ProfileConfig {
+            root_dirs,
+            playback_mode,
+            volume,
+        },
+    ))
+}
+
+fn format_profile(name: &str, profile: &ProfileConfig) -> String {
+    let dirs = profile
+        .root_dirs
+        .iter()
+        .map(|d| d.display().to_string())
+        .collect::<Vec<_>>();
+    format!(
+        "{name}\t{}\t{}\t{}",
+        profile.playback_mode.name(),
+        f32::from(profile.volume),
+        dirs.join("\t")
+    )
+}
+
+/// Profiles saved by earlier sessions with `:profile-save`. A missing or unreadable file gives
+/// none.
+pub fn load_profiles() -> HashMap<String, ProfileConfig> {
+    profiles_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|contents| contents.lines().filter_map(parse_profile).collect())
+        .unwrap_or_default()
+}
+
+/// Store `profiles` as tab-separated lines for the next launch.
+pub fn save_profiles(profiles: &HashMap<String, ProfileConfig>) -> Result<()> {
+    let Some(path) = profiles_path() else {
+        return Ok(());
+    };
+    let mut contents = String::new();
+    for (name, profile) in profiles {
+        writeln!(contents, "{}", format_profile(name, profile))?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn saved_searches_path() -> Option<PathBuf> {
+    data_dir().map(|d| d.join("searches.tsv"))
+}
+
+/// Parse a saved search from a line of `name, case_sensitive, query`.
+fn parse_saved_search(line: &str) -> Option<(String, SearchPreset)> {
+    let mut fields = line.splitn(3, '\t');
+    let name = fields.next()?.to_string();
+    let case_sensitive = fields.next()?.parse().ok()?;
+    let query = fields.next()?.to_string();
+    Some((
+        name,
+        SearchPreset {
+            query,
+            case_sensitive,
+        },
+    ))
+}
+
+fn favourites_path() -> Option<PathBuf> {
+    data_dir().map(|d| d.join("favourites.txt"))
+}
+
+/// Songs favourited in earlier sessions. A missing or unreadable file gives none.
+pub fn load_favourites() -> HashSet<PathBuf> {
+    favourites_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|contents| {
+            contents
+                .lines()
+                .filter(|l| !l.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Store the paths of the favourite songs, one per line.
+pub fn save_favourites(favourites: &HashSet<PathBuf>) -> Result<()> {
+    let Some(path) = favourites_path() else {
+        return Ok(());
+    };
+    let mut contents = String::new();
+    for favourite in favourites {
+        writeln!(contents, "{}", favourite.display())?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Searches saved by an earlier session. A missing or unreadable file gives none.
+pub fn load_saved_searches() -> HashMap<String, SearchPreset> {
+    saved_searches_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|contents| contents.lines().filter_map(parse_saved_search).collect())
+        .unwrap_or_default()
+}
+
+/// Store `searches` as tab-separated lines for the next launch.
+pub fn save_saved_searches(searches: &HashMap<String, SearchPreset>) -> Result<()> {
+    let Some(path) = saved_searches_path() else {
+        return Ok(());
+    };
+    let mut contents = String::new();
+    for (name, preset) in searches {
+        writeln!(
+            contents,
+            "{name}\t{}\t{}",
+            preset.case_sensitive, preset.query
+        )?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_saved_search() {
+        assert_eq!(
+            parse_saved_search("unrated\ttrue\tBlack\tSabbath"),
+            Some((
+                String::from("unrated"),
+                SearchPreset {
+                    query: String::from("Black\tSabbath"),
+                    case_sensitive: true,
+                }
+            ))
+        );
+        assert_eq!(parse_saved_search("unrated\tmaybe\tquery"), None);
+        assert_eq!(parse_saved_search("unrated"), None);
+    }
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(
+            parse_sort("year desc\n"),
+            Some((Column::Year, SortDirection::Descending))
+        );
+        assert_eq!(parse_sort("year"), None);
+        assert_eq!(parse_sort("colour asc"), None);
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        let profile = ProfileConfig {
+            root_dirs: vec![PathBuf::from("/music/jazz"), PathBuf::from("/mnt/jazz")],
+            playback_mode: PlaybackMode::Shuffle,
+            volume: Volume::new(0.5),
+        };
+        let line = format_profile("jazz", &profile);
+        assert_eq!(line, "jazz\tShuffle\t0.5\t/music/jazz\t/mnt/jazz");
+        let (name, parsed) = parse_profile(&line).unwrap();
+        assert_eq!(name, "jazz");
+        assert_eq!(parsed.root_dirs, profile.root_dirs);
+        assert_eq!(parsed.playback_mode, PlaybackMode::Shuffle);
+        assert_eq!(parsed.volume, profile.volume);
+
+        assert!(parse_profile("jazz\tShuffle\t0.5").is_none());
+        assert!(parse_profile("jazz\tLoud\t0.5\t/music").is_none());
+    }
+
+    #[test]
+    fn test_config_genre_color() {
+        let config = Config::default();
+        assert_eq!(config.genre_color("rock"), Some(Color::Red));
+        assert_eq!(config.genre_color("JAZZ"), Some(Color::Blue));
+        assert_eq!(config.genre_color("Polka"), None);
+    }
+
+    #[test]
+    fn test_keybindings_validate() {
+        let mut bindings = Keybindings::default();
+        assert!(bindings.validate().is_empty());
+
+        bindings.seek_forward = bindings.volume_up;
+        let conflicts = bindings.validate();
+        assert_eq!(
+            conflicts,
+            vec![BindingConflict {
+                binding: (KeyCode::Char('='), KeyModifiers::NONE),
+                actions: (Action::VolumeUp, Action::SeekForward),
+            }]
+        );
+        assert_eq!(
+            conflicts[0].to_string(),
+            "Keybinding conflict: '=' bound to both volume_up and seek_forward"
+        );
+    }
+
+    #[test]
+    fn test_config_parse() {
+        let (config, problems) = Config::parse(
+            "# Comment\n\
+             \n\
+             scan_extensions = mp3, .wma\n\
+             max_file_size_mb = 200\n\
+             confirm_rescan = true\n\
+             columns = title, genre\n\
+             column_width.title = 8\n\
+             genre_color.Polka = light green\n\
+             key.skip = Ctrl+n\n\
+             sort_key = year\n\
+             volume = 11\n\
+             event_poll_ms = soon\n\
+             column_width.title = 99\n\
+             key.dance = d\n\
+             set_title\n",
+        );
+        assert_eq!(config.scan_extensions, ["mp3", "wma"]);
+        assert_eq!(config.max_file_size_mb, Some(200));
+        assert!(config.confirm_rescan);
+        assert_eq!(config.columns.visible(), [Column::Title, Column::Genre]);
+        assert_eq!(config.column_widths.weight(Column::Title), 8);
+        assert_eq!(config.genre_color("polka"), Some(Color::LightGreen));
+        assert_eq!(
+            config.keybindings.skip,
+            (KeyCode::Char('n'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(config.sort_key, Column::Year);
+        // Bad lines leave their settings alone
+        assert_eq!(config.event_poll_ms, Config::default().event_poll_ms);
+        assert!(config.set_title);
+        assert_eq!(
+            problems,
+            [
+                "Config line 11: unknown setting 'volume'",
+                "Config line 12: 'soon' isn't a valid value for event_poll_ms",
+                "Config line 13: '99' isn't a valid value for column_width.title",
+                "Config line 14: unknown action 'dance'",
+                "Config line 15: expected `key = value`",
+            ]
+        );
+        assert!(Config::parse("").1.is_empty());
+        assert!(Config::parse("skip_silence = true").0.skip_silence);
+        assert!(
+            Config::parse("pause_on_focus_loss = true")
+                .0
+                .pause_on_focus_loss
+        );
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        for binding in [
+            (KeyCode::Char('='), KeyModifiers::NONE),
+            (KeyCode::Char('+'), KeyModifiers::CONTROL),
+            (KeyCode::Right, KeyModifiers::SHIFT | KeyModifiers::ALT),
+            (KeyCode::F(5), KeyModifiers::NONE),
+        ] {
+            assert_eq!(parse_binding(&describe_binding(binding)), Some(binding));
+        }
+        assert_eq!(
+            parse_binding("+"),
+            Some((KeyCode::Char('+'), KeyModifiers::NONE))
+        );
+        assert_eq!(
+            parse_binding("F12"),
+            Some((KeyCode::F(12), KeyModifiers::NONE))
+        );
+        assert_eq!(parse_binding("Hyper+x"), None);
+        assert_eq!(parse_binding("Space"), None);
+        assert_eq!(parse_binding(""), None);
+    }
+
+    #[test]
+    fn test_describe_binding() {
+        assert_eq!(
+            describe_binding((KeyCode::Char('='), KeyModifiers::NONE)),
+            "="
+        );
+        assert_eq!(
+            describe_binding((KeyCode::Char('s'), KeyModifiers::CONTROL)),
+            "Ctrl+s"
+        );
+        assert_eq!(
+            describe_binding((KeyCode::Right, KeyModifiers::SHIFT | KeyModifiers::ALT)),
+            "Alt+Shift+Right"
+        );
+    }
+
+    #[test]
+    fn test_keybindings_reset_conflicts() {
+        let mut bindings = Keybindings::default();
+        bindings.volume_up = (KeyCode::Char('+'), KeyModifiers::NONE);
+        bindings.skip = (KeyCode::Char('n'), KeyModifiers::NONE);
+        bindings.seek_backward = bindings.skip;
+        assert_eq!(bindings.reset_conflicts().len(), 1);
+        assert!(bindings.validate().is_empty());
+        assert_eq!(bindings.volume_up, (KeyCode::Char('+'), KeyModifiers::NONE));
+        assert_eq!(bindings.skip, Keybindings::default().skip);
+        assert_eq!(bindings.seek_backward, Keybindings::default().seek_backward);
+    }
+}
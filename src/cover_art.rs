@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use audiotags::{MimeType, Picture, Tag};
+use eyre::{eyre, Result};
+use lru::LruCache;
+use ratatui::{
+    style::{Color, Style},
+    text::Line,
+};
+
+use crate::app::SongInfo;
+use crate::config;
+
+/// Image files commonly left next to the tracks of an album, matched case-insensitively.
+const DIRECTORY_COVER_NAMES: [&str; 5] = [
+    "folder.jpg",
+    "cover.jpg",
+    "front.jpg",
+    "album.jpg",
+    "artwork.jpg",
+];
+
+/// Width and height of the placeholder shown for songs without cover art, in cells.
+const PLACEHOLDER_SIZE: usize = 8;
+
+/// Cover art extracted from song tags into files under the cache directory, so every consumer
+/// reads the same image instead of pulling it out of the tags itself.
+///
+/// Images are written exactly as they're embedded, named after an FNV-1a hash of the song's path.
+/// The hash is fixed rather than Rust's default one, which can change between releases and would
+/// orphan the cache.
+#[derive(Default)]
+pub struct CoverArtCache {
+    /// Cover lookups already done this session, including songs that have no cover.
+    extracted: HashMap<PathBuf, Option<PathBuf>>,
+    /// Cover images found next to songs, keyed by directory.
+    directory_covers: HashMap<PathBuf, Option<PathBuf>>,
+}
+
+impl CoverArtCache {
+    fn cache_dir() -> Option<PathBuf> {
+        config::cache_dir().map(|d| d.join("covers"))
+    }
+
+    fn extension(mime_type: MimeType) -> &'static str {
+        match mime_type {
+            MimeType::Png => "png",
+            MimeType::Jpeg => "jpg",
+            MimeType::Tiff => "tiff",
+            MimeType::Bmp => "bmp",
+            MimeType::Gif => "gif",
+        }
+    }
+
+    fn cache_stem(song_path: &Path) -> String {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0100_0000_01b3;
+        let hash = song_path
+            .as_os_str()
+            .as_encoded_bytes()
+            .iter()
+            .fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+            });
+        format!("{hash:016x}")
+    }
+
+    /// The cached cover of the song at `song_path` in `cache_dir`, if it was extracted before.
+    fn cached_file(cache_dir: &Path, song_path: &Path) -> Option<PathBuf> {
+        let stem = Self::cache_stem(song_path);
+        [
+            MimeType::Png,
+            MimeType::Jpeg,
+            MimeType::Tiff,
+            MimeType::Bmp,
+            MimeType::Gif,
+        ]
+        .into_iter()
+        .map(|mime_type| cache_dir.join(format!("{stem}.{}", Self::extension(mime_type))))
+        .find(|path| path.exists())
+    }
+
+    /// Path to the cover image for `song`, extracting it from the song's tags first if it isn't
+    /// cached yet. Songs without an embedded cover fall back to an image in their directory.
+    /// Returns `None` if neither is found.
+    pub fn get_or_extract(&mut self, song: &SongInfo) -> Option<PathBuf> {
+        let song_path = song.file_path();
+        let embedded = if let Some(cached) = self.extracted.get(song_path) {
+            cached.clone()
+        } else {
+            let cover = Self::extract(song_path);
+            self.extracted
+                .insert(song_path.to_path_buf(), cover.clone());
+            cover
+        };
+        embedded.or_else(|| {
+            let dir = song_path.parent()?;
+            self.directory_covers
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| find_directory_cover(song_path))
+                .clone()
+        })
+    }
+
+    /// Whether `song` has cover art, embedded or next to it in its directory. Unlike
+    /// [`Self::get_or_extract`] this only reads the song's tags, so it stays cheap when checking
+    /// a whole library.
+    pub fn has_cover(&mut self, song: &SongInfo) -> bool {
+        let song_path = song.file_path();
+        let embedded = match self.extracted.get(song_path) {
+            Some(cached) => cached.is_some(),
+            None => Tag::new()
+                .read_from_path(song_path)
+                .is_ok_and(|tag| tag.album_cover().is_some()),
+        };
+        embedded
+            || song_path.parent().is_some_and(|dir| {
+                self.directory_covers
+                    .entry(dir.to_path_buf())
+                    .or_insert_with(|| find_directory_cover(song_path))
+                    .is_some()
+            })
+    }
+
+    /// Drop what's known about the cover of the song at `song_path`, so the next lookup reads its
+    /// tags again.
+    pub fn forget(&mut self, song_path: &Path) {
+        self.extracted.remove(song_path);
+    }
+
+    /// Keep the cached cover of the song at `from` for it at `to`, after its file was moved.
+    pub fn move_song(&mut self, from: &Path, to: &Path) {
+        self.extracted.remove(from);
+        if let Some(cache_dir) = Self::cache_dir() {
+            Self::move_cached_file(&cache_dir, from, to);
+        }
+    }
+
+    /// Rename the cover cached in `cache_dir` for the song at `from` to the name used for `to`.
+    fn move_cached_file(cache_dir: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+        let cached = Self::cached_file(cache_dir, from)?;
+        let target = cache_dir
+            .join(Self::cache_stem(to))
+            .with_extension(cached.extension()?);
+        fs::rename(&cached, &target).ok()?;
+        Some(target)
+    }
+
+    fn extract(song_path: &Path) -> Option<PathBuf> {
+        let cache_dir = Self::cache_dir()?;
+        fs::create_dir_all(&cache_dir).ok()?;
+        // Already extracted in an earlier session
+        if let Some(existing) = Self::cached_file(&cache_dir, song_path) {
+            return Some(existing);
+        }
+
+        let tag = Tag::new().read_from_path(song_path).ok()?;
+        let cover = tag.album_cover()?;
+        let cover_path = cache_dir.join(format!(
+            "{}.{}",
+            Self::cache_stem(song_path),
+            Self::extension(cover.mime_type)
+        ));
+        fs::write(&cover_path, cover.data).ok()?;
+        Some(cover_path)
+    }
+}
+
+/// Cover art already drawn for recently shown albums, so it isn't drawn again every frame.
+///
+/// Albums are told apart by the directory their tracks are in. Once more than the cache's
+/// capacity have been drawn, the one shown least recently is dropped.
+pub struct ArtworkCache<T> {
+    entries: LruCache<PathBuf, T>,
+}
+
+impl<T> ArtworkCache<T> {
+    /// A cache holding the art of up to `max_size` albums, and always at least one.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: LruCache::new(NonZeroUsize::new(max_size).unwrap_or(NonZeroUsize::MIN)),
+        }
+    }
+
+    /// The art for `song`'s album, drawn with `render` from the song's path if it isn't cached.
+    pub fn get_or_render(&mut self, song: &SongInfo, render: impl FnOnce(&Path) -> T) -> &T {
+        let song_path = song.file_path();
+        let album_dir = song_path.parent().unwrap_or(song_path).to_path_buf();
+        self.entries.get_or_insert(album_dir, || render(song_path))
+    }
+}
+
+/// Stand-in drawn where the cover would be when a song has none.
+pub struct CoverArtPlaceholder;
+
+impl CoverArtPlaceholder {
+    pub const SIZE: usize = PLACEHOLDER_SIZE;
+
+    /// A grey square with a music note in the middle.
+    pub fn render() -> Vec<Line<'static>> {
+        let style = Style::default().bg(Color::DarkGray);
+        (0..PLACEHOLDER_SIZE)
+            .map(|row| {
+                if row == PLACEHOLDER_SIZE / 2 - 1 {
+                    let left = (PLACEHOLDER_SIZE - 1) / 2;
+                    let right = PLACEHOLDER_SIZE - 1 - left;
+                    Line::styled(format!("{}♪{}", " ".repeat(left), " ".repeat(right)), style)
+                } else {
+                    Line::styled(" ".repeat(PLACEHOLDER_SIZE), style)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Embed the image at `image_path` as the cover of the song at `song_path`, replacing any cover
+/// it already has.
+pub fn embed_cover(song_path: &Path, image_path: &Path) -> Result<()> {
+    let mime_type = match image_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+        .as_deref()
+    {
+        Some("png") => MimeType::Png,
+        Some("jpg" | "jpeg") => MimeType::Jpeg,
+        Some("tif" | "tiff") => MimeType::Tiff,
+        Some("bmp") => MimeType::Bmp,
+        Some("gif") => MimeType::Gif,
+        _ => return Err(eyre!("Unsupported image type: {}", image_path.display())),
+    };
+    let data = fs::read(image_path)?;
+    let mut tag = Tag::new().read_from_path(song_path)?;
+    tag.set_album_cover(Picture {
+        data: &data,
+        mime_type,
+    });
+    let song_path = song_path
+        .to_str()
+        .ok_or_else(|| eyre!("Invalid path: {}", song_path.display()))?;
+    tag.write_to_path(song_path)?;
+    Ok(())
+}
+
+/// Look for a cover image such as `folder.jpg` or `cover.jpg` in the same directory as
+/// `song_path`.
+pub fn find_directory_cover(song_path: &Path) -> Option<PathBuf> {
+    let entries = fs::read_dir(song_path.parent()?).ok()?;
+    let candidates = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .collect::<Vec<_>>();
+    // Earlier names in the list take priority
+    DIRECTORY_COVER_NAMES.iter().find_map(|name| {
+        candidates
+            .iter()
+            .find(|p| {
+                p.file_name()
+                    .and_then(|f| f.to_str())
+                    .is_some_and(|f| f.eq_ignore_ascii_case(name))
+            })
+            .cloned()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use std::cell::Cell;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_find_directory_cover() {
+        let td = TempDir::new("tempdir").unwrap();
+        let song = td.path().join("song.mp3");
+        fs::write(&song, []).unwrap();
+        assert_eq!(find_directory_cover(&song), None);
+
+        fs::write(td.path().join("Front.JPG"), []).unwrap();
+        assert_eq!(
+            find_directory_cover(&song),
+            Some(td.path().join("Front.JPG"))
+        );
+        fs::write(td.path().join("folder.jpg"), []).unwrap();
+        assert_eq!(
+            find_directory_cover(&song),
+            Some(td.path().join("folder.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_cache_stem_is_stable() {
+        // FNV-1a of the empty string is the offset basis
+        assert_eq!(CoverArtCache::cache_stem(Path::new("")), "cbf29ce484222325");
+        assert_eq!(
+            CoverArtCache::cache_stem(Path::new("a")),
+            "af63dc4c8601ec8c"
+        );
+
+        let td = TempDir::new("tempdir").unwrap();
+        let song = Path::new("/music/song.mp3");
+        assert_eq!(CoverArtCache::cached_file(td.path(), song), None);
+        let cached = td
+            .path()
+            .join(format!("{}.png", CoverArtCache::cache_stem(song)));
+        fs::write(&cached, []).unwrap();
+        assert_eq!(
+            CoverArtCache::cached_file(td.path(), song),
+            Some(cached.clone())
+        );
+
+        let moved = Path::new("/music/moved.mp3");
+        let target = CoverArtCache::move_cached_file(td.path(), song, moved).unwrap();
+        assert!(!cached.exists());
+        assert_eq!(CoverArtCache::cached_file(td.path(), moved), Some(target));
+    }
+
+    #[test]
+    fn test_has_cover() {
+        let td = TempDir::new("tempdir").unwrap();
+        let song = td.path().join("song.mp3");
+        fs::write(&song, []).unwrap();
+        assert!(!CoverArtCache::default().has_cover(&SongInfo::untagged(&song)));
+
+        fs::write(td.path().join("cover.jpg"), []).unwrap();
+        assert!(CoverArtCache::default().has_cover(&SongInfo::untagged(&song)));
+    }
+
+    #[test]
+    fn test_artwork_cache_evicts_least_recently_used() {
+        let song = |path: &str| SongInfo::untagged(Path::new(path));
+        let mut cache = ArtworkCache::new(2);
+        let renders = Cell::new(0);
+        let render = |path: &Path| {
+            renders.set(renders.get() + 1);
+            path.to_path_buf()
+        };
+        cache.get_or_render(&song("a/1.mp3"), render);
+        // Tracks from the same album share its art
+        assert_eq!(
+            cache.get_or_render(&song("a/2.mp3"), render),
+            Path::new("a/1.mp3")
+        );
+        cache.get_or_render(&song("b/1.mp3"), render);
+        cache.get_or_render(&song("a/1.mp3"), render);
+        cache.get_or_render(&song("c/1.mp3"), render);
+        assert_eq!(renders.get(), 3);
+        // "b" was shown least recently, so it made way for "c"
+        cache.get_or_render(&song("a/1.mp3"), render);
+        cache.get_or_render(&song("b/1.mp3"), render);
+        assert_eq!(renders.get(), 4);
+    }
+
+    #[test]
+    fn test_cover_art_placeholder_is_square() {
+        let lines = CoverArtPlaceholder::render();
+        assert_eq!(lines.len(), CoverArtPlaceholder::SIZE);
+        assert!(lines.iter().all(|l| l.width() == CoverArtPlaceholder::SIZE));
+        assert_eq!(
+            lines.iter().filter(|l| l.to_string().contains('♪')).count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use eyre::{eyre, Result};
+
+const MARKER: &[u8; 4] = b"fLaC";
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+/// Size of a metadata block header: a flag and type byte, then a 24-bit length.
+const HEADER_SIZE: u64 = 4;
+
+/// Where a metadata block sits in the file.
+struct Block {
+    kind: u8,
+    is_last: bool,
+    /// Offset of the block's header.
+    offset: u64,
+    len: u64,
+}
+
+impl Block {
+    fn end(&self) -> u64 {
+        self.offset + HEADER_SIZE + self.len
+    }
+}
+
+fn read_blocks(file: &mut (impl Read + Seek)) -> Result<Vec<Block>> {
+    let mut marker = [0; 4];
+    file.read_exact(&mut marker)?;
+    if &marker != MARKER {
+        return Err(eyre!("Not a FLAC file"));
+    }
+    let mut blocks = Vec::new();
+    loop {
+        let offset = file.stream_position()?;
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+        let block = Block {
+            kind: header[0] & 0x7f,
+            is_last: header[0] & 0x80 != 0,
+            offset,
+            len: u64::from(u32::from_be_bytes([0, header[1], header[2], header[3]])),
+        };
+        file.seek(SeekFrom::Start(block.end()))?;
+        let is_last = block.is_last;
+        blocks.push(block);
+        if is_last {
+            return Ok(blocks);
+        }
+    }
+}
+
+fn block_header(kind: u8, is_last: bool, len: u64) -> Result<[u8; 4]> {
+    let len = u32::try_from(len)
+        .ok()
+        .filter(|l| *l < 1 << 24)
+        .ok_or_else(|| eyre!("Metadata block too large"))?;
+    let [_, a, b, c] = len.to_be_bytes();
+    Ok([kind | if is_last { 0x80 } else { 0 }, a, b, c])
+}
+
+/// Split the first `n` bytes off `rest`.
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if rest.len() < n {
+        return Err(eyre!("Truncated Vorbis comment block"));
+    }
+    let (taken, remaining) = rest.split_at(n);
+    *rest = remaining;
+    Ok(taken)
+}
+
+/// Split a little-endian 32-bit length off `rest`.
+fn take_len(rest: &mut &[u8]) -> Result<usize> {
+    Ok(u32::from_le_bytes(take(rest, 4)?.try_into()?) as usize)
+}
+
+/// Parse a Vorbis comment block into its vendor string and `KEY=value` comments.
+fn parse_comments(mut body: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>)> {
+    let vendor_len = take_len(&mut body)?;
+    let vendor = take(&mut body, vendor_len)?.to_vec();
+    let count = take_len(&mut body)?;
+    let mut comments = Vec::new();
+    for _ in 0..count {
+        let len = take_len(&mut body)?;
+        comments.push(take(&mut body, len)?.to_vec());
+    }
+    Ok((vendor, comments))
+}
+
+fn encode_comments(vendor: &[u8], comments: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&u32::try_from(vendor.len())?.to_le_bytes());
+    body.extend_from_slice(vendor);
+    body.extend_from_slice(&u32::try_from(comments.len())?.to_le_bytes());
+    for comment in comments {
+        body.extend_from_slice(&u32::try_from(comment.len())?.to_le_bytes());
+        body.extend_from_slice(comment);
+    }
+    Ok(body)
+}
+
+/// The Vorbis comments of the FLAC file at `path` as name and value pairs, in file order.
+pub fn read_comments(path: &Path) -> Result<Vec<(String, String)>> {
+    let mut file = File::open(path)?;
+    let blocks = read_blocks(&mut file)?;
+    let Some(block) = blocks.iter().find(|b| b.kind == VORBIS_COMMENT) else {
+        return Ok(Vec::new());
+    };
+    let mut body = vec![0; usize::try_from(block.len)?];
+    file.seek(SeekFrom::Start(block.offset + HEADER_SIZE))?;
+    file.read_exact(&mut body)?;
+    let (_, comments) = parse_comments(&body)?;
+    Ok(comments
+        .iter()
+        .filter_map(|c| {
+            let comment = String::from_utf8_lossy(c);
+            let (name, value) = comment.split_once('=')?;
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect())
+}
+
+/// Set the Vorbis comments in `fields` on the FLAC file at `path`, replacing any comments with
+/// the same names and keeping the rest. Only the metadata is touched.
+///
+/// The comment block is rewritten in place when it fits in the space of the old block and any
+/// padding after it. Otherwise the file is rewritten with the audio frames streamed across.
+pub fn write_tags(path: &Path, fields: &[(String, String)]) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let blocks = read_blocks(&mut file)?;
+    let comment_ix = blocks.iter().position(|b| b.kind == VORBIS_COMMENT);
+
+    let (vendor, mut comments) = match comment_ix {
+        Some(ix) => {
+            let mut body = vec![0; usize::try_from(blocks[ix].len)?];
+            file.seek(SeekFrom::Start(blocks[ix].offset + HEADER_SIZE))?;
+            file.read_exact(&mut body)?;
+            parse_comments(&body)?
+        }
+        None => (b"rustplayer".to_vec(), Vec::new()),
+    };
+    let is_field = |comment: &[u8], name: &str| {
+        comment.len() > name.len()
+            && comment[name.len()] == b'='
+            && comment[..name.len()].eq_ignore_ascii_case(name.as_bytes())
+    };
+    comments.retain(|c| !fields.iter().any(|(name, _)| is_field(c, name)));
+    comments.extend(
+        fields
+            .iter()
+            .map(|(name, value)| format!("{name}={value}").into_bytes()),
+    );
+    let body = encode_comments(&vendor, &comments)?;
+    let new_len = body.len() as u64;
+
+    if let Some(ix) = comment_ix {
+        let block = &blocks[ix];
+        let padding = blocks
+            .get(ix + 1)
+            .filter(|b| b.kind == PADDING && !block.is_last);
+        // Space the comment block can grow into without moving the audio
+        let available = padding.map_or(block.len, |p| p.end() - block.offset - HEADER_SIZE);
+        let is_last = padding.map_or(block.is_last, |p| p.is_last);
+        if new_len == available || new_len + HEADER_SIZE <= available {
+            let leftover = available - new_len;
+            file.seek(SeekFrom::Start(block.offset))?;
+            file.write_all(&block_header(
+                VORBIS_COMMENT,
+                is_last && leftover == 0,
+                new_len,
+            )?)?;
+            file.write_all(&body)?;
+            if leftover > 0 {
+                let padding_len = leftover - HEADER_SIZE;
+                file.write_all(&block_header(PADDING, is_last, padding_len)?)?;
+                file.write_all(&vec![0; usize::try_from(padding_len)?])?;
+            }
+            return Ok(());
+        }
+    }
+    rewrite(path, &mut file, &blocks, comment_ix, &body)
+}
+
+/// Write a copy of the FLAC file with its comment block replaced by `body`, then move it over
+/// the original.
+fn rewrite(
+    path: &Path,
+    file: &mut File,
+    blocks: &[Block],
+    comment_ix: Option<usize>,
+    body: &[u8],
+) -> Result<()> {
+    let mut new_blocks = Vec::<(u8, Vec<u8>)>::new();
+    for (ix, block) in blocks.iter().enumerate() {
+        if Some(ix) == comment_ix {
+            new_blocks.push((VORBIS_COMMENT, body.to_vec()));
+            continue;
+        }
+        let mut contents = vec![0; usize::try_from(block.len)?];
+        file.seek(SeekFrom::Start(block.offset + HEADER_SIZE))?;
+        file.read_exact(&mut contents)?;
+        new_blocks.push((block.kind, contents));
+        if comment_ix.is_none() && block.kind == STREAMINFO {
+            new_blocks.push((VORBIS_COMMENT, body.to_vec()));
+        }
+    }
+
+    let tmp_path = path.with_extension("flac.tmp");
+    let result = (|| -> Result<()> {
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(MARKER)?;
+        let last_ix = new_blocks.len() - 1;
+        for (ix, (kind, contents)) in new_blocks.iter().enumerate() {
+            out.write_all(&block_header(*kind, ix == last_ix, contents.len() as u64)?)?;
+            out.write_all(contents)?;
+        }
+        let audio_start = blocks.last().map_or(MARKER.len() as u64, Block::end);
+        file.seek(SeekFrom::Start(audio_start))?;
+        io::copy(&mut BufReader::new(&mut *file), &mut out)?;
+        out.flush()?;
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Ok(fs::rename(&tmp_path, path)?),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use tempdir::TempDir;
+
+    const AUDIO: &[u8] = b"\xff\xf8 pretend audio frames";
+
+    /// A FLAC file with an empty STREAMINFO, a comment block, and optionally some padding.
+    fn fixture(dir: &Path, comments: &[&str], padding: u64) -> std::path::PathBuf {
+        let path = dir.join("song.flac");
+        let comments = comments
+            .iter()
+            .map(|c| c.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let body = encode_comments(b"test", &comments).unwrap();
+        let mut contents = MARKER.to_vec();
+        contents.extend(block_header(STREAMINFO, false, 34).unwrap());
+        contents.extend([0; 34]);
+        contents.extend(block_header(VORBIS_COMMENT, padding == 0, body.len() as u64).unwrap());
+        contents.extend(body);
+        if padding > 0 {
+            contents.extend(block_header(PADDING, true, padding).unwrap());
+            contents.extend(vec![0; usize::try_from(padding).unwrap()]);
+        }
+        contents.extend(AUDIO);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn comments(path: &Path) -> Vec<String> {
+        read_comments(path)
+            .unwrap()
+            .into_iter()
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect()
+    }
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_tags_in_place_uses_padding() {
+        let td = TempDir::new("tempdir").unwrap();
+        let path = fixture(td.path(), &["TITLE=Old", "ARTIST=Someone"], 64);
+        let len = fs::metadata(&path).unwrap().len();
+
+        write_tags(&path, &fields(&[("title", "A longer new title")])).unwrap();
+        assert_eq!(
+            comments(&path),
+            ["ARTIST=Someone", "title=A longer new title"]
+        );
+        // The padding shrank to make room, so nothing after it moved
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        assert!(fs::read(&path).unwrap().ends_with(AUDIO));
+    }
+
+    #[test]
+    fn test_write_tags_rewrites_when_out_of_space() {
+        let td = TempDir::new("tempdir").unwrap();
+        let path = fixture(td.path(), &["TITLE=Old"], 0);
+
+        write_tags(&path, &fields(&[("ALBUM", "Somewhere"), ("TITLE", "New")])).unwrap();
+        assert_eq!(comments(&path), ["ALBUM=Somewhere", "TITLE=New"]);
+        assert!(fs::read(&path).unwrap().ends_with(AUDIO));
+        assert!(!path.with_extension("flac.tmp").exists());
+    }
+}
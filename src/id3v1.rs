@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use eyre::Result;
+use id3::TagLike;
+
+/// `ID3v1` tags are always the last 128 bytes of the file.
+const TAG_SIZE: usize = 128;
+/// Genre byte meaning no genre is set.
+const NO_GENRE: u8 = 255;
+
+/// The fields of an `ID3v1` (or v1.1, with a track number) tag.
+#[derive(Debug, Default, PartialEq)]
+pub struct Id3v1Tag {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub year: Option<i32>,
+    pub comment: String,
+    pub track: Option<u8>,
+    /// Index into the standard `ID3v1` genre list.
+    pub genre: Option<u8>,
+}
+
+/// Decode a fixed-width Latin-1 field, which is padded with NULs or spaces.
+fn decode_latin1(bytes: &[u8]) -> String {
+    let text = bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| char::from(*b))
+        .collect::<String>();
+    text.trim_end().to_string()
+}
+
+/// Parse the 128 bytes of an `ID3v1` tag. Returns `None` if they don't start with the `TAG` marker.
+pub fn parse(bytes: &[u8; TAG_SIZE]) -> Option<Id3v1Tag> {
+    if &bytes[..3] != b"TAG" {
+        return None;
+    }
+    // v1.1 takes the last two bytes of the comment for a zero byte and the track number
+    let is_v1_1 = bytes[125] == 0 && bytes[126] != 0;
+    let comment_end = if is_v1_1 { 125 } else { 127 };
+    Some(Id3v1Tag {
+        title: decode_latin1(&bytes[3..33]),
+        artist: decode_latin1(&bytes[33..63]),
+        album: decode_latin1(&bytes[63..93]),
+        year: decode_latin1(&bytes[93..97]).parse().ok(),
+        comment: decode_latin1(&bytes[97..comment_end]),
+        track: is_v1_1.then_some(bytes[126]),
+        genre: Some(bytes[127]).filter(|g| *g != NO_GENRE),
+    })
+}
+
+/// Read the `ID3v1` tag at the end of the file at `path`, if it has one.
+pub fn read(path: &Path) -> Result<Option<Id3v1Tag>> {
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < TAG_SIZE as u64 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-i64::try_from(TAG_SIZE)?))?;
+    let mut bytes = [0; TAG_SIZE];
+    file.read_exact(&mut bytes)?;
+    Ok(parse(&bytes))
+}
+
+fn has_id3v2(path: &Path) -> Result<bool> {
+    let mut marker = [0; 3];
+    let mut file = File::open(path)?;
+    Ok(file.read_exact(&mut marker).is_ok() && &marker == b"ID3")
+}
+
+/// Copy the `ID3v1` tag of the MP3 at `path` into a new `ID3v2.4` tag at the start of the file. The
+/// `ID3v1` tag is left in place for old players. Returns whether the file was changed, which it
+/// isn't if it has no `ID3v1` tag or already has an `ID3v2` tag.
+pub fn upgrade(path: &Path) -> Result<bool> {
+    if has_id3v2(path)? {
+        return Ok(false);
+    }
+    let Some(v1) = read(path)? else {
+        return Ok(false);
+    };
+
+    let mut tag = id3::Tag::new();
+    if !v1.title.is_empty() {
+        tag.set_title(v1.title);
+    }
+    if !v1.artist.is_empty() {
+        tag.set_artist(v1.artist);
+    }
+    if !v1.album.is_empty() {
+        tag.set_album(v1.album);
+    }
+    if let Some(year) = v1.year {
+        tag.set_year(year);
+    }
+    if let Some(track) = v1.track {
+        tag.set_track(u32::from(track));
+    }
+    // ID3v2 refers to ID3v1 genres by their number in brackets
+    if let Some(genre) = v1.genre {
+        tag.set_genre(format!("({genre})"));
+    }
+    if !v1.comment.is_empty() {
+        tag.add_frame(id3::frame::Comment {
+            lang: String::from("eng"),
+            description: String::new(),
+            text: v1.comment,
+        });
+    }
+    tag.write_to_path(path, id3::Version::Id3v24)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate tempdir;
+
+    use super::*;
+    use tempdir::TempDir;
+
+    fn v1_tag(track: Option<u8>) -> [u8; TAG_SIZE] {
+        let mut bytes = [0; TAG_SIZE];
+        bytes[..3].copy_from_slice(b"TAG");
+        bytes[3..9].copy_from_slice(b"Caf\xe9  ");
+        bytes[33..38].copy_from_slice(b"Artst");
+        bytes[93..97].copy_from_slice(b"1987");
+        bytes[97..104].copy_from_slice(b"Comment");
+        if let Some(track) = track {
+            bytes[126] = track;
+        }
+        bytes[127] = 17;
+        bytes
+    }
+
+    #[test]
+    fn test_parse() {
+        let tag = parse(&v1_tag(Some(4))).unwrap();
+        assert_eq!(
+            tag,
+            Id3v1Tag {
+                title: String::from("Café"),
+                artist: String::from("Artst"),
+                album: String::new(),
+                year: Some(1987),
+                comment: String::from("Comment"),
+                track: Some(4),
+                genre: Some(17),
+            }
+        );
+        assert_eq!(parse(&v1_tag(None)).unwrap().track, None);
+        assert_eq!(parse(&[0; TAG_SIZE]), None);
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let td = TempDir::new("tempdir").unwrap();
+        let path = td.path().join("old.mp3");
+        let mut contents = vec![0xff; 256];
+        contents.extend_from_slice(&v1_tag(Some(4)));
+        std::fs::write(&path, contents).unwrap();
+
+        assert!(upgrade(&path).unwrap());
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.title(), Some("Café"));
+        assert_eq!(tag.year(), Some(1987));
+        assert_eq!(tag.track(), Some(4));
+        // Already upgraded
+        assert!(!upgrade(&path).unwrap());
+    }
+}
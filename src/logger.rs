@@ -0,0 +1,52 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use eyre::{eyre, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::config;
+
+/// Writes warnings and errors to a file, since the terminal is taken over by the UI. The file
+/// only holds the latest session.
+struct FileLogger {
+    file: Mutex<File>,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{secs} {} {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Send log records to `rustplayer.log` in the data directory, replacing the last session's log.
+pub fn init() -> Result<()> {
+    let dir = config::data_dir().ok_or_else(|| eyre!("No data directory"))?;
+    fs::create_dir_all(&dir)?;
+    let file = File::create(dir.join("rustplayer.log"))?;
+    log::set_logger(Box::leak(Box::new(FileLogger {
+        file: Mutex::new(file),
+    })))
+    .map_err(|e| eyre!("{e}"))?;
+    log::set_max_level(LevelFilter::Warn);
+    Ok(())
+}
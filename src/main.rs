@@ -1,28 +1,62 @@
-use std::{path::PathBuf, time::Instant};
+use std::path::PathBuf;
 
 use eyre::Result;
 
+mod alarm;
 mod app;
+mod config;
+mod cover_art;
+mod flac;
+mod id3v1;
+mod logger;
+mod mp4_atoms;
+mod queue;
+mod rename;
+mod replaygain;
+mod stats;
 mod tui;
 use app::PlayerApp;
+use config::Config;
 use tui::Tui;
 
+/// Print the name of every audio output device, one per line.
+fn list_devices() -> Result<()> {
+    use rodio::cpal::traits::{DeviceTrait, HostTrait};
+
+    for device in rodio::cpal::default_host().output_devices()? {
+        println!("{}", device.name()?);
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let args = std::env::args();
+    if args.len() == 2 && std::env::args().nth(1).as_deref() == Some("--list-devices") {
+        if let Err(e) = list_devices() {
+            eprintln!("Couldn't list audio devices: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     if args.len() != 2 {
         return Err(eyre::eyre!("Must provide a path to search for files."));
     }
     let root_dir = PathBuf::from(&args.collect::<Vec<_>>()[1]);
 
+    if let Err(e) = logger::init() {
+        eprintln!("Couldn't open the log file: {e}");
+    }
+    let (config, config_problems) = Config::load();
+
     let mut tui = Tui::new()?;
-    let mut app = PlayerApp::new(&root_dir)?;
-    let mut dt = 0.0;
+    let mut app = PlayerApp::new(&root_dir, config)?;
+    app.report_config_problems(&config_problems);
 
     while app.is_alive() {
-        let start = Instant::now();
-        app.update(dt)?;
-        tui.update(&mut app)?;
-        dt = start.elapsed().as_secs_f64();
+        if let Err(e) = app.update().and_then(|()| tui.update(&mut app)) {
+            app.save_listening_time();
+            return Err(e);
+        }
     }
 
     Ok(())
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Read the iTunes metadata atoms of an MP4/M4A file, keyed by atom name (`©nam`, `aART`, ...).
+///
+/// Text atoms are returned as-is. The binary `trkn` and `disk` atoms are formatted as
+/// `number/total`, leaving out whichever half is missing. Unreadable files give an empty map.
+pub fn read_atoms(path: &Path) -> HashMap<String, String> {
+    let Ok(tag) = mp4ameta::Tag::read_from_path(path) else {
+        return HashMap::new();
+    };
+
+    let mut atoms = tag
+        .strings()
+        .map(|(ident, value)| (ident.to_string(), value.to_string()))
+        .collect::<HashMap<_, _>>();
+    for (key, (number, total)) in [("trkn", tag.track()), ("disk", tag.disc())] {
+        if number.is_some() || total.is_some() {
+            let number = number.map_or(String::new(), |n| n.to_string());
+            let total = total.map_or(String::new(), |t| t.to_string());
+            atoms.insert(key.to_string(), format!("{number}/{total}"));
+        }
+    }
+    atoms
+}
+
+/// Parse a `number/total` pair as produced by [`read_atoms`].
+pub fn parse_pair(value: &str) -> (Option<u16>, Option<u16>) {
+    let (number, total) = value.split_once('/').unwrap_or((value, ""));
+    (number.parse().ok(), total.parse().ok())
+}
@@ -0,0 +1,105 @@
+use std::collections::{HashSet, VecDeque};
+
+/// Songs to play next, ahead of the normal playback order, as library indices.
+#[derive(Default, Clone)]
+pub struct Queue {
+    entries: VecDeque<usize>,
+}
+
+impl Queue {
+    pub fn push(&mut self, ix: usize) {
+        self.entries.push_back(ix);
+    }
+
+    /// Add `ix` to the end of the queue unless it's already queued. Returns whether it was added.
+    pub fn push_dedup(&mut self, ix: usize) -> bool {
+        if self.entries.contains(&ix) {
+            return false;
+        }
+        self.entries.push_back(ix);
+        true
+    }
+
+    /// Remove every entry that's already queued earlier, keeping the first of each. Returns how
+    /// many entries were removed.
+    pub fn deduplicate(&mut self) -> usize {
+        let before = self.entries.len();
+        let mut seen = HashSet::new();
+        self.entries.retain(|ix| seen.insert(*ix));
+        before - self.entries.len()
+    }
+
+    /// Take the next song to play off the front of the queue.
+    pub fn pop_next(&mut self) -> Option<usize> {
+        self.entries.pop_front()
+    }
+
+    /// Take the entry at `pos` out of the queue.
+    pub fn remove(&mut self, pos: usize) -> Option<usize> {
+        self.entries.remove(pos)
+    }
+
+    /// Library indices of the queued songs, in the order they'll play.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Move every entry to the library index `new_ix` gives for it after the library changed,
+    /// dropping entries whose songs are gone.
+    pub fn remap(&mut self, new_ix: impl Fn(usize) -> Option<usize>) {
+        self.entries = self.entries.iter().filter_map(|e| new_ix(*e)).collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_push_dedup() {
+        let mut q = Queue::default();
+        assert!(q.push_dedup(3));
+        assert!(q.push_dedup(1));
+        assert!(!q.push_dedup(3));
+        assert_eq!(q.pop_next(), Some(3));
+        assert_eq!(q.pop_next(), Some(1));
+        assert_eq!(q.pop_next(), None);
+    }
+
+    #[test]
+    fn test_queue_deduplicate() {
+        let mut q = Queue::default();
+        for ix in [2, 5, 2, 7, 5, 2] {
+            q.push(ix);
+        }
+        assert_eq!(q.deduplicate(), 3);
+        assert_eq!(q.entries, [2, 5, 7]);
+    }
+
+    #[test]
+    fn test_queue_remap() {
+        let mut q = Queue::default();
+        for ix in [4, 1, 2] {
+            q.push(ix);
+        }
+        // Index 2 was removed from the library, moving later songs down
+        q.remap(|ix| match ix {
+            2 => None,
+            ix if ix > 2 => Some(ix - 1),
+            ix => Some(ix),
+        });
+        assert_eq!(q.entries, [3, 1]);
+    }
+}
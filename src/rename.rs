@@ -0,0 +1,115 @@
+use std::path::{Component, PathBuf};
+
+use eyre::{eyre, Result};
+
+use crate::app::SongInfo;
+
+/// Characters that aren't allowed in file names on Windows, and so are replaced in tag values.
+const ILLEGAL_PATH_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Make a tag value safe to use as part of a file name. Values of `.` or `..` would point at
+/// another directory, so they're replaced too.
+pub fn sanitize_component(s: &str) -> String {
+    let sanitized = s
+        .chars()
+        .map(|c| {
+            if ILLEGAL_PATH_CHARS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect::<String>()
+        .trim()
+        .to_string();
+    match sanitized.as_str() {
+        "." | ".." => String::from("_"),
+        _ => sanitized,
+    }
+}
+
+/// `value`, unless it's blank. Blank tags count as missing, so they can't leave an empty
+/// directory name.
+fn present(value: Option<&str>) -> Option<&str> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+/// The value of `field` for `song`, with `spec` giving the minimum width of numbers. Widths
+/// starting with `0` pad with zeroes, as in `{track:02}`.
+fn field_value(song: &SongInfo, field: &str, spec: Option<&str>) -> Result<String> {
+    let text = |value: Option<&str>, fallback: &str| present(value).unwrap_or(fallback).to_string();
+    let number = |value: Option<i64>| -> Result<String> {
+        let value = value.unwrap_or(0);
+        let Some(spec) = spec else {
+            return Ok(value.to_string());
+        };
+        let width = spec
+            .parse::<usize>()
+            .map_err(|_| eyre!("Invalid width in {{{field}:{spec}}}"))?;
+        Ok(if spec.starts_with('0') {
+            format!("{value:0width$}")
+        } else {
+            format!("{value:width$}")
+        })
+    };
+    match field {
+        "title" => Ok(text(song.title(), "Unknown Title")),
+        "artist" => Ok(text(song.artist(), "Unknown Artist")),
+        "album" => Ok(text(song.album(), "Unknown Album")),
+        "album_artist" => Ok(text(
+            present(song.album_artist()).or(song.artist()),
+            "Unknown Artist",
+        )),
+        "genre" => Ok(text(song.genre(), "Unknown Genre")),
+        "year" => number(song.year().map(i64::from)),
+        "track" => number(song.track().0.map(i64::from)),
+        "disc" => number(song.disc().0.map(i64::from)),
+        _ => Err(eyre!("Unknown field in template: {{{field}}}")),
+    }
+}
+
+/// Path for `song` relative to the library root, built by replacing each `{field}` in `template`
+/// with the song's tags. The song's file extension is added unless the template already ends
+/// with it.
+///
+/// Fails if the path would be absolute or climb out of the root, as with a template like
+/// `../{title}`.
+pub fn render_template(template: &str, song: &SongInfo) -> Result<PathBuf> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| eyre!("Unclosed {{ in template: {template}"))?;
+        let token = &rest[start + 1..start + end];
+        let (field, spec) = match token.split_once(':') {
+            Some((field, spec)) => (field, Some(spec)),
+            None => (token, None),
+        };
+        rendered.push_str(&sanitize_component(&field_value(song, field, spec)?));
+        rest = &rest[start + end + 1..];
+    }
+    rendered.push_str(rest);
+
+    if let Some(ext) = song.file_path().extension().and_then(|e| e.to_str()) {
+        let suffix = format!(".{ext}");
+        if !rendered.to_lowercase().ends_with(&suffix.to_lowercase()) {
+            rendered.push_str(&suffix);
+        }
+    }
+    let path = PathBuf::from(rendered);
+    let escapes_root = path.components().any(|c| {
+        matches!(
+            c,
+            Component::RootDir | Component::Prefix(_) | Component::ParentDir
+        )
+    });
+    if escapes_root {
+        return Err(eyre!(
+            "Template would move files outside the library: {}",
+            path.display()
+        ));
+    }
+    Ok(path)
+}
@@ -0,0 +1,240 @@
+use std::f64::consts::PI;
+use std::path::Path;
+
+use eyre::{eyre, Result};
+use id3::{frame::ExtendedText, TagLike};
+
+use crate::flac;
+
+/// Loudness that replay gain 2.0 normalises to, in LUFS.
+const REFERENCE_LOUDNESS: f64 = -18.0;
+/// Blocks quieter than this are ignored entirely, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks this far below the ungated loudness are ignored, in LU.
+const RELATIVE_GATE: f64 = -10.0;
+/// Gating blocks are 400 ms long and overlap by 75%, so they're built from 100 ms steps.
+const STEPS_PER_BLOCK: usize = 4;
+
+/// Gain and peak to store in replay gain tags.
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayGain {
+    /// Gain to apply, in dB.
+    pub gain: f32,
+    /// Largest absolute sample value, where full scale is 1.0.
+    pub peak: f32,
+}
+
+/// A single second-order IIR filter section.
+#[derive(Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The two-stage K-weighting filter from ITU-R BS.1770, with coefficients derived for any
+/// sample rate.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let fs = f64::from(sample_rate);
+
+    // High-shelf "pre-filter" modelling the acoustic effect of the head
+    let (f0, gain_db, q) = (
+        1_681.974_450_955_533,
+        3.999_843_853_973_347,
+        0.707_175_236_955_419_6,
+    );
+    let k = (PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // RLB high-pass
+    let (f0, q) = (38.135_470_876_024_44, 0.500_327_037_323_877_3);
+    let k = (PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    [shelf, high_pass]
+}
+
+fn energy_to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// Integrated loudness meter following EBU R128. Tracks added one after another are measured as
+/// though they were a single stream, which gives album loudness.
+#[derive(Default)]
+pub struct LoudnessMeter {
+    /// Mean square energy of each 100 ms step, summed across channels.
+    steps: Vec<f64>,
+    /// Mean square energy of each gating block.
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    /// Measure a track of interleaved samples in the range `[-1.0, 1.0]`.
+    pub fn add_track(
+        &mut self,
+        samples: impl Iterator<Item = f32>,
+        channels: u16,
+        sample_rate: u32,
+    ) {
+        let channels = usize::from(channels.max(1));
+        let step_len = (sample_rate as usize / 10).max(1);
+        let mut filters = vec![k_weighting(sample_rate); channels];
+        let mut sum_sq = 0.0;
+        let mut frames_in_step = 0;
+        // Blocks don't span tracks
+        let first_step = self.steps.len();
+
+        for (i, sample) in samples.enumerate() {
+            self.peak = self.peak.max(sample.abs());
+            let channel = i % channels;
+            let [pre_filter, high_pass] = &mut filters[channel];
+            let weighted = high_pass.process(pre_filter.process(f64::from(sample)));
+            sum_sq += weighted * weighted;
+
+            if channel == channels - 1 {
+                frames_in_step += 1;
+                if frames_in_step == step_len {
+                    #[allow(clippy::cast_precision_loss)]
+                    self.steps.push(sum_sq / step_len as f64);
+                    sum_sq = 0.0;
+                    frames_in_step = 0;
+                    if self.steps.len() - first_step >= STEPS_PER_BLOCK {
+                        let block = &self.steps[self.steps.len() - STEPS_PER_BLOCK..];
+                        #[allow(clippy::cast_precision_loss)]
+                        self.blocks
+                            .push(block.iter().sum::<f64>() / STEPS_PER_BLOCK as f64);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gated integrated loudness in LUFS, or `None` if everything measured was silent.
+    pub fn integrated_loudness(&self) -> Option<f64> {
+        let mean_loudness = |blocks: &[f64]| {
+            #[allow(clippy::cast_precision_loss)]
+            let mean = blocks.iter().sum::<f64>() / blocks.len() as f64;
+            (!blocks.is_empty()).then(|| energy_to_loudness(mean))
+        };
+
+        let above_absolute = self
+            .blocks
+            .iter()
+            .copied()
+            .filter(|z| energy_to_loudness(*z) > ABSOLUTE_GATE)
+            .collect::<Vec<_>>();
+        let relative_gate = mean_loudness(&above_absolute)? + RELATIVE_GATE;
+        let above_relative = above_absolute
+            .into_iter()
+            .filter(|z| energy_to_loudness(*z) > relative_gate)
+            .collect::<Vec<_>>();
+        mean_loudness(&above_relative)
+    }
+
+    pub fn replay_gain(&self) -> Option<ReplayGain> {
+        #[allow(clippy::cast_possible_truncation)]
+        self.integrated_loudness().map(|loudness| ReplayGain {
+            gain: (REFERENCE_LOUDNESS - loudness) as f32,
+            peak: self.peak,
+        })
+    }
+}
+
+/// Write `replaygain_album_gain` and `replaygain_album_peak` to an MP3 (ID3 `TXXX` frames) or
+/// FLAC (Vorbis comments) file.
+pub fn write_album_gain_tags(path: &Path, album_gain: ReplayGain) -> Result<()> {
+    let gain = format!("{:.2} dB", album_gain.gain);
+    let peak = format!("{:.6}", album_gain.peak);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("mp3") => {
+            let mut tag = id3::no_tag_ok(id3::Tag::read_from_path(path))?.unwrap_or_default();
+            tag.add_frame(ExtendedText {
+                description: String::from("REPLAYGAIN_ALBUM_GAIN"),
+                value: gain,
+            });
+            tag.add_frame(ExtendedText {
+                description: String::from("REPLAYGAIN_ALBUM_PEAK"),
+                value: peak,
+            });
+            tag.write_to_path(path, id3::Version::Id3v24)?;
+        }
+        Some("flac") => flac::write_tags(
+            path,
+            &[
+                (String::from("REPLAYGAIN_ALBUM_GAIN"), gain),
+                (String::from("REPLAYGAIN_ALBUM_PEAK"), peak),
+            ],
+        )?,
+        _ => return Err(eyre!("Can't write ReplayGain tags to {}", path.display())),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, amplitude: f64, sample_rate: u32, secs: u32) -> impl Iterator<Item = f32> {
+        #[allow(clippy::cast_possible_truncation)]
+        (0..sample_rate * secs).map(move |i| {
+            (amplitude * (2.0 * PI * freq * f64::from(i) / f64::from(sample_rate)).sin()) as f32
+        })
+    }
+
+    #[test]
+    fn test_loudness_of_full_scale_sine() {
+        // BS.1770 calibration: a 0 dBFS 997 Hz sine in one channel reads -3.01 LUFS
+        let mut meter = LoudnessMeter::default();
+        meter.add_track(sine(997.0, 1.0, 48_000, 5), 1, 48_000);
+        let loudness = meter.integrated_loudness().unwrap();
+        assert!((loudness + 3.01).abs() < 0.05, "got {loudness}");
+    }
+
+    #[test]
+    fn test_album_loudness_spans_tracks() {
+        let mut quiet = LoudnessMeter::default();
+        quiet.add_track(sine(997.0, 0.1, 44_100, 5), 1, 44_100);
+        let mut album = LoudnessMeter::default();
+        album.add_track(sine(997.0, 0.1, 44_100, 5), 1, 44_100);
+        album.add_track(sine(997.0, 1.0, 44_100, 5), 1, 44_100);
+        let quiet_gain = quiet.replay_gain().unwrap();
+        let album_gain = album.replay_gain().unwrap();
+        // The loud track pulls the album gain down and sets the peak
+        assert!(album_gain.gain < quiet_gain.gain);
+        assert!((album_gain.peak - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_silence_has_no_loudness() {
+        let mut meter = LoudnessMeter::default();
+        meter.add_track(std::iter::repeat_n(0.0, 48_000), 2, 48_000);
+        assert!(meter.integrated_loudness().is_none());
+        assert!(meter.replay_gain().is_none());
+    }
+}
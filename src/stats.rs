@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use eyre::Result;
+
+use crate::config;
+
+#[derive(Debug, Clone, Default)]
+pub struct PlayStats {
+    pub play_count: u32,
+    /// Times the song was skipped early on, a sign it's put up with rather than enjoyed.
+    pub skip_count: u32,
+    pub last_played: Option<SystemTime>,
+    /// When the song was first played. Set once and never changed.
+    pub first_played: Option<SystemTime>,
+}
+
+impl PlayStats {
+    /// Share of the song's plays and skips that were skips, nudged towards zero for songs that
+    /// have hardly been played.
+    pub fn skip_ratio(&self) -> f32 {
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = self.skip_count as f32 / (self.play_count + self.skip_count + 1) as f32;
+        ratio
+    }
+}
+
+/// Marks the line holding the total listening time rather than a song's stats.
+const LISTENING_TIME_KEY: &str = "#listening_time";
+
+/// Per-file play statistics, persisted as tab-separated lines of
+/// `path, play_count, last_played, skip_count, first_played`. Times are in seconds since the
+/// Unix epoch, or empty. Columns are missing from files written before they were tracked, and a
+/// missing `first_played` falls back to `last_played`.
+///
+/// A `#listening_time` line holds the total time spent listening across every session, in
+/// seconds.
+#[derive(Default)]
+pub struct PlayStatsStore {
+    stats: HashMap<PathBuf, PlayStats>,
+    listening_time: Duration,
+    store_path: Option<PathBuf>,
+}
+
+impl PlayStatsStore {
+    /// Load the store from the data directory. A missing file gives an empty store.
+    pub fn load() -> Result<Self> {
+        let Some(store_path) = config::data_dir().map(|d| d.join("playstats.tsv")) else {
+            return Ok(Self::default());
+        };
+        let (stats, listening_time) = match fs::read_to_string(&store_path) {
+            Ok(contents) => Self::parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (HashMap::new(), Duration::ZERO),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            stats,
+            listening_time,
+            store_path: Some(store_path),
+        })
+    }
+
+    fn parse(contents: &str) -> (HashMap<PathBuf, PlayStats>, Duration) {
+        let mut stats = HashMap::new();
+        let mut listening_time = Duration::ZERO;
+        for line in contents.lines() {
+            if let Some(secs) = line
+                .strip_prefix(LISTENING_TIME_KEY)
+                .and_then(|rest| rest.strip_prefix('\t'))
+            {
+                listening_time = Duration::from_secs(secs.parse().unwrap_or(0));
+            } else if let Some((path, song_stats)) = Self::parse_line(line) {
+                stats.insert(path, song_stats);
+            }
+        }
+        (stats, listening_time)
+    }
+
+    fn parse_line(line: &str) -> Option<(PathBuf, PlayStats)> {
+        let mut fields = line.split('\t');
+        let path = PathBuf::from(fields.next()?);
+        let play_count = fields.next()?.parse().ok()?;
+        let parse_time = |field: Option<&str>| {
+            field
+                .and_then(|secs| secs.parse().ok())
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        let last_played = parse_time(fields.next());
+        let skip_count = fields.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        let first_played = match fields.next() {
+            Some(field) => parse_time(Some(field)),
+            None => last_played,
+        };
+        Some((
+            path,
+            PlayStats {
+                play_count,
+                skip_count,
+                last_played,
+                first_played,
+            },
+        ))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let Some(store_path) = &self.store_path else {
+            return Ok(());
+        };
+        if let Some(parent) = store_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(store_path, self.contents()?)?;
+        Ok(())
+    }
+
+    /// The store in the format [`Self::load`] reads.
+    fn contents(&self) -> Result<String> {
+        let mut contents = String::new();
+        writeln!(
+            contents,
+            "{LISTENING_TIME_KEY}\t{}",
+            self.listening_time.as_secs()
+        )?;
+        let format_time = |time: Option<SystemTime>| {
+            time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(String::new(), |d| d.as_secs().to_string())
+        };
+        for (path, stats) in &self.stats {
+            writeln!(
+                contents,
+                "{}\t{}\t{}\t{}\t{}",
+                path.display(),
+                stats.play_count,
+                format_time(stats.last_played),
+                stats.skip_count,
+                format_time(stats.first_played)
+            )?;
+        }
+        Ok(contents)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&PlayStats> {
+        self.stats.get(path)
+    }
+
+    pub fn play_count(&self, path: &Path) -> u32 {
+        self.get(path).map_or(0, |s| s.play_count)
+    }
+
+    pub fn record_play(&mut self, path: &Path) {
+        let stats = self.stats.entry(path.to_path_buf()).or_default();
+        let now = SystemTime::now();
+        stats.play_count += 1;
+        stats.last_played = Some(now);
+        stats.first_played.get_or_insert(now);
+    }
+
+    /// Total time spent listening, across every session.
+    pub fn listening_time(&self) -> Duration {
+        self.listening_time
+    }
+
+    pub fn add_listening_time(&mut self, time: Duration) {
+        self.listening_time += time;
+    }
+
+    pub fn record_skip(&mut self, path: &Path) {
+        self.stats.entry(path.to_path_buf()).or_default().skip_count += 1;
+    }
+
+    /// Keep the stats of the song at `from` under `to`, after its file was moved.
+    pub fn move_song(&mut self, from: &Path, to: &Path) {
+        if let Some(stats) = self.stats.remove(from) {
+            self.stats.insert(to.to_path_buf(), stats);
+        }
+    }
+
+    /// The [skip ratio](PlayStats::skip_ratio) of the song at `path`, or 0 if it has no stats.
+    pub fn skip_ratio(&self, path: &Path) -> f32 {
+        self.get(path).map_or(0.0, PlayStats::skip_ratio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let (path, stats) = PlayStatsStore::parse_line("/a.mp3\t3\t60\t2\t30").unwrap();
+        assert_eq!(path, Path::new("/a.mp3"));
+        assert_eq!(stats.play_count, 3);
+        assert_eq!(stats.last_played, Some(UNIX_EPOCH + Duration::from_mins(1)));
+        assert_eq!(stats.skip_count, 2);
+        assert_eq!(
+            stats.first_played,
+            Some(UNIX_EPOCH + Duration::from_secs(30))
+        );
+
+        // Written before first plays were recorded
+        let (_, stats) = PlayStatsStore::parse_line("/a.mp3\t3\t60\t2").unwrap();
+        assert_eq!(stats.first_played, stats.last_played);
+
+        // Written before skips were counted
+        let (_, stats) = PlayStatsStore::parse_line("/a.mp3\t3\t").unwrap();
+        assert_eq!(stats.last_played, None);
+        assert_eq!(stats.skip_count, 0);
+        assert!(PlayStatsStore::parse_line("/a.mp3").is_none());
+    }
+
+    #[test]
+    fn test_listening_time_is_saved() {
+        let mut store = PlayStatsStore::default();
+        store.record_play(Path::new("/a.mp3"));
+        store.add_listening_time(Duration::from_secs(90));
+        store.add_listening_time(Duration::from_millis(600));
+        let (stats, listening_time) = PlayStatsStore::parse(&store.contents().unwrap());
+        assert_eq!(listening_time, Duration::from_secs(90));
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[Path::new("/a.mp3")].play_count, 1);
+
+        // Written before listening time was tracked
+        let (_, listening_time) = PlayStatsStore::parse("/a.mp3\t3\t60");
+        assert_eq!(listening_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn test_first_played_is_kept() {
+        let mut store = PlayStatsStore::default();
+        let path = Path::new("/a.mp3");
+        store.record_play(path);
+        let first = store.get(path).unwrap().first_played;
+        store.record_play(path);
+        let stats = store.get(path).unwrap();
+        assert_eq!(stats.first_played, first);
+        assert!(stats.last_played >= first);
+    }
+
+    #[test]
+    fn test_move_song() {
+        let mut store = PlayStatsStore::default();
+        store.record_play(Path::new("/a.mp3"));
+        store.move_song(Path::new("/a.mp3"), Path::new("/b.mp3"));
+        assert!(store.get(Path::new("/a.mp3")).is_none());
+        assert_eq!(store.play_count(Path::new("/b.mp3")), 1);
+    }
+
+    #[test]
+    fn test_skip_ratio() {
+        let mut store = PlayStatsStore::default();
+        let path = Path::new("/a.mp3");
+        assert!(store.skip_ratio(path).abs() < f32::EPSILON);
+        store.record_play(path);
+        store.record_skip(path);
+        store.record_skip(path);
+        assert!((store.skip_ratio(path) - 0.5).abs() < f32::EPSILON);
+    }
+}
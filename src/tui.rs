@@ -1,7 +1,14 @@
 use std::io::{stdout, Stdout};
 
 use crossterm::{
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    event::{
+        DisableFocusChange, EnableFocusChange, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    terminal::{
+        disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen, SetTitle,
+    },
     ExecutableCommand,
 };
 use eyre::Result;
@@ -9,163 +16,561 @@ use ratatui::{
     backend::CrosstermBackend,
     layout::{Alignment, Constraint, Layout, Rect},
     style::{Color, Modifier, Style, Stylize},
-    text::Line,
+    text::{Line, Span},
     widgets::{
         block::{Position, Title},
-        Block, Borders, Gauge, Paragraph, Row, Table, TableState,
+        Block, Borders, Cell, Clear, Gauge, List, ListState, Paragraph, Row, Table, TableState,
     },
     Frame, Terminal,
 };
 
-use crate::app::{AppUiMode, PlaybackMode, PlayerApp};
+use crate::app::{
+    format_long_duration, AppUiMode, AudioManagerTrait, FocusedPanel, PlayerApp, SongInfo,
+    BITRATE_BUCKETS,
+};
+use crate::config::Column;
+use crate::cover_art::CoverArtPlaceholder;
+
+/// Shown in place of the play icon while a song is loading, one character per frame.
+const BUFFERING_SPINNER: [&str; 6] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴"];
 
 pub struct Tui {
     terminal: Terminal<CrosstermBackend<Stdout>>,
     ui_state: UiState,
+    /// What the window title was last set to, if it has been.
+    window_title: Option<String>,
+    restore_title: bool,
+    /// Whether the terminal was asked to report keys it can't otherwise tell apart.
+    keyboard_enhanced: bool,
 }
 
 impl Tui {
     pub fn new() -> Result<Self> {
+        // Lets keys like `Ctrl + Enter` through, and tells held keys apart from fresh presses, in
+        // terminals that support it. Elsewhere a held key arrives as repeated presses.
+        let keyboard_enhanced = supports_keyboard_enhancement().unwrap_or(false);
+        Self::set_panic_handler(keyboard_enhanced);
         stdout().execute(EnterAlternateScreen)?;
+        stdout().execute(EnableFocusChange)?;
+        if keyboard_enhanced {
+            stdout().execute(PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_EVENT_TYPES,
+            ))?;
+        }
         enable_raw_mode()?;
         let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
         terminal.clear()?;
         Ok(Self {
             terminal,
             ui_state: UiState::new(),
+            window_title: None,
+            restore_title: false,
+            keyboard_enhanced,
         })
     }
 
+    /// Put the terminal back to normal before a panic message is printed. Otherwise the message
+    /// would go to the alternate screen and be lost, and the shell would be left in raw mode.
+    fn set_panic_handler(keyboard_enhanced: bool) {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = disable_raw_mode();
+            if keyboard_enhanced {
+                let _ = stdout().execute(PopKeyboardEnhancementFlags);
+            }
+            let _ = stdout().execute(DisableFocusChange);
+            let _ = stdout().execute(LeaveAlternateScreen);
+            default_hook(info);
+        }));
+    }
+
     pub fn update(&mut self, app: &mut PlayerApp) -> Result<()> {
+        if let Some((width, height)) = app.take_resize() {
+            self.resize_handler(width, height)?;
+        }
+        self.update_window_title(app)?;
+
+        // The search prompt's layout has a shorter file list, which shifts the scroll position,
+        // so put it back afterwards
+        let searching = *app.ui_mode() == AppUiMode::SearchPopup;
+        if searching && !self.ui_state.was_searching {
+            self.ui_state.last_list_offset = Some(self.ui_state.table_state.offset());
+        } else if !searching && self.ui_state.was_searching {
+            if let Some(offset) = self.ui_state.last_list_offset.take() {
+                *self.ui_state.table_state.offset_mut() = offset;
+            }
+        }
+        self.ui_state.was_searching = searching;
+
         self.ui_state
             .table_state
             .select(Some(app.selected_file_ix()));
+        self.ui_state
+            .duplicates_table_state
+            .select(Some(app.selected_duplicate_ix()));
+        self.ui_state
+            .queue_list
+            .select(Some(app.selected_queue_ix()));
+        self.ui_state.frame_count = self.ui_state.frame_count.wrapping_add(1);
         self.terminal
             .draw(|f| Self::ui(f, app, &mut self.ui_state))?;
         Ok(())
     }
 
-    fn draw_ui_file_list_mode(frame: &mut Frame, app: &mut PlayerApp, ui_state: &mut UiState) {
-        let layout =
-            Layout::vertical([Constraint::Fill(8), Constraint::Min(3)]).split(frame.size());
+    /// Show the playing song in the window title, if it has changed since the title was last set.
+    fn update_window_title(&mut self, app: &PlayerApp) -> Result<()> {
+        let (set_title, restore_title) = app.window_title_settings();
+        self.restore_title = restore_title;
+        if !set_title {
+            return Ok(());
+        }
+        let title = app
+            .active_song()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        // Leave the title alone until something has played
+        let unchanged = self
+            .window_title
+            .as_ref()
+            .map_or(title.is_empty(), |t| *t == title);
+        if !unchanged {
+            // Written straight to the terminal, since it isn't part of any frame
+            stdout().execute(SetTitle(&title))?;
+            self.window_title = Some(title);
+        }
+        Ok(())
+    }
+
+    /// Fit the terminal's buffers to its new size straight away, so the frame drawn next
+    /// doesn't mix in what was on screen before.
+    fn resize_handler(&mut self, width: u16, height: u16) -> Result<()> {
+        self.terminal.resize(Rect::new(0, 0, width, height))?;
+        Ok(())
+    }
+
+    fn draw_ui_file_list_mode(
+        frame: &mut Frame,
+        app: &mut PlayerApp,
+        ui_state: &mut UiState,
+        area: Rect,
+    ) {
+        let layout = Layout::vertical([Constraint::Fill(8), Constraint::Min(3)]).split(area);
         let bottom_layout =
             Layout::horizontal([Constraint::Fill(4), Constraint::Min(1)]).split(layout[1]);
 
+        Self::draw_now_playing(frame, app, bottom_layout[1]);
+        Self::draw_library_panels(frame, app, ui_state, layout[0]);
+        Self::draw_playback_bar(frame, app, ui_state, bottom_layout[0]);
+    }
+
+    fn draw_now_playing(frame: &mut Frame, app: &mut PlayerApp, area: Rect) {
+        let block = Block::default().title("Now Playing").borders(Borders::ALL);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let mut tags_area = inner;
+        if let Some(art) = app.active_artwork().map(<[_]>::to_vec) {
+            #[allow(clippy::cast_possible_truncation)]
+            let size = CoverArtPlaceholder::SIZE as u16;
+            let [art_area, rest] =
+                *Layout::horizontal([Constraint::Length(size), Constraint::Fill(1)])
+                    .spacing(1)
+                    .split(inner)
+            else {
+                unreachable!()
+            };
+            // Keep the middle of the placeholder in view when the panel is too short for it
+            let scroll = size.saturating_sub(art_area.height) / 2;
+            frame.render_widget(Paragraph::new(art).scroll((scroll, 0)), art_area);
+            tags_area = rest;
+        }
+
         let tags = match app.active_song() {
             Some(t) => {
                 format!(
                     "{}\n{}\n{}",
-                    t.title().unwrap_or("Unknown Title"),
-                    t.album().unwrap_or("Unknown Album"),
-                    t.artist().unwrap_or("Unknown Artist")
+                    t.display_title(),
+                    t.display_album(),
+                    t.display_artist()
                 )
             }
             _ => String::from("Unknown Song"),
         };
-
-        let tag_info =
-            Paragraph::new(tags).block(Block::default().title("Now Playing").borders(Borders::ALL));
-        frame.render_widget(tag_info, bottom_layout[1]);
-
-        Self::draw_file_list(frame, app, ui_state, layout[0]);
-        Self::draw_playback_bar(frame, app, ui_state, bottom_layout[0]);
+        frame.render_widget(Paragraph::new(tags), tags_area);
     }
 
-    fn draw_ui_search_mode(frame: &mut Frame, app: &mut PlayerApp, ui_state: &mut UiState) {
+    fn draw_ui_prompt_mode(
+        frame: &mut Frame,
+        app: &mut PlayerApp,
+        ui_state: &mut UiState,
+        area: Rect,
+        prompt: &str,
+    ) {
         let layout =
             Layout::vertical([Constraint::Fill(1), Constraint::Fill(8), Constraint::Min(3)])
-                .split(frame.size());
+                .split(area);
         let bottom_layout =
             Layout::horizontal([Constraint::Fill(4), Constraint::Min(1)]).split(layout[2]);
 
-        let tags = match app.active_song() {
-            Some(t) => {
-                format!(
-                    "{}\n{}\n{}",
-                    t.title().unwrap_or("Unknown Title"),
-                    t.album().unwrap_or("Unknown Album"),
-                    t.artist().unwrap_or("Unknown Artist")
-                )
-            }
-            _ => String::from("Unknown Song"),
-        };
-
-        let search_text = app.search_query().unwrap_or("Search...");
-        frame.render_widget(Line::from(search_text), layout[0]);
-
-        let tag_info =
-            Paragraph::new(tags).block(Block::default().title("Now Playing").borders(Borders::ALL));
-        frame.render_widget(tag_info, bottom_layout[1]);
+        frame.render_widget(Line::from(prompt), layout[0]);
 
-        Self::draw_file_list(frame, app, ui_state, layout[1]);
+        Self::draw_now_playing(frame, app, bottom_layout[1]);
+        Self::draw_library_panels(frame, app, ui_state, layout[1]);
         Self::draw_playback_bar(frame, app, ui_state, bottom_layout[0]);
     }
 
     fn ui(frame: &mut Frame, app: &mut PlayerApp, ui_state: &mut UiState) {
+        // The status bar stays on the bottom row whatever else is shown
+        let [area, status_area] =
+            *Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).split(frame.size())
+        else {
+            unreachable!()
+        };
+        Self::draw_status_bar(frame, app, status_area);
+
         match app.ui_mode() {
-            AppUiMode::FileList => Self::draw_ui_file_list_mode(frame, app, ui_state),
-            AppUiMode::SearchPopup => Self::draw_ui_search_mode(frame, app, ui_state),
-            AppUiMode::InfoPopup => todo!(),
+            AppUiMode::FileList => Self::draw_ui_file_list_mode(frame, app, ui_state, area),
+            AppUiMode::SearchPopup => {
+                let case = if app.search_case_sensitive() {
+                    "[Aa]"
+                } else {
+                    "[aa]"
+                };
+                let prompt = format!("{case} {}", app.search_query().unwrap_or("Search..."));
+                Self::draw_ui_prompt_mode(frame, app, ui_state, area, &prompt);
+            }
+            AppUiMode::CommandPrompt => {
+                let prompt = format!(":{}", app.command_input());
+                Self::draw_ui_prompt_mode(frame, app, ui_state, area, &prompt);
+            }
+            AppUiMode::CoverPathPrompt => {
+                let prompt = format!("Cover image: {}", app.cover_path_input());
+                Self::draw_ui_prompt_mode(frame, app, ui_state, area, &prompt);
+            }
+            AppUiMode::Duplicates => Self::draw_duplicates(frame, app, ui_state, area),
+            AppUiMode::ColumnConfig => {
+                Self::draw_ui_file_list_mode(frame, app, ui_state, area);
+                Self::draw_column_config(frame, app);
+            }
+            AppUiMode::InfoPopup => {
+                Self::draw_ui_file_list_mode(frame, app, ui_state, area);
+                Self::draw_info_popup(frame, app);
+            }
+            AppUiMode::QualityReport => Self::draw_quality_report(frame, app, area),
+            AppUiMode::ConfirmRescan => {
+                let prompt = "Rescan library? This may take a while. [y/N]";
+                Self::draw_ui_prompt_mode(frame, app, ui_state, area, prompt);
+            }
+            AppUiMode::ConfirmDelete => {
+                let [prompt_area, list_area] =
+                    *Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).split(area)
+                else {
+                    unreachable!()
+                };
+                let path = app
+                    .pending_delete()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default();
+                let prompt = format!("Delete {path}? This can't be undone. [y/N]");
+                frame.render_widget(Line::from(prompt), prompt_area);
+                Self::draw_duplicates(frame, app, ui_state, list_area);
+            }
         }
     }
 
-    fn draw_file_list(frame: &mut Frame, app: &mut PlayerApp, ui_state: &mut UiState, rect: Rect) {
-        let table_rows = app
-            .library()
-            .files()
+    fn draw_quality_report(frame: &mut Frame, app: &PlayerApp, area: Rect) {
+        const BAR_WIDTH: usize = 30;
+        let Some(report) = app.quality_report() else {
+            return;
+        };
+        let total = report.total_tracks;
+        let heading = |text: &str| Line::from(text.to_string().bold());
+        let bar_line = |label: &str, count: usize, max: usize, detail: String| {
+            Line::from(vec![
+                Span::raw(format!("  {label:<12}")),
+                Span::styled(
+                    format!("{:<BAR_WIDTH$}", bar(count, max, BAR_WIDTH)),
+                    Style::new().fg(Color::Cyan),
+                ),
+                Span::raw(format!(" {detail}")),
+            ])
+        };
+
+        let mut lines = vec![heading("Tagged"), Line::default()];
+        for (label, missing) in [
+            ("Title", report.tracks_missing_title),
+            ("Artist", report.tracks_missing_artist),
+            ("Album", report.tracks_missing_album),
+            ("Year", report.tracks_missing_year),
+            ("Genre", report.tracks_missing_genre),
+            ("Cover", report.tracks_missing_cover),
+        ] {
+            let tagged = total - missing;
+            let detail = format!("{tagged} / {total} ({missing} missing)");
+            lines.push(bar_line(label, tagged, total, detail));
+        }
+
+        lines.extend([Line::default(), heading("Formats"), Line::default()]);
+        let mut formats = report.format_distribution.iter().collect::<Vec<_>>();
+        formats.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (format, count) in formats {
+            lines.push(bar_line(format, *count, total, count.to_string()));
+        }
+
+        lines.extend([Line::default(), heading("Bitrates"), Line::default()]);
+        for bucket in BITRATE_BUCKETS {
+            let count = report
+                .bitrate_distribution
+                .get(&bucket)
+                .copied()
+                .unwrap_or(0);
+            let label = format!("~{bucket} kbps");
+            lines.push(bar_line(&label, count, total, count.to_string()));
+        }
+
+        let title = format!(
+            "Library quality: {total} tracks, {} ('Esc' closes)",
+            format_long_duration(app.library().total_duration())
+        );
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().title(title.bold())),
+            area,
+        );
+    }
+
+    fn draw_duplicates(frame: &mut Frame, app: &PlayerApp, ui_state: &mut UiState, area: Rect) {
+        #[allow(clippy::cast_precision_loss)]
+        let megabytes = |bytes: u64| format!("{:.1} MB", bytes as f64 / 1_000_000.0);
+        let rows = app
+            .duplicates()
             .iter()
-            .filter(|s| {
-                // TODO: If the current selected row ix is > the length of the filtered search
-                // results, the selection disappears. It doesn't crash but is annoying.
-                if let Some(q) = app.search_query() {
-                    let query = q.to_lowercase();
-                    let title = s.title().unwrap_or("").to_lowercase();
-                    let artist = s.artist().unwrap_or("").to_lowercase();
-                    let album = s.album().unwrap_or("").to_lowercase();
-                    title.contains(&query) || artist.contains(&query) || album.contains(&query)
-                } else {
-                    true
-                }
-            })
-            .map(|s| {
+            .map(|((first, first_size), (second, second_size))| {
                 Row::new(vec![
-                    format!("{:02}", s.track().0.unwrap_or(0)),     // Track ID
-                    format!("{}", s.title().unwrap_or("Unknown")),  // Song title
-                    format!("{}", s.artist().unwrap_or("Unknown")), // Artist name
-                    format!("{}", s.album().unwrap_or("Unknown")),  // Album name
-                    format!(
-                        "{}",
-                        format!(
-                            "{:02}:{:02}",
-                            s.duration().as_secs() / 60,
-                            s.duration().as_secs() % 60
-                        )
-                    ), // Duration
+                    first.display().to_string(),
+                    megabytes(*first_size),
+                    second.display().to_string(),
+                    megabytes(*second_size),
                 ])
             })
             .collect::<Vec<_>>();
         let widths = [
-            Constraint::Fill(1), // Track ID
-            Constraint::Fill(5), // Song title
-            Constraint::Fill(5), // Artist name
-            Constraint::Fill(5), // Album name
-            Constraint::Fill(2), // Duration
+            Constraint::Fill(5), // First copy
+            Constraint::Fill(1), // First copy size
+            Constraint::Fill(5), // Second copy
+            Constraint::Fill(1), // Second copy size
         ];
         let header =
-            Row::new(["#", "Title", "Artist", "Album", "Length"]).style(Style::new().bold());
-        let table = Table::new(table_rows, widths)
+            Row::new(["First copy", "Size", "Second copy", "Size"]).style(Style::new().bold());
+        let title = format!(
+            "Duplicates: {} ('1'/'2' deletes the first/second copy, 'Esc' closes)",
+            rows.len()
+        );
+        let table = Table::new(rows, widths)
+            .column_spacing(1)
+            .style(Style::new().bg(Color::Black).fg(Color::White))
+            .header(header)
+            .highlight_style(Style::new().reversed())
+            .block(Block::default().title(title.bold()));
+        frame.render_stateful_widget(table, area, &mut ui_state.duplicates_table_state);
+    }
+
+    /// The file list, with the queue beside it while there's anything queued or it has focus.
+    fn draw_library_panels(
+        frame: &mut Frame,
+        app: &mut PlayerApp,
+        ui_state: &mut UiState,
+        rect: Rect,
+    ) {
+        let queue_focused = app.focused_panel() == FocusedPanel::QueuePanel;
+        if app.queue().is_empty() && !queue_focused {
+            Self::draw_file_list(frame, app, ui_state, rect);
+            return;
+        }
+        let layout = Layout::horizontal([Constraint::Fill(3), Constraint::Fill(1)]).split(rect);
+        let border_style = |focused: bool| {
+            if focused {
+                Style::new().fg(Color::Cyan)
+            } else {
+                Style::new()
+            }
+        };
+
+        let list_block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(border_style(!queue_focused));
+        let list_area = list_block.inner(layout[0]);
+        frame.render_widget(list_block, layout[0]);
+        Self::draw_file_list(frame, app, ui_state, list_area);
+
+        let files = app.library().files();
+        let items = app
+            .queue()
+            .iter()
+            .map(|ix| files[ix].to_string())
+            .collect::<Vec<_>>();
+        let mut queue = List::new(items)
+            .block(
+                Block::default()
+                    .title(format!("Queue ({})", app.queue().len()))
+                    .title(
+                        Title::from(
+                            app.next_song_info()
+                                .map(|s| format!("Up next: {s}"))
+                                .unwrap_or_default(),
+                        )
+                        .position(Position::Bottom),
+                    )
+                    .borders(Borders::ALL)
+                    .border_style(border_style(queue_focused)),
+            )
+            .style(Style::new().bg(Color::Black).fg(Color::White));
+        if queue_focused {
+            queue = queue.highlight_style(Style::new().reversed());
+        }
+        frame.render_stateful_widget(queue, layout[1], &mut ui_state.queue_list);
+    }
+
+    fn draw_info_popup(frame: &mut Frame, app: &PlayerApp) {
+        let area = frame.size();
+        let popup = Rect {
+            x: area.width / 10,
+            y: area.height / 10,
+            width: area.width * 8 / 10,
+            height: area.height * 8 / 10,
+        };
+        let (title, lines) = app.info();
+        let text = lines
+            .iter()
+            .map(|l| Line::from(l.as_str()))
+            .collect::<Vec<_>>();
+        frame.render_widget(Clear, popup);
+        frame.render_widget(
+            Paragraph::new(text).block(Block::default().title(title).borders(Borders::ALL)),
+            popup,
+        );
+    }
+
+    fn draw_file_list(frame: &mut Frame, app: &mut PlayerApp, ui_state: &mut UiState, rect: Rect) {
+        let files = app.library().files();
+        let columns = app.columns().visible();
+        let query = app.search_query().unwrap_or("");
+        let case_sensitive = app.search_case_sensitive();
+        let playing_ix = app.active_song_index();
+        let table_rows = app
+            .visible_indices()
+            .into_iter()
+            .map(|ix| {
+                let s = &files[ix];
+                // Flag poorly tagged songs so they stand out from the "Unknown" placeholders
+                let style = if playing_ix == Some(ix) {
+                    Style::new().bold().fg(Color::Green)
+                } else if !s.missing_tags().is_empty() || s.has_tag_warnings() {
+                    Style::new().fg(Color::Yellow)
+                } else if let Some(color) = app.genre_color(s) {
+                    Style::new().fg(color)
+                } else {
+                    Style::new()
+                };
+                let favourite = if app.is_favourite(s) { "♥" } else { "" };
+                Row::new(
+                    std::iter::once(Cell::from(favourite).style(Style::new().fg(Color::Red)))
+                        .chain(columns.iter().map(|c| {
+                            let text = Self::column_cell(s, *c);
+                            // Only the columns searched through can contain the match
+                            if matches!(c, Column::Title | Column::Artist | Column::Album) {
+                                Cell::from(highlight_match(&text, query, case_sensitive))
+                            } else {
+                                Cell::from(text)
+                            }
+                        }))
+                        .collect::<Vec<_>>(),
+                )
+                .style(style)
+            })
+            .collect::<Vec<_>>();
+        let column_widths = app.column_widths();
+        // The first column is just wide enough for the favourite heart
+        let widths = std::iter::once(Constraint::Length(1))
+            .chain(
+                columns
+                    .iter()
+                    .map(|c| Constraint::Fill(column_widths.weight(*c))),
+            )
+            .collect::<Vec<_>>();
+        let header = Row::new(std::iter::once("").chain(columns.iter().map(|c| c.header())))
+            .style(Style::new().bold());
+        let mut table = Table::new(table_rows, widths)
             .column_spacing(1)
             .style(Style::new().bg(Color::Black).fg(Color::White))
             .header(header)
             .highlight_style(Style::new().reversed());
+        let total_duration = format_long_duration(app.library().total_duration());
+        let summary = match app.profile() {
+            Some(profile) => format!("{profile} · {total_duration}"),
+            None => total_duration,
+        };
+        let block = Block::default()
+            .title(app.view_title().unwrap_or_default().bold())
+            .title(Title::from(summary).alignment(Alignment::Right));
+        table = table.block(block);
 
         frame.render_stateful_widget(table, rect, ui_state.table_state());
     }
 
+    fn column_cell(song: &SongInfo, column: Column) -> String {
+        match column {
+            Column::Track => format!("{:02}", song.track().0.unwrap_or(0)),
+            Column::Title => song.display_title().to_string(),
+            Column::Artist if song.artists().is_empty() => song.display_artist().to_string(),
+            Column::Artist => song.artists().join(" / "),
+            Column::Album => song.display_album().to_string(),
+            Column::Year => song.year().map_or(String::new(), |y| y.to_string()),
+            Column::Genre => song.genre().unwrap_or("").to_string(),
+            Column::Duration => format!(
+                "{:02}:{:02}",
+                song.duration().as_secs() / 60,
+                song.duration().as_secs() % 60
+            ),
+        }
+    }
+
+    fn draw_column_config(frame: &mut Frame, app: &PlayerApp) {
+        let area = frame.size();
+        #[allow(clippy::cast_possible_truncation)]
+        let height = (Column::ALL.len() as u16 + 2).min(area.height);
+        let width = 46.min(area.width);
+        let popup = Rect {
+            x: area.width.saturating_sub(width) / 2,
+            y: area.height.saturating_sub(height) / 2,
+            width,
+            height,
+        };
+        let columns = app.columns();
+        let widths = app.column_widths();
+        let items = Column::ALL
+            .iter()
+            .map(|c| {
+                let check = if columns.is_visible(*c) { 'x' } else { ' ' };
+                format!("[{check}] {:<14} width {}", c.name(), widths.weight(*c))
+            })
+            .collect::<Vec<_>>();
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .title("Columns ('Space' toggles, '←'/'→' resizes)")
+                    .borders(Borders::ALL),
+            )
+            .highlight_style(Style::new().reversed());
+        let mut state = ListState::default().with_selected(Some(app.selected_column_ix()));
+        frame.render_widget(Clear, popup);
+        frame.render_stateful_widget(list, popup, &mut state);
+    }
+
     fn draw_playback_bar(
         frame: &mut Frame,
         app: &mut PlayerApp,
-        _ui_state: &mut UiState,
+        ui_state: &mut UiState,
         rect: Rect,
     ) {
         let total_duration = app
@@ -175,7 +580,7 @@ impl Tui {
         let elapsed_duration = match app.active_song() {
             Some(_) => app
                 .audio_manager()
-                .playback_progress()
+                .position()
                 .as_secs_f64()
                 .min(total_duration),
             None => 0.0,
@@ -202,79 +607,285 @@ impl Tui {
             None => String::from("--:--"),
         };
 
-        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
-        let display_volume = (100.0 * app.volume()) as u32;
-        let playback_divider = if app.is_playing() { "" } else { "" };
+        let playback_divider = if app.is_buffering() {
+            BUFFERING_SPINNER[ui_state.frame_count % BUFFERING_SPINNER.len()]
+        } else if app.is_playing() {
+            ""
+        } else {
+            ""
+        };
         let active_color = if app.is_playing() {
             Color::Green
         } else {
             Color::Yellow
         };
 
-        let tags = match app.active_song() {
-            Some(t) => {
-                format!(
-                    "{} - {}",
-                    t.title().unwrap_or("Unknown Title"),
-                    t.artist().unwrap_or("Unknown Artist"),
-                )
-            }
-            _ => String::new(),
-        };
+        let block = Self::playback_bar_block(app);
+        let label = format!("{playback_fmt} {playback_divider} {total_fmt}");
 
-        let shuffle_icon = match app.playback_mode() {
-            PlaybackMode::Normal => "",
-            PlaybackMode::Shuffle => "",
+        if let Some(waveform) = app.waveform() {
+            let inner = block.inner(rect);
+            frame.render_widget(block, rect);
+            Self::draw_waveform(
+                frame,
+                inner,
+                waveform,
+                playback_progress,
+                active_color,
+                label,
+            );
+            return;
         }
-        .to_string();
 
         let playback_bar = Gauge::default()
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title(tags)
-                    .title(
-                        Title::from(format!("Volume: {display_volume}%"))
-                            .position(Position::Bottom),
-                    )
-                    .title(
-                        Title::from(shuffle_icon)
-                            .position(Position::Top)
-                            .alignment(Alignment::Right),
-                    ),
-            )
+            .block(block)
             .gauge_style(
                 Style::default()
                     .fg(active_color)
                     .bg(Color::Black)
                     .add_modifier(Modifier::BOLD),
             )
-            .label(format!("{playback_fmt} {playback_divider} {total_fmt}",))
+            .label(label)
             .use_unicode(true)
             .ratio(playback_progress);
         frame.render_widget(playback_bar, rect);
     }
+
+    /// One row summing up the player: whether it's playing, the volume, the playback mode, the
+    /// library's size, and how far into the song it is.
+    fn draw_status_bar(frame: &mut Frame, app: &PlayerApp, rect: Rect) {
+        let state = if app.is_playing() { "●" } else { "⏸" };
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        let volume = (100.0 * f32::from(app.volume())) as u32;
+        let elapsed = match app.active_song() {
+            Some(_) => app.audio_manager().position().as_secs(),
+            None => 0,
+        };
+        let status = format!(
+            "{state} Vol:{volume}% | {} | Lib: {} tracks | {:02}:{:02}:{:02} elapsed",
+            app.playback_mode().name(),
+            format_count(app.library().files().len()),
+            elapsed / 3600,
+            elapsed / 60 % 60,
+            elapsed % 60
+        );
+        frame.render_widget(
+            Paragraph::new(status).style(Style::new().bg(Color::DarkGray).fg(Color::White)),
+            rect,
+        );
+    }
+
+    /// Border and titles around the playback bar: what's playing, volume, playback mode, status
+    /// messages, and the alarm.
+    fn playback_bar_block(app: &PlayerApp) -> Block<'static> {
+        #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
+        let display_volume = (100.0 * f32::from(app.volume())) as u32;
+        let tags = app
+            .active_song()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+
+        let mode_label = app.playback_mode().label();
+
+        let alarm_fmt = app
+            .alarm_time()
+            .map_or(String::new(), |t| format!("⏰ {t}"));
+        let status_fmt = app.status_message().unwrap_or("").to_string();
+
+        Block::default()
+            .borders(Borders::ALL)
+            .title(tags)
+            .title(Title::from(format!("Volume: {display_volume}%")).position(Position::Bottom))
+            .title(
+                Title::from(mode_label)
+                    .position(Position::Top)
+                    .alignment(Alignment::Right),
+            )
+            .title(
+                Title::from(status_fmt)
+                    .position(Position::Bottom)
+                    .alignment(Alignment::Center),
+            )
+            .title(
+                Title::from(alarm_fmt)
+                    .position(Position::Bottom)
+                    .alignment(Alignment::Right),
+            )
+    }
+
+    fn draw_waveform(
+        frame: &mut Frame,
+        rect: Rect,
+        waveform: &[f32],
+        progress: f64,
+        played_color: Color,
+        label: String,
+    ) {
+        let [wave_area, label_area] =
+            *Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).split(rect)
+        else {
+            unreachable!()
+        };
+        let line = Self::waveform_line(
+            waveform,
+            usize::from(wave_area.width),
+            progress,
+            played_color,
+        );
+        let rows = vec![line; usize::from(wave_area.height)];
+        frame.render_widget(Paragraph::new(rows), wave_area);
+        frame.render_widget(
+            Paragraph::new(label).alignment(Alignment::Center),
+            label_area,
+        );
+    }
+
+    /// One row of block characters showing `waveform` squeezed into `width` columns, with the
+    /// first `progress` of it in `played_color`.
+    fn waveform_line(
+        waveform: &[f32],
+        width: usize,
+        progress: f64,
+        played_color: Color,
+    ) -> Line<'static> {
+        const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let played_cols = (progress * width as f64).round() as usize;
+        let (mut played, mut unplayed) = (String::new(), String::new());
+        for col in 0..width {
+            let start = col * waveform.len() / width;
+            let end = ((col + 1) * waveform.len() / width).max(start + 1);
+            let amplitude = waveform
+                .get(start..end.min(waveform.len()))
+                .map_or(0.0, |w| w.iter().copied().fold(0.0, f32::max));
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let level = LEVELS[((amplitude * 7.0).round() as usize).min(7)];
+            if col < played_cols {
+                played.push(level);
+            } else {
+                unplayed.push(level);
+            }
+        }
+        Line::from(vec![
+            Span::styled(played, Style::default().fg(played_color)),
+            Span::styled(unplayed, Style::default().fg(Color::DarkGray)),
+        ])
+    }
 }
 
 impl Drop for Tui {
     fn drop(&mut self) {
+        if self.keyboard_enhanced {
+            if let Err(e) = stdout().execute(PopKeyboardEnhancementFlags) {
+                eprintln!("Error restoring keyboard reporting: {e}");
+            }
+        }
+        if let Err(e) = stdout().execute(DisableFocusChange) {
+            eprintln!("Error disabling focus events: {e}");
+        }
         if let Err(e) = stdout().execute(LeaveAlternateScreen) {
             eprintln!("Error executing LeaveAlternateScreen: {e}");
         }
         if let Err(e) = disable_raw_mode() {
             eprintln!("Error disabling raw mode: {e}");
         }
+        if self.restore_title && self.window_title.is_some() {
+            if let Err(e) = stdout().execute(SetTitle("")) {
+                eprintln!("Error clearing the window title: {e}");
+            }
+        }
+    }
+}
+
+/// `count` with commas between each group of three digits, e.g. `1,234`.
+fn format_count(count: usize) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+/// A horizontal bar of block characters `count / max` of `width` columns long, to the nearest
+/// eighth of a column.
+fn bar(count: usize, max: usize, width: usize) -> String {
+    const PARTIAL: [char; 7] = ['▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    if max == 0 {
+        return String::new();
+    }
+    let eighths = (count.min(max) * width * 8 + max / 2) / max;
+    let mut bar = "█".repeat(eighths / 8);
+    if let Some(partial) = (eighths % 8).checked_sub(1) {
+        bar.push(PARTIAL[partial]);
+    }
+    bar
+}
+
+/// Byte range of the first match of `query` in `text`.
+fn find_match(text: &str, query: &str, case_sensitive: bool) -> Option<(usize, usize)> {
+    if query.is_empty() {
+        return None;
+    }
+    if case_sensitive {
+        return text.find(query).map(|start| (start, start + query.len()));
+    }
+    let query = query.to_lowercase();
+    text.char_indices().find_map(|(start, _)| {
+        // Lowercasing can change lengths, so grow the candidate a character at a time
+        let mut lowered = String::new();
+        for (offset, c) in text[start..].char_indices() {
+            lowered.extend(c.to_lowercase());
+            if lowered == query {
+                return Some((start, start + offset + c.len_utf8()));
+            }
+            if !query.starts_with(&lowered) {
+                return None;
+            }
+        }
+        None
+    })
+}
+
+/// `text` with the part that matches the search `query` in bold.
+fn highlight_match(text: &str, query: &str, case_sensitive: bool) -> Line<'static> {
+    match find_match(text, query, case_sensitive) {
+        Some((start, end)) => Line::from(vec![
+            Span::raw(text[..start].to_string()),
+            Span::styled(text[start..end].to_string(), Modifier::BOLD),
+            Span::raw(text[end..].to_string()),
+        ]),
+        None => Line::raw(text.to_string()),
     }
 }
 
 struct UiState {
     table_state: TableState,
+    duplicates_table_state: TableState,
+    queue_list: ListState,
+    /// File list scroll offset from before the search prompt opened.
+    last_list_offset: Option<usize>,
+    was_searching: bool,
+    /// Frames drawn so far, for animating the buffering spinner.
+    frame_count: usize,
 }
 
 impl UiState {
     pub fn new() -> Self {
         Self {
             table_state: TableState::default(),
+            duplicates_table_state: TableState::default(),
+            queue_list: ListState::default(),
+            last_list_offset: None,
+            was_searching: false,
+            frame_count: 0,
         }
     }
 
@@ -282,3 +893,56 @@ impl UiState {
         &mut self.table_state
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_count() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1_234), "1,234");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+    }
+
+    #[test]
+    fn test_bar() {
+        assert_eq!(bar(10, 10, 4), "████");
+        assert_eq!(bar(5, 10, 4), "██");
+        assert_eq!(bar(1, 16, 2), "▏");
+        assert_eq!(bar(3, 16, 2), "▍");
+        assert_eq!(bar(0, 10, 4), "");
+        assert_eq!(bar(5, 0, 4), "");
+        // Counts over the maximum don't overflow the width
+        assert_eq!(bar(20, 10, 4), "████");
+    }
+
+    #[test]
+    fn test_highlight_match() {
+        let line = highlight_match("Back in Black", "black", false);
+        let spans = line
+            .spans
+            .iter()
+            .map(|s| s.content.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(spans, ["Back in ", "Black", ""]);
+        assert_eq!(line.spans[1].style, Style::from(Modifier::BOLD));
+
+        assert_eq!(
+            highlight_match("Beyoncé", "CÉ", false).spans[1].content,
+            "cé"
+        );
+        assert_eq!(highlight_match("Abba", "zz", false).spans.len(), 1);
+        assert_eq!(highlight_match("Abba", "", false).spans.len(), 1);
+        assert_eq!(
+            highlight_match("back in Black", "B", false).spans[0].content,
+            ""
+        );
+        // A case-sensitive search skips past the lowercase "b"
+        assert_eq!(
+            highlight_match("back in Black", "B", true).spans[0].content,
+            "back in "
+        );
+    }
+}